@@ -0,0 +1,101 @@
+// How long fitting takes and how many candidates it encodes on the way, for
+// a few kinds of input. Run with `cargo bench`, compare against a saved
+// baseline with `cargo bench -- --save-baseline before` and
+// `--baseline before`
+
+use std::{cell::Cell, path::PathBuf, time::Duration};
+
+use autosize::{
+    fit,
+    image::{self, DynamicImage},
+    FitOptions,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+fn open(path: &str) -> DynamicImage {
+    image::open(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+}
+
+// (name, image, output format, targets in bytes)
+fn cases() -> Vec<(&'static str, DynamicImage, &'static str, Vec<u64>)> {
+    vec![
+        (
+            "photo",
+            open("input/kodim23.png"),
+            "jpg",
+            vec![30_000, 120_000],
+        ),
+        (
+            "hd",
+            open("input/lober_hd.png"),
+            "jpg",
+            vec![20_000, 80_000],
+        ),
+        (
+            "screenshot",
+            open("tests/assets/screenshot.png"),
+            "png",
+            vec![10_000, 25_000],
+        ),
+        (
+            "screenshot",
+            open("tests/assets/screenshot.png"),
+            "webp",
+            vec![8_000, 30_000],
+        ),
+        (
+            "pixel_art",
+            open("tests/assets/pixel_art.gif"),
+            "gif",
+            vec![400, 2_000],
+        ),
+    ]
+}
+
+fn options(target: u64, format: &str) -> FitOptions {
+    FitOptions::new(target)
+        .format(format)
+        .tolerance(target / 50)
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fit");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+    for (name, img, format, targets) in cases() {
+        for target in targets {
+            // Criterion only measures time, so the encodes and sizes are
+            // tallied over every run it makes and printed after it. The
+            // search picks scales at random, so these are averages
+            let (runs, encodes, size) = (Cell::new(0u64), Cell::new(0u64), Cell::new(0usize));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}.{}", name, format), target),
+                &target,
+                |b, &target| {
+                    b.iter(|| {
+                        let fitted = fit(&img, &options(target, format)).unwrap();
+                        runs.set(runs.get() + 1);
+                        encodes.set(encodes.get() + fitted.encodes as u64);
+                        size.set(size.get() + fitted.bytes.len());
+                    })
+                },
+            );
+            if runs.get() > 0 {
+                let runs = runs.get() as f64;
+                eprintln!(
+                    "{}.{} at {} B: {:.1} encodes, {:.1}% of the target on average",
+                    name,
+                    format,
+                    target,
+                    encodes.get() as f64 / runs,
+                    size.get() as f64 / runs / target as f64 * 100.0
+                );
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
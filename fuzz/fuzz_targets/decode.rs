@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Whatever the bytes are, decoding has to come back with an image or an
+// error
+fuzz_target!(|data: &[u8]| {
+    let _ = autosize::decode(data);
+});
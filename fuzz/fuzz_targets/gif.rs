@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Same as decode, but always behind a GIF signature so the fuzzer spends its
+// time inside the frame-by-frame GIF decoding rather than format sniffing
+fuzz_target!(|data: &[u8]| {
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend_from_slice(data);
+    let _ = autosize::decode(&gif);
+});
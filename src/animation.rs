@@ -0,0 +1,1103 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use color_quant::NeuQuant;
+use gif::{DisposalMethod, Encoder, Repeat};
+use image::{codecs::gif::GifDecoder, imageops, AnimationDecoder, Rgba, RgbaImage};
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    crop::Rect,
+    dither::{self, Dither},
+    encode::EncoderSettings,
+    interlace,
+    messages::tr,
+    options::Options,
+    oracle::TIMED_OUT,
+    pipeline, progress, resample, resume, search_rng, tools, units, Found,
+};
+
+// Which frame to keep when turning an animation into a still
+#[derive(Clone, Copy)]
+pub enum Still {
+    // The frame with the most detail, see Animation::pick_still
+    Auto,
+    Frame(usize),
+}
+
+// Which part of an animation to keep before it's fitted, see --trim-frames
+// and --trim-duration. Frames are cut first, the duration counts from the
+// first frame that's left
+#[derive(Clone, Default)]
+pub struct Trim {
+    // From the first index up to but not including the second, or the end
+    pub frames: Option<(usize, Option<usize>)>,
+    // In milliseconds
+    pub duration: Option<u64>,
+}
+
+impl Trim {
+    pub fn is_set(&self) -> bool {
+        self.frames.is_some() || self.duration.is_some()
+    }
+}
+
+// Changes to how an animation plays, made after any trim: reversed, then
+// played forwards and back, then sped up or slowed down
+#[derive(Clone, Default)]
+pub struct Transform {
+    pub reverse: bool,
+    pub boomerang: bool,
+    // Playback speed as a factor, 2 for twice as fast
+    pub speed: Option<f64>,
+}
+
+impl Transform {
+    pub fn is_set(&self) -> bool {
+        self.reverse || self.boomerang || self.speed.is_some()
+    }
+}
+
+// Browsers show frames with shorter delays than this for 10cs instead, so
+// speeding up below it would slow the animation down
+const MIN_DELAY: f64 = 2.0;
+
+// How long a frame with this delay is really shown, in centiseconds. A delay
+// of 0 plays as 10cs
+fn shown(delay: u16) -> u16 {
+    match delay {
+        0 => 10,
+        d => d,
+    }
+}
+
+// With --adaptive, the least detail a frame in fast motion is given, as a
+// fraction of the animation's scale, and the mean change between frames (out
+// of 255) at which it gets that little
+const MIN_DETAIL: f64 = 0.6;
+const FULL_MOTION: f64 = 20.0;
+// Motion is measured on frames about this wide
+const MOTION_WIDTH: u32 = 160;
+
+// How far a channel can differ from the first frame and still count as
+// unchanged, for encoder noise, and the border kept around what changes
+const CHANGE_TOLERANCE: u8 = 8;
+const CHANGE_MARGIN: u32 = 4;
+
+// Shannon entropy of the luma histogram, in bits per pixel
+pub fn entropy(frame: &RgbaImage) -> f64 {
+    let mut histogram = [0u64; 256];
+    for p in frame.pixels() {
+        let luma = (p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000;
+        histogram[luma as usize] += 1;
+    }
+    let total = (frame.width() * frame.height()).max(1) as f64;
+    histogram
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// The decoded animation, kept untouched for the whole search so every
+// candidate is resized from the original frames rather than a previous one
+#[derive(Clone)]
+pub struct Animation {
+    pub frames: Vec<RgbaImage>,
+    // Per-frame delays in centiseconds
+    pub delays: Vec<u16>,
+}
+
+impl Animation {
+    pub fn load_gif<P: AsRef<Path>>(path: P) -> Result<Animation, Box<dyn Error>> {
+        Animation::read_gif(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_gif<R: Read>(reader: R) -> Result<Animation, Box<dyn Error>> {
+        let decoder = GifDecoder::new(reader)?;
+        let mut frames = Vec::new();
+        let mut delays = Vec::new();
+        for frame in decoder.into_frames() {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            delays.push((numer as f64 / denom.max(1) as f64 / 10.0).round() as u16);
+            frames.push(frame.into_buffer());
+        }
+        Ok(Animation { frames, delays })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Fade-ins, blank title cards and end frames carry little detail, so the
+    // highest-entropy frame is usually the most representative one
+    pub fn pick_still(&self, still: Still) -> Result<usize, Box<dyn Error>> {
+        match still {
+            Still::Frame(index) if index < self.len() => Ok(index),
+            Still::Frame(index) => Err(format!(
+                "--still {} is out of range, the animation has {} frames",
+                index,
+                self.len()
+            )
+            .into()),
+            Still::Auto => {
+                let entropies = self.frames.par_iter().map(entropy).collect::<Vec<_>>();
+                Ok((0..self.len()).fold(0, |best, i| {
+                    if entropies[i] > entropies[best] {
+                        i
+                    } else {
+                        best
+                    }
+                }))
+            }
+        }
+    }
+
+    pub fn resize(&self, scale: f64, filter: imageops::FilterType) -> Vec<RgbaImage> {
+        self.frames
+            .par_iter()
+            .map(|frame| {
+                let (w, h) = (frame.width() as f64, frame.height() as f64);
+                resample::rgba(
+                    frame,
+                    ((w * scale) as u32).max(1),
+                    ((h * scale) as u32).max(1),
+                    filter,
+                )
+            })
+            .collect()
+    }
+
+    // How much detail each frame keeps with --adaptive, from MIN_DETAIL for
+    // fast motion, where the eye can't follow it anyway, to 1.0 for frames
+    // that hold still. Smoothed over a few frames and rounded to tenths, so
+    // the animation falls into segments rather than flickering between
+    // sharpness from one frame to the next
+    pub fn detail_by_motion(&self) -> Vec<f64> {
+        let probes = self
+            .frames
+            .par_iter()
+            .map(|frame| {
+                let gray = imageops::grayscale(frame);
+                let factor = (gray.width() as f64 / MOTION_WIDTH as f64).max(1.0);
+                imageops::resize(
+                    &gray,
+                    ((gray.width() as f64 / factor) as u32).max(1),
+                    ((gray.height() as f64 / factor) as u32).max(1),
+                    imageops::FilterType::Triangle,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut motion = (1..probes.len())
+            .map(|i| {
+                let (a, b) = (&probes[i - 1], &probes[i]);
+                if a.dimensions() != b.dimensions() {
+                    return FULL_MOTION;
+                }
+                let sum = a
+                    .as_raw()
+                    .iter()
+                    .zip(b.as_raw())
+                    .map(|(x, y)| x.abs_diff(*y) as u64)
+                    .sum::<u64>();
+                sum as f64 / a.as_raw().len().max(1) as f64
+            })
+            .collect::<Vec<_>>();
+        // The first frame moves as much as the one after it
+        motion.insert(0, motion.first().copied().unwrap_or(0.0));
+        (0..motion.len())
+            .map(|i| {
+                let mut window = motion[i.saturating_sub(2)..(i + 3).min(motion.len())].to_vec();
+                window.sort_by(f64::total_cmp);
+                let median = window[window.len() / 2];
+                let detail = 1.0 - (1.0 - MIN_DETAIL) * (median / FULL_MOTION).min(1.0);
+                (detail * 10.0).round() / 10.0
+            })
+            .collect()
+    }
+
+    // Frames at the given scale, each with only detail[i] of that: shrunk
+    // further and brought back up to the same size, so the animation keeps
+    // one size while frames in motion cost fewer bytes
+    pub fn resize_adaptive(
+        &self,
+        scale: f64,
+        detail: &[f64],
+        filter: imageops::FilterType,
+    ) -> Vec<RgbaImage> {
+        self.frames
+            .par_iter()
+            .zip(detail)
+            .map(|(frame, &detail)| {
+                let (w, h) = (frame.width() as f64, frame.height() as f64);
+                let (sw, sh) = (((w * scale) as u32).max(1), ((h * scale) as u32).max(1));
+                if detail >= 1.0 {
+                    return resample::rgba(frame, sw, sh, filter);
+                }
+                let small = resample::rgba(
+                    frame,
+                    ((sw as f64 * detail) as u32).max(1),
+                    ((sh as f64 * detail) as u32).max(1),
+                    filter,
+                );
+                resample::rgba(&small, sw, sh, imageops::FilterType::Triangle)
+            })
+            .collect()
+    }
+
+    // Encodes the animation at the given scale, skipping the resize at 1.0
+    pub fn encode(
+        &self,
+        scale: f64,
+        filter: imageops::FilterType,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if scale == 1.0 {
+            encode_gif(&self.frames, &self.delays, settings)
+        } else {
+            encode_gif(&self.resize(scale, filter), &self.delays, settings)
+        }
+    }
+
+    // The part of the animation trim keeps, with the last frame cut short
+    // when the duration ends partway through it
+    pub fn trim(&self, trim: &Trim) -> Result<Animation, Box<dyn Error>> {
+        let (start, end) = trim.frames.unwrap_or((0, None));
+        let end = end.unwrap_or(self.len()).min(self.len());
+        if start >= end {
+            return Err(format!(
+                "--trim-frames leaves no frames, the animation has {}",
+                self.len()
+            )
+            .into());
+        }
+        let mut frames = self.frames[start..end].to_vec();
+        let mut delays = self.delays[start..end].to_vec();
+        if let Some(ms) = trim.duration {
+            // Delays are in centiseconds, and at least one frame stays
+            let limit = (ms / 10).max(1) as u32;
+            let mut elapsed = 0;
+            let mut kept = 0;
+            for delay in delays.iter_mut() {
+                if elapsed >= limit {
+                    break;
+                }
+                *delay = (shown(*delay) as u32).min(limit - elapsed) as u16;
+                elapsed += *delay as u32;
+                kept += 1;
+            }
+            frames.truncate(kept);
+            delays.truncate(kept);
+        }
+        Ok(Animation { frames, delays })
+    }
+
+    pub fn transform(&self, transform: &Transform) -> Animation {
+        let mut frames = self.frames.clone();
+        let mut delays = self.delays.clone();
+        if transform.reverse {
+            frames.reverse();
+            delays.reverse();
+        }
+        // Back from the second to last frame to the second, so neither end
+        // is shown twice in a row when it loops
+        if transform.boomerang && frames.len() > 2 {
+            let back = 1..frames.len() - 1;
+            frames.extend(
+                frames[back.clone()]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+            delays.extend(delays[back].iter().rev().copied().collect::<Vec<_>>());
+        }
+        let Some(speed) = transform.speed else {
+            return Animation { frames, delays };
+        };
+        // Frames that would go by faster than browsers allow are dropped,
+        // their time going to the frame before
+        let mut kept: Vec<(RgbaImage, f64)> = Vec::new();
+        for (frame, delay) in frames.into_iter().zip(delays) {
+            let delay = shown(delay) as f64 / speed;
+            match kept.last_mut() {
+                Some((_, last)) if *last < MIN_DELAY => *last += delay,
+                _ => kept.push((frame, delay)),
+            }
+        }
+        Animation {
+            delays: kept
+                .iter()
+                .map(|(_, d)| d.round().clamp(MIN_DELAY, u16::MAX as f64) as u16)
+                .collect(),
+            frames: kept.into_iter().map(|(f, _)| f).collect(),
+        }
+    }
+
+    // Merges runs of frames that look the same into their first frame, shown
+    // for as long as the whole run was. Frames count as the same when no
+    // channel of any pixel differs by more than tolerance from the run's
+    // first frame, comparing against that rather than the frame before so a
+    // slow fade can't creep through. Screen recordings are full of these,
+    // captured at a steady rate while nothing moved
+    pub fn coalesce(&self, tolerance: u8) -> Animation {
+        let same = |a: &RgbaImage, b: &RgbaImage| {
+            a.dimensions() == b.dimensions()
+                && a.as_raw()
+                    .iter()
+                    .zip(b.as_raw())
+                    .all(|(x, y)| x.abs_diff(*y) <= tolerance)
+        };
+        let mut frames: Vec<RgbaImage> = Vec::new();
+        let mut delays: Vec<u16> = Vec::new();
+        for (frame, &delay) in self.frames.iter().zip(&self.delays) {
+            match frames.last() {
+                Some(last) if same(last, frame) => {
+                    // A 0 delay's time has to be kept once merged
+                    let merged = delays.last_mut().expect("a delay for every frame");
+                    *merged = shown(*merged).saturating_add(shown(delay));
+                }
+                _ => {
+                    frames.push(frame.clone());
+                    delays.push(delay);
+                }
+            }
+        }
+        Animation { frames, delays }
+    }
+
+    // The smallest part of the frames that takes in every pixel that ever
+    // differs from the first frame, with a little margin. None when nothing
+    // changes or the frames aren't all the same size
+    pub fn changing_region(&self) -> Option<Rect> {
+        let first = self.frames.first()?;
+        let (w, h) = first.dimensions();
+        if self.frames.iter().any(|f| f.dimensions() != (w, h)) {
+            return None;
+        }
+        let bounds = self.frames[1..]
+            .par_iter()
+            .filter_map(|frame| {
+                let mut bounds: Option<(u32, u32, u32, u32)> = None;
+                for (x, y, pixel) in frame.enumerate_pixels() {
+                    let before = first.get_pixel(x, y);
+                    let changed = pixel
+                        .0
+                        .iter()
+                        .zip(before.0)
+                        .any(|(a, b)| a.abs_diff(b) > CHANGE_TOLERANCE);
+                    if changed {
+                        bounds = Some(match bounds {
+                            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                            None => (x, y, x, y),
+                        });
+                    }
+                }
+                bounds
+            })
+            .reduce_with(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))?;
+        let (x0, y0) = (
+            bounds.0.saturating_sub(CHANGE_MARGIN),
+            bounds.1.saturating_sub(CHANGE_MARGIN),
+        );
+        let (x1, y1) = (
+            (bounds.2 + CHANGE_MARGIN).min(w - 1),
+            (bounds.3 + CHANGE_MARGIN).min(h - 1),
+        );
+        Some(Rect {
+            x: x0,
+            y: y0,
+            w: x1 - x0 + 1,
+            h: y1 - y0 + 1,
+        })
+    }
+
+    pub fn crop(&self, r: Rect) -> Animation {
+        Animation {
+            frames: self
+                .frames
+                .par_iter()
+                .map(|frame| imageops::crop_imm(frame, r.x, r.y, r.w, r.h).to_image())
+                .collect(),
+            delays: self.delays.clone(),
+        }
+    }
+
+    // Every other frame, each one kept showing for as long as the pair did
+    pub fn drop_every_other(&self) -> Animation {
+        Animation {
+            frames: self.frames.iter().step_by(2).cloned().collect(),
+            delays: self
+                .delays
+                .chunks(2)
+                .map(|pair| pair.iter().fold(0u16, |a, &d| a.saturating_add(shown(d))))
+                .collect(),
+        }
+    }
+
+    // Encodes and runs any configured external tools, as the search measures
+    pub fn encode_candidate(&self, scale: f64, opts: &Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        tools::apply(
+            &opts.tools,
+            "gif",
+            self.encode(opts.resize_scale(scale), opts.filter, &opts.encoder)?,
+        )
+    }
+}
+
+// Quantizes every frame in parallel, then writes them out in order.
+pub fn encode_gif(
+    images: &[RgbaImage],
+    delays: &[u16],
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let colors = settings.palette.map_or(256, usize::from);
+    encode_gif_colors(images, delays, settings, colors)
+}
+
+// Quantizes a frame down to at most `colors` palette entries.
+// speed is the NeuQuant sampling factor: 1 is slowest/best, 30 is fastest
+fn quantize(image: &RgbaImage, speed: i32, colors: usize, dither: Dither) -> gif::Frame<'static> {
+    let (w, h) = (image.width() as u16, image.height() as u16);
+    let mut pixels = image.as_raw().clone();
+    if colors >= 256 && dither == Dither::None {
+        return gif::Frame::from_rgba_speed(w, h, &mut pixels, speed);
+    }
+    let mut transparent = None;
+    for pix in pixels.chunks_exact_mut(4) {
+        if pix[3] != 0 {
+            pix[3] = 0xFF;
+        } else {
+            transparent = Some([pix[0], pix[1], pix[2], pix[3]]);
+        }
+    }
+    let nq = NeuQuant::new(speed, colors.min(256), &pixels);
+    let transparent = transparent.map(|t| nq.index_of(&t) as u8);
+    let palette = nq.color_map_rgb();
+    let indices = dither::indices(image, &entries(&palette), transparent, dither, |p| {
+        nq.index_of(&[p[0], p[1], p[2], 0xFF]) as u8
+    });
+    gif::Frame::from_palette_pixels(w, h, indices, palette, transparent)
+}
+
+// A palette as written in a GIF, one colour per entry
+fn entries(rgb: &[u8]) -> Vec<[u8; 3]> {
+    rgb.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect()
+}
+
+// Pixels sampled for a global palette, spread evenly over all the frames.
+// NeuQuant samples what it's given anyway, so more would only cost memory
+const PALETTE_SAMPLES: usize = 1 << 20;
+
+// One palette for every frame, learned from all of them. Each frame getting
+// its own palette makes the same colour land on slightly different entries
+// from frame to frame, which flickers once the frames are resized and
+// requantized. When any frame has transparency, or reserve says some will,
+// the last entry is kept for it
+struct GlobalPalette {
+    nq: NeuQuant,
+    transparent: Option<u8>,
+}
+
+impl GlobalPalette {
+    fn new(images: &[RgbaImage], speed: i32, colors: usize, reserve: bool) -> GlobalPalette {
+        let total = images.iter().map(|i| i.pixels().len()).sum::<usize>();
+        let step = total.div_ceil(PALETTE_SAMPLES).max(1);
+        let mut transparent = reserve;
+        let mut samples = Vec::with_capacity(total / step * 4 + 4);
+        for (i, pixel) in images.iter().flat_map(|i| i.pixels()).enumerate() {
+            if pixel[3] == 0 {
+                transparent = true;
+            } else if i % step == 0 {
+                samples.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 0xFF]);
+            }
+        }
+        if samples.is_empty() {
+            samples.extend_from_slice(&[0, 0, 0, 0xFF]);
+        }
+        let colors = colors.clamp(2, 256);
+        let opaque = if transparent { colors - 1 } else { colors };
+        GlobalPalette {
+            nq: NeuQuant::new(speed, opaque, &samples),
+            transparent: transparent.then_some(opaque as u8),
+        }
+    }
+
+    // The palette as written in the GIF's header, transparent entry last
+    fn rgb(&self) -> Vec<u8> {
+        let mut rgb = self.nq.color_map_rgb();
+        if self.transparent.is_some() {
+            rgb.extend_from_slice(&[0, 0, 0]);
+        }
+        rgb
+    }
+
+    fn frame(&self, image: &RgbaImage, dither: Dither) -> gif::Frame<'static> {
+        let palette = entries(&self.nq.color_map_rgb());
+        let indices = dither::indices(image, &palette, self.transparent, dither, |p| {
+            self.nq.index_of(&[p[0], p[1], p[2], 0xFF]) as u8
+        });
+        let mut frame = gif::Frame::from_indexed_pixels(
+            image.width() as u16,
+            image.height() as u16,
+            indices,
+            self.transparent,
+        );
+        frame.palette = None;
+        frame
+    }
+}
+
+// Applies the per-frame settings shared by every way of writing frames
+fn finish_frame(frame: &mut gif::Frame<'static>, delay: u16, settings: &EncoderSettings) {
+    if settings.interlaced {
+        frame.buffer =
+            interlace::gif_rows(&frame.buffer, frame.width as usize, frame.height as usize).into();
+        frame.interlaced = true;
+    }
+    frame.delay = delay;
+}
+
+// palette is the global one, empty when every frame brings its own
+fn write_gif(
+    frames: &[gif::Frame],
+    w: u16,
+    h: u16,
+    palette: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut bytes, w, h, palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        for frame in frames {
+            encoder.write_frame(frame)?;
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn encode_gif_colors(
+    images: &[RgbaImage],
+    delays: &[u16],
+    settings: &EncoderSettings,
+    colors: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let global = settings
+        .gif_global_palette
+        .then(|| GlobalPalette::new(images, settings.gif_speed, colors, false));
+    let frames = images
+        .par_iter()
+        .zip(delays.par_iter())
+        .map(|(image, delay)| {
+            let mut frame = match &global {
+                Some(global) => global.frame(image, settings.dither),
+                None => quantize(image, settings.gif_speed, colors, settings.dither),
+            };
+            finish_frame(&mut frame, *delay, settings);
+            // Frames are full composites, so clear before drawing the next
+            frame.dispose = DisposalMethod::Background;
+            frame
+        })
+        .collect::<Vec<_>>();
+    let (w, h) = match images.first() {
+        Some(image) => (image.width() as u16, image.height() as u16),
+        None => return Err("animation has no frames".into()),
+    };
+    let palette = global.map(|g| g.rgb()).unwrap_or_default();
+    write_gif(&frames, w, h, &palette)
+}
+
+// The part of `cur` that differs from `prev` as (left, top, patch), with
+// pixels that didn't change made transparent so they show the frame below
+fn diff_patch(prev: &RgbaImage, cur: &RgbaImage) -> (u32, u32, RgbaImage) {
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in cur.enumerate_pixels() {
+        if prev.get_pixel(x, y) != pixel {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x);
+            y1 = y1.max(y);
+        }
+    }
+    // Identical frames still need a frame to carry their delay
+    if x0 == u32::MAX {
+        return (0, 0, RgbaImage::new(1, 1));
+    }
+    let mut patch = imageops::crop_imm(cur, x0, y0, x1 - x0 + 1, y1 - y0 + 1).to_image();
+    for (x, y, pixel) in patch.enumerate_pixels_mut() {
+        if prev.get_pixel(x0 + x, y0 + y) == pixel {
+            *pixel = Rgba([0, 0, 0, 0]);
+        }
+    }
+    (x0, y0, patch)
+}
+
+// gifsicle-style frame differencing: every frame after the first only
+// stores the rectangle that changed, and unchanged pixels inside it become
+// transparent runs that LZW compresses to almost nothing
+pub fn encode_gif_diffed(
+    images: &[RgbaImage],
+    delays: &[u16],
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Real transparency would be mistaken for "unchanged" when drawn over
+    // the previous frame, so those animations keep full frames
+    if images
+        .iter()
+        .any(|image| image.pixels().any(|pixel| pixel[3] == 0))
+    {
+        return encode_gif(images, delays, settings);
+    }
+    let colors = settings.palette.map_or(256, usize::from);
+    // Learned from the whole frames, with an entry for the unchanged pixels
+    // of the patches
+    let global = settings
+        .gif_global_palette
+        .then(|| GlobalPalette::new(images, settings.gif_speed, colors, images.len() > 1));
+    let frames = (0..images.len())
+        .into_par_iter()
+        .map(|i| {
+            let (left, top, patch) = match i {
+                0 => (0, 0, images[0].clone()),
+                _ => diff_patch(&images[i - 1], &images[i]),
+            };
+            let mut frame = match &global {
+                Some(global) => global.frame(&patch, settings.dither),
+                None => quantize(&patch, settings.gif_speed, colors, settings.dither),
+            };
+            finish_frame(&mut frame, delays[i], settings);
+            frame.left = left as u16;
+            frame.top = top as u16;
+            frame.dispose = DisposalMethod::Keep;
+            frame
+        })
+        .collect::<Vec<_>>();
+    let (w, h) = match images.first() {
+        Some(image) => (image.width() as u16, image.height() as u16),
+        None => return Err("animation has no frames".into()),
+    };
+    let palette = global.map(|g| g.rgb()).unwrap_or_default();
+    write_gif(&frames, w, h, &palette)
+}
+
+// An animation as it would be written at scale: with frame differencing
+// when that's on and pays off
+pub fn encode_final(
+    anim: &Animation,
+    scale: f64,
+    opts: &Options,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !opts.encoder.gif_optimize {
+        return anim.encode_candidate(scale, opts);
+    }
+    // Neither encoding needs the other, so they're made side by side.
+    // Errors don't cross threads, their messages do
+    let diffed = || match scale == 1.0 {
+        true => encode_gif_diffed(&anim.frames, &anim.delays, &opts.encoder),
+        false => encode_gif_diffed(
+            &anim.resize(opts.resize_scale(scale), opts.filter),
+            &anim.delays,
+            &opts.encoder,
+        ),
+    };
+    let (bytes, optimized) = rayon::join(
+        || {
+            anim.encode_candidate(scale, opts)
+                .map_err(|e| e.to_string())
+        },
+        || diffed().map_err(|e| e.to_string()),
+    );
+    let (bytes, optimized) = (bytes?, optimized?);
+    Ok(if optimized.len() < bytes.len() {
+        optimized
+    } else {
+        bytes
+    })
+}
+
+// Searches for the largest scale whose encoding fits the target, like
+// search does for stills
+pub fn search_gif(anim: &Animation, opts: &Options) -> Result<Found, Box<dyn Error>> {
+    let (target, m, byte_diff) = (opts.target, opts.iters, opts.byte_halt);
+    let settings = &opts.encoder;
+    // With --adaptive every candidate is made with the same detail per frame
+    let detail = opts.adaptive.then(|| anim.detail_by_motion());
+    if let Some(detail) = &detail {
+        let segments = 1 + detail.windows(2).filter(|w| w[0] != w[1]).count();
+        let least = detail.iter().copied().fold(1.0, f64::min);
+        println!("{}", tr!(AdaptiveDetail, segments, least));
+    }
+    let frames_at = |scale: f64| match &detail {
+        Some(detail) => anim.resize_adaptive(opts.resize_scale(scale), detail, opts.filter),
+        None => anim.resize(opts.resize_scale(scale), opts.filter),
+    };
+    let candidate = |scale: f64| match &detail {
+        Some(_) => tools::apply(
+            &opts.tools,
+            "gif",
+            encode_gif(&frames_at(scale), &anim.delays, settings)?,
+        ),
+        None => anim.encode_candidate(scale, opts),
+    };
+    // The size of a candidate, like oracle::Encoder measures a still's:
+    // timed with --encode-timeout, and scales at or above one that ran out
+    // of time skipped
+    let mut too_slow = None;
+    let mut measure = |scale: f64| -> Result<f64, Box<dyn Error>> {
+        if too_slow.is_some_and(|slow| scale >= slow) {
+            return Ok(TIMED_OUT as f64);
+        }
+        let Some(timeout) = opts.encode_timeout else {
+            return Ok(candidate(scale)?.len() as f64);
+        };
+        let frames = match scale == 1.0 {
+            true => anim.frames.clone(),
+            false => frames_at(scale),
+        };
+        let (delays, tools) = (anim.delays.clone(), opts.tools.clone());
+        let settings = *settings;
+        let encoded = pipeline::with_timeout(timeout, move || {
+            encode_gif(&frames, &delays, &settings)
+                .and_then(|bytes| tools::apply(&tools, "gif", bytes))
+                .map_err(|e| e.to_string())
+        })?;
+        match encoded {
+            Some(bytes) => Ok(bytes.len() as f64),
+            None => {
+                if !opts.quiet {
+                    println!("{}", tr!(CandidateTimedOut, scale, timeout.as_secs_f64()));
+                }
+                too_slow = Some(scale);
+                Ok(TIMED_OUT as f64)
+            }
+        }
+    };
+    let fingerprint = match &opts.session {
+        Some(_) => resume::fingerprint(anim, opts),
+        None => 0,
+    };
+    // Picking up from a checkpoint skips the encodes it already did
+    let resumed = opts.session.as_ref().and_then(|s| s.resumed);
+    let resumed = match resumed {
+        Some(state) if state.fingerprint == fingerprint && state.target == target => Some(state),
+        Some(_) => {
+            println!("{}", tr!(CheckpointMismatch));
+            None
+        }
+        None => None,
+    };
+    let (mut scale, mut encodes, osize, mut imgsize);
+    match resumed {
+        Some(state) => {
+            (scale, encodes, osize, imgsize) =
+                (state.scale, state.encodes, state.full_size, state.size);
+        }
+        None => {
+            scale = 1.0f64.clamp(opts.min_scale, opts.max_scale);
+            encodes = 1;
+            osize = measure(1.0)?;
+            imgsize = if scale == 1.0 {
+                osize
+            } else {
+                encodes += 1;
+                measure(scale)?
+            };
+        }
+    }
+    let mut psize = osize;
+    if psize < target as f64 {
+        psize = target as f64;
+    }
+    let mut i = 0;
+    let mut diff;
+    let mut rng = search_rng(opts);
+    let mut best_scale = 1.0f64.min(opts.max_scale);
+    let mut best_diff = f64::MAX;
+    let mut best_size = imgsize;
+    let (mut a, mut b) = (0.0f64, 1.0f64);
+    if target > osize as u64 {
+        a = b;
+        b = (target as f64 / osize) * 1.05;
+    }
+    a = a.max(opts.min_scale);
+    b = b.min(opts.max_scale);
+    if let Some(state) = resumed {
+        (i, best_scale, best_diff, best_size) =
+            (state.i, state.best_scale, state.best_diff, state.best_size);
+        (a, b) = (state.low, state.high);
+        println!("{}", tr!(ResumedAt, i, best_scale));
+    }
+    let mut saved_at = Instant::now();
+
+    println!("{}", tr!(Starting));
+    progress::emit(
+        opts,
+        "search",
+        &[("target", target.into()), ("iterations", (m as u64).into())],
+    );
+    loop {
+        let state = resume::State {
+            fingerprint,
+            target,
+            byte_halt: byte_diff,
+            iters: m,
+            gif_speed: settings.gif_speed,
+            i,
+            scale,
+            size: imgsize,
+            full_size: osize,
+            best_scale,
+            best_diff,
+            best_size,
+            low: a,
+            high: b,
+            encodes,
+        };
+        resume::save(opts, &state, &mut saved_at);
+        diff = imgsize - target as f64;
+
+        if diff.abs() < best_diff.abs() && diff <= 0.0 {
+            best_scale = scale;
+            best_diff = diff;
+            best_size = imgsize;
+            println!("\r\u{8}||{:^wa$}({:6.2}%) || BEST_DIFF: {:>width$}, BEST_SCALE: {:5.2} || SCALE: {:.2} || RANGE: ({:>5.2}:{:<5.2}) ||", i, (i as f32 / m as f32) * 100.0, best_diff, best_scale, scale, a, b, wa=(m.to_string().len()+2), width=(psize.to_string().len()));
+        }
+        let best = if best_diff == f64::MAX {
+            f64::NAN
+        } else {
+            best_scale
+        };
+        progress::iteration(opts, i, scale, imgsize as u64, best, (a, b));
+
+        // Close enough only counts from below, a candidate just over the
+        // target doesn't fit
+        let close = diff <= 0.0 && diff.abs() < byte_diff as f64;
+        if i > m || (1.0 - (a.min(b) / a.max(b))).abs() < 0.05 || close {
+            break;
+        } else {
+            print!("\r\u{8}");
+            print!(
+                "||{:^wa$}({:3.2}%) ||",
+                i,
+                (i as f32 / m as f32) * 100.0,
+                wa = (m.to_string().len() + 2)
+            );
+            io::stdout().flush().unwrap();
+        }
+
+        let lscale = scale;
+        if imgsize < target as f64 {
+            a = scale - (1.0 / (i + 2) as f64);
+        } else {
+            b = scale + (1.0 / (i + 2) as f64);
+        }
+        a = a.max(opts.min_scale);
+        b = b.min(opts.max_scale);
+        if a >= b {
+            break;
+        }
+        scale = rng.gen_range(a..b);
+        if scale < 0.0 {
+            scale = lscale;
+        }
+        imgsize = measure(scale)?;
+        encodes += 1;
+
+        i += 1;
+    }
+    let at = format!("{:^wa$}", i, wa = (m.to_string().len() + 2));
+    println!("{}", tr!(StoppedAt, at, (i as f32 / m as f32) * 100.0));
+    progress::emit(
+        opts,
+        "search_done",
+        &[
+            ("iterations", (i as u64).into()),
+            ("scale", best_scale.into()),
+        ],
+    );
+    // Nothing fit above the scale floor, so stay at the floor and shrink the
+    // palette instead of the resolution
+    let floor = best_diff == f64::MAX && opts.min_scale > 0.0;
+    if !floor && too_slow.is_some_and(|slow| best_scale >= slow) {
+        return Err(tr!(AllCandidatesTimedOut).into());
+    }
+    // The target is a limit, so handing back something over it isn't a
+    // result
+    if best_diff == f64::MAX && !floor {
+        return Err(tr!(NothingFits, units::format_size(target)).into());
+    }
+    let mut bytes = match floor {
+        true => Vec::new(),
+        false => candidate(best_scale)?,
+    };
+    if floor {
+        best_scale = opts.min_scale;
+        let frames = frames_at(best_scale);
+        let mut colors = settings.palette.map_or(256, usize::from);
+        bytes = encode_gif_colors(&frames, &anim.delays, settings, colors)?;
+        while bytes.len() as u64 > target && colors > 2 {
+            colors /= 2;
+            bytes = encode_gif_colors(&frames, &anim.delays, settings, colors)?;
+        }
+        best_size = bytes.len() as f64;
+        if best_size as u64 > target {
+            return Err(tr!(FloorUnreachable, best_scale).into());
+        }
+        println!("{}", tr!(FloorGifPalette, best_scale, colors));
+    }
+    // Lossless frame differencing on the chosen scale, if it pays off
+    if settings.gif_optimize && best_size as u64 <= target {
+        let optimized = encode_gif_diffed(&frames_at(best_scale), &anim.delays, settings)?;
+        if optimized.len() < bytes.len() {
+            bytes = optimized;
+        }
+    }
+    Ok(Found {
+        scale: opts.resize_scale(best_scale),
+        bytes,
+        encodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An animation of flat grey frames, one per (level, delay)
+    fn flat(frames: &[(u8, u16)]) -> Animation {
+        Animation {
+            frames: frames
+                .iter()
+                .map(|&(level, _)| {
+                    RgbaImage::from_pixel(4, 4, image::Rgba([level, level, level, 255]))
+                })
+                .collect(),
+            delays: frames.iter().map(|&(_, delay)| delay).collect(),
+        }
+    }
+
+    fn levels(anim: &Animation) -> Vec<u8> {
+        anim.frames.iter().map(|f| f.get_pixel(0, 0).0[0]).collect()
+    }
+
+    #[test]
+    fn coalescing_merges_runs_and_adds_up_their_delays() {
+        let anim = flat(&[(10, 5), (11, 5), (12, 5), (50, 4), (50, 0), (10, 7)]);
+        let merged = anim.coalesce(2);
+        assert_eq!(levels(&merged), vec![10, 50, 10]);
+        // A 0 delay plays as 10cs and counts as that once merged
+        assert_eq!(merged.delays, vec![15, 14, 7]);
+        // Exact repeats only
+        let exact = anim.coalesce(0);
+        assert_eq!(levels(&exact), vec![10, 11, 12, 50, 10]);
+        assert_eq!(exact.delays, vec![5, 5, 5, 14, 7]);
+    }
+
+    #[test]
+    fn coalescing_compares_with_the_first_frame_of_the_run() {
+        // Each step is within the tolerance, the fade as a whole isn't
+        let fade = flat(&[(0, 2), (2, 2), (4, 2), (6, 2), (8, 2)]);
+        let merged = fade.coalesce(2);
+        assert_eq!(levels(&merged), vec![0, 4, 8]);
+        assert_eq!(merged.delays, vec![4, 4, 2]);
+    }
+
+    #[test]
+    fn trimming_cuts_frames_then_the_duration() {
+        let anim = flat(&[(0, 10), (1, 20), (2, 0), (3, 30), (4, 10)]);
+        let trim = |frames, duration| {
+            anim.trim(&Trim { frames, duration })
+                .map(|t| (levels(&t), t.delays))
+        };
+        assert_eq!(
+            trim(Some((1, Some(4))), None).unwrap(),
+            (vec![1, 2, 3], vec![20, 0, 30])
+        );
+        assert_eq!(
+            trim(Some((3, None)), None).unwrap(),
+            (vec![3, 4], vec![30, 10])
+        );
+        // The last frame kept is cut short where the duration ends
+        assert_eq!(trim(None, Some(250)).unwrap(), (vec![0, 1], vec![10, 15]));
+        // A 0 delay plays for 10cs, so it counts as that
+        assert_eq!(
+            trim(None, Some(350)).unwrap(),
+            (vec![0, 1, 2], vec![10, 20, 5])
+        );
+        assert_eq!(
+            trim(Some((2, None)), Some(150)).unwrap(),
+            (vec![2, 3], vec![10, 5])
+        );
+        // At least one frame stays
+        assert_eq!(trim(None, Some(1)).unwrap(), (vec![0], vec![1]));
+        assert!(trim(Some((5, None)), None).is_err());
+        assert!(trim(Some((3, Some(3))), None).is_err());
+    }
+
+    #[test]
+    fn transforms_reverse_then_boomerang_then_change_speed() {
+        let anim = flat(&[(0, 10), (1, 20), (2, 30), (3, 0)]);
+        let transform = |reverse, boomerang, speed| {
+            let t = anim.transform(&Transform {
+                reverse,
+                boomerang,
+                speed,
+            });
+            (levels(&t), t.delays)
+        };
+        assert_eq!(
+            transform(true, false, None),
+            (vec![3, 2, 1, 0], vec![0, 30, 20, 10])
+        );
+        // Neither end is shown twice in a row when it loops
+        assert_eq!(
+            transform(false, true, None),
+            (vec![0, 1, 2, 3, 2, 1], vec![10, 20, 30, 0, 30, 20])
+        );
+        assert_eq!(
+            transform(true, true, None),
+            (vec![3, 2, 1, 0, 1, 2], vec![0, 30, 20, 10, 20, 30])
+        );
+        assert_eq!(
+            transform(false, false, Some(2.0)),
+            (vec![0, 1, 2, 3], vec![5, 10, 15, 5])
+        );
+        assert_eq!(
+            transform(false, false, Some(0.5)),
+            (vec![0, 1, 2, 3], vec![20, 40, 60, 20])
+        );
+        // Too fast for browsers, so frames are dropped and their time goes
+        // to the frame before, and a short last frame is held for the least
+        // browsers allow
+        assert_eq!(
+            transform(false, false, Some(10.0)),
+            (vec![0, 2, 3], vec![3, 3, 2])
+        );
+    }
+
+    #[test]
+    fn dropping_every_other_frame_keeps_the_running_time() {
+        let anim = flat(&[(0, 0), (1, 0), (2, 0), (3, 5), (4, 7)]);
+        let halved = anim.drop_every_other();
+        assert_eq!(levels(&halved), vec![0, 2, 4]);
+        // A 0 delay plays as 10cs, so a pair of them is shown for 20
+        assert_eq!(halved.delays, vec![20, 15, 7]);
+    }
+
+    #[test]
+    fn coalescing_keeps_frames_of_other_sizes() {
+        let mut anim = flat(&[(0, 3), (0, 3)]);
+        anim.frames[1] = RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(anim.coalesce(255).len(), 2);
+    }
+}
@@ -0,0 +1,32 @@
+use std::{
+    fs::{self, File, FileTimes, Metadata},
+    io,
+    path::Path,
+};
+
+// Sets a file's access/modification times to those in meta
+pub fn copy_times(meta: &Metadata, to: &Path) -> io::Result<()> {
+    let mut times = FileTimes::new().set_modified(meta.modified()?);
+    if let Ok(accessed) = meta.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    File::options().write(true).open(to)?.set_times(times)
+}
+
+// Copies timestamps, permissions and (on unix) extended attributes, so photo
+// managers keyed on modification time see the output as the same photo.
+// Extended attributes are best effort, as not every filesystem takes them
+pub fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::metadata(from)?;
+    #[cfg(unix)]
+    if let Ok(names) = xattr::list(from) {
+        for name in names {
+            if let Ok(Some(value)) = xattr::get(from, &name) {
+                let _ = xattr::set(to, &name, &value);
+            }
+        }
+    }
+    // Times first, a read-only permission would stop us opening it to write
+    copy_times(&meta, to)?;
+    fs::set_permissions(to, meta.permissions())
+}
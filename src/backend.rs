@@ -0,0 +1,353 @@
+use std::{error::Error, io::Cursor};
+
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use jpeg_encoder::{ColorType, Encoder};
+
+use crate::{
+    animation,
+    depth::{self, Depth},
+    encode::EncoderSettings,
+    formats, interlace,
+};
+
+// Something that turns pixels into one or more formats. encode::encode asks
+// BACKENDS in order for the first one that takes the image, so a new
+// encoder (or a faster one for a format that already has one) is a type
+// here and a line in BACKENDS, and the searches never know the difference
+pub trait EncoderBackend: Sync {
+    // As `autosize formats` lists it
+    fn name(&self) -> &'static str;
+
+    // Extensions it writes
+    fn formats(&self) -> Vec<&'static str>;
+
+    // Whether it takes this image with these settings. By default anything
+    // in one of its formats, the special cases narrow it down
+    fn accepts(&self, _img: &DynamicImage, ext: &str, _settings: &EncoderSettings) -> bool {
+        self.formats().iter().any(|f| f.eq_ignore_ascii_case(ext))
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        ext: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>>;
+}
+
+// 4-bit greyscale PNGs for --depth gray4, which the png crate can't write
+// from an 8-bit image on its own
+struct Gray4Png;
+
+impl EncoderBackend for Gray4Png {
+    fn name(&self) -> &'static str {
+        "4-bit grey PNG"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["png"]
+    }
+
+    fn accepts(&self, img: &DynamicImage, ext: &str, settings: &EncoderSettings) -> bool {
+        ext.eq_ignore_ascii_case("png")
+            && !settings.interlaced
+            && settings.depth == Depth::Gray4
+            && matches!(img, DynamicImage::ImageLuma8(_))
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        _: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        depth::encode_gray4_png(&img.to_luma8())
+    }
+}
+
+// Indexed PNGs with the planner's palette
+struct PalettePng;
+
+impl EncoderBackend for PalettePng {
+    fn name(&self) -> &'static str {
+        "indexed PNG"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["png"]
+    }
+
+    fn accepts(&self, _: &DynamicImage, ext: &str, settings: &EncoderSettings) -> bool {
+        ext.eq_ignore_ascii_case("png")
+            && !settings.interlaced
+            && settings.palette.is_some()
+            && settings.depth == Depth::Full
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let colors = settings.palette.expect("only accepted with a palette");
+        depth::encode_palette_png(img, colors, settings.gif_speed, settings.dither)
+    }
+}
+
+// Adam7 PNGs for --interlace
+struct InterlacedPng;
+
+impl EncoderBackend for InterlacedPng {
+    fn name(&self) -> &'static str {
+        "interlaced PNG"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["png"]
+    }
+
+    fn accepts(&self, _: &DynamicImage, ext: &str, settings: &EncoderSettings) -> bool {
+        ext.eq_ignore_ascii_case("png") && settings.interlaced
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        _: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        interlace::encode_png(img)
+    }
+}
+
+// mozjpeg's trellis quantization usually saves 10-20% over a plain encoder at
+// the same quality
+#[cfg(feature = "mozjpeg")]
+struct Mozjpeg;
+
+#[cfg(feature = "mozjpeg")]
+impl EncoderBackend for Mozjpeg {
+    fn name(&self) -> &'static str {
+        "mozjpeg (--mozjpeg)"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["jpg", "jpeg"]
+    }
+
+    fn accepts(&self, _: &DynamicImage, ext: &str, settings: &EncoderSettings) -> bool {
+        crate::encode::is_jpeg(ext) && settings.mozjpeg
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        use crate::encode::Subsampling;
+        use mozjpeg::{ColorSpace, Compress};
+
+        let (color_space, pixels) = match img {
+            DynamicImage::ImageLuma8(gray) => (ColorSpace::JCS_GRAYSCALE, gray.as_raw().clone()),
+            _ => (ColorSpace::JCS_RGB, img.to_rgb8().into_raw()),
+        };
+        let (w, h) = (img.width() as usize, img.height() as usize);
+        let sampling = match settings.subsampling {
+            Subsampling::S444 => (1, 1),
+            Subsampling::S422 => (2, 1),
+            Subsampling::Auto | Subsampling::S420 => (2, 2),
+        };
+        // libjpeg reports errors by unwinding, so keep them from taking down the run
+        std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+            let mut compress = Compress::new(color_space);
+            compress.set_size(w, h);
+            compress.set_quality(settings.jpeg_quality as f32);
+            if color_space == ColorSpace::JCS_RGB {
+                compress.set_chroma_sampling_pixel_sizes(sampling, sampling);
+            }
+            // mozjpeg's defaults are progressive, which only --progressive
+            // should give. Without a scan script it writes a baseline JPEG
+            match settings.progressive {
+                true => compress.set_progressive_mode(),
+                false => compress.set_optimize_scans(false),
+            }
+            let mut started = compress.start_compress(Vec::new())?;
+            started.write_scanlines(&pixels)?;
+            started.finish()
+        })
+        .map_err(|_| "mozjpeg failed to encode the image")?
+        .map_err(|e| e.into())
+    }
+}
+
+// JPEGs with our own quality, subsampling and progressive settings
+struct Jpeg;
+
+impl EncoderBackend for Jpeg {
+    fn name(&self) -> &'static str {
+        "jpeg-encoder"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["jpg", "jpeg"]
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, settings.jpeg_quality);
+        encoder.set_sampling_factor(settings.subsampling.factor());
+        encoder.set_progressive(settings.progressive);
+        let (w, h) = (img.width() as u16, img.height() as u16);
+        match img {
+            DynamicImage::ImageLuma8(gray) => {
+                encoder.encode(gray.as_raw(), w, h, ColorType::Luma)?
+            }
+            _ => encoder.encode(img.to_rgb8().as_raw(), w, h, ColorType::Rgb)?,
+        }
+        Ok(bytes)
+    }
+}
+
+// Single-frame GIFs, through the same quantizer as animations
+struct Gif;
+
+impl EncoderBackend for Gif {
+    fn name(&self) -> &'static str {
+        "gif (NeuQuant palettes)"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["gif"]
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        animation::encode_gif(&[img.to_rgba8()], &[0], settings)
+    }
+}
+
+// JPEG XL through libjxl's cjxl
+#[cfg(feature = "jxl")]
+struct Jxl;
+
+#[cfg(feature = "jxl")]
+impl EncoderBackend for Jxl {
+    fn name(&self) -> &'static str {
+        "cjxl"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        vec!["jxl"]
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        _: &str,
+        settings: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        crate::jxl::encode(img, settings)
+    }
+}
+
+// Whatever else the image crate writes, with its own defaults
+struct Library;
+
+impl EncoderBackend for Library {
+    fn name(&self) -> &'static str {
+        "image crate"
+    }
+
+    fn formats(&self) -> Vec<&'static str> {
+        ImageFormat::all()
+            .filter(|f| f.can_write() && f.writing_enabled())
+            .flat_map(|f| f.extensions_str().iter().copied())
+            .collect()
+    }
+
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        ext: &str,
+        _: &EncoderSettings,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let format = ImageFormat::from_extension(ext)
+            .ok_or_else(|| format!("Unsupported output format '{}'", ext))?;
+        let mut bytes = Cursor::new(Vec::new());
+        img.write_to(&mut bytes, ImageOutputFormat::from(format))?;
+        Ok(bytes.into_inner())
+    }
+}
+
+// Every backend in this build, special cases before the general ones
+pub static BACKENDS: &[&dyn EncoderBackend] = &[
+    &Gray4Png,
+    &PalettePng,
+    &InterlacedPng,
+    #[cfg(feature = "mozjpeg")]
+    &Mozjpeg,
+    &Jpeg,
+    &Gif,
+    #[cfg(feature = "jxl")]
+    &Jxl,
+    &Library,
+];
+
+// The backend that encodes img as ext with these settings
+pub fn find(
+    img: &DynamicImage,
+    ext: &str,
+    settings: &EncoderSettings,
+) -> Result<&'static dyn EncoderBackend, Box<dyn Error>> {
+    BACKENDS
+        .iter()
+        .copied()
+        .find(|b| b.accepts(img, ext, settings))
+        .ok_or_else(|| match formats::check_write(ext) {
+            Err(e) => e,
+            Ok(()) => format!("Nothing in this build encodes '{}'", ext).into(),
+        })
+}
+
+#[cfg(all(test, feature = "mozjpeg"))]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    // Whether the JPEG's frame header is a progressive one, SOF2
+    fn progressive(jpeg: &[u8]) -> bool {
+        let sof = jpeg
+            .windows(2)
+            .find(|m| m[0] == 0xff && (m[1] == 0xc0 || m[1] == 0xc2))
+            .expect("a frame header");
+        sof[1] == 0xc2
+    }
+
+    #[test]
+    fn mozjpeg_is_progressive_only_when_asked() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([x as u8 * 4, y as u8 * 4, 128])
+        }));
+        let mut settings = EncoderSettings {
+            mozjpeg: true,
+            ..EncoderSettings::default()
+        };
+        let baseline = Mozjpeg.encode(&img, "jpg", &settings).unwrap();
+        assert!(!progressive(&baseline));
+        settings.progressive = true;
+        let progressed = Mozjpeg.encode(&img, "jpg", &settings).unwrap();
+        assert!(progressive(&progressed));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    attrs,
+    history::{self, Entry},
+    messages::tr,
+    options::Options,
+    settings,
+};
+
+// Where originals go before anything overwrites them. Set with
+// `backup_dir = <path>` in the config file or --backup-dir
+pub fn default_dir() -> PathBuf {
+    settings::read_config()
+        .into_iter()
+        .rev()
+        .find(|(key, _)| key == "backup_dir")
+        .map(|(_, dir)| PathBuf::from(dir))
+        .unwrap_or_else(|| settings::data_dir().join("backups"))
+}
+
+// Each backup is a tab-separated `time, original path, backup path` line
+fn index_path(dir: &Path) -> PathBuf {
+    dir.join("index")
+}
+
+// Paths go in the index as text, with `%`, tabs and line breaks written as
+// `%XX` and so is anything that isn't UTF-8, so every name reads back as
+// it was. A name that's valid UTF-8 is stored as it is otherwise
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '%' | '\t' | '\n' | '\r' => out.push_str(&format!("%{:02X}", c as u8)),
+            c => out.push(c),
+        }
+    }
+}
+
+// Two hex digits after a `%`, if that's what's there
+fn hex_byte(field: &[u8]) -> Option<u8> {
+    let hex = std::str::from_utf8(field.get(..2)?).ok()?;
+    u8::from_str_radix(hex, 16).ok()
+}
+
+// The bytes an escaped field stands for
+#[cfg(not(windows))]
+fn unescape_bytes(field: &str) -> Vec<u8> {
+    let (field, mut bytes, mut i) = (field.as_bytes(), Vec::new(), 0);
+    while i < field.len() {
+        match (field[i], hex_byte(&field[i + 1..])) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(unix)]
+fn escape_path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut out = String::new();
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        escape_text(chunk.valid(), &mut out);
+        for byte in chunk.invalid() {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+#[cfg(unix)]
+fn unescape_path(field: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(unescape_bytes(field)))
+}
+
+// Windows names are UTF-16 that may hold unpaired surrogates, which are
+// written as `%uXXXX`
+#[cfg(windows)]
+fn escape_path(path: &Path) -> String {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut out = String::new();
+    for c in char::decode_utf16(path.as_os_str().encode_wide()) {
+        match c {
+            Ok(c) => escape_text(c.encode_utf8(&mut [0; 4]), &mut out),
+            Err(e) => out.push_str(&format!("%u{:04X}", e.unpaired_surrogate())),
+        }
+    }
+    out
+}
+
+#[cfg(windows)]
+fn unescape_path(field: &str) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+
+    let (mut units, mut rest) = (Vec::new(), field);
+    while let Some(c) = rest.chars().next() {
+        let surrogate = rest
+            .strip_prefix("%u")
+            .and_then(|r| u16::from_str_radix(r.get(..4)?, 16).ok());
+        match (surrogate, hex_byte(&rest.as_bytes()[1..])) {
+            (Some(unit), _) => {
+                units.push(unit);
+                rest = &rest[6..];
+            }
+            (None, Some(byte)) if c == '%' => {
+                units.push(byte as u16);
+                rest = &rest[3..];
+            }
+            _ => {
+                units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    PathBuf::from(OsString::from_wide(&units))
+}
+
+// Elsewhere there's no way at the raw name, so it's kept as best it can be
+#[cfg(not(any(unix, windows)))]
+fn escape_path(path: &Path) -> String {
+    let mut out = String::new();
+    escape_text(&path.to_string_lossy(), &mut out);
+    out
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unescape_path(field: &str) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&unescape_bytes(field)).into_owned())
+}
+
+// Copies path into the backup dir if it exists, so it can be restored later
+pub fn backup(path: &Path, dir: &Path) -> io::Result<Option<PathBuf>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir)?;
+    let original = fs::canonicalize(path)?;
+    let time = Entry::now();
+    let name = path.file_name().unwrap_or_default();
+    // Prefixed onto the original name as an OsString, which keeps names that
+    // aren't valid UTF-8 as they are
+    let prefixed = |prefix: String| {
+        let mut copy = OsString::from(prefix);
+        copy.push(name);
+        dir.join(copy)
+    };
+    let mut copy = prefixed(format!("{}_", time));
+    let mut n = 1;
+    while copy.exists() {
+        copy = prefixed(format!("{}_{}_", time, n));
+        n += 1;
+    }
+    fs::copy(path, &copy)?;
+    // Absolute, so restore works from any directory
+    let copy = fs::canonicalize(copy)?;
+    let mut index = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_path(dir))?;
+    writeln!(
+        index,
+        "{}\t{}\t{}",
+        time,
+        escape_path(&original),
+        escape_path(&copy)
+    )?;
+    Ok(Some(copy))
+}
+
+// Writes an output file, backing up whatever it would replace first
+pub fn write_output(path: &Path, bytes: &[u8], opts: &Options) -> io::Result<()> {
+    let copy = backup(path, &opts.backup_dir)?;
+    if let Some(copy) = copy.filter(|_| !opts.quiet) {
+        println!("{}", tr!(BackedUpExisting, path.display(), copy.display()));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    opts.retry.run(path.display(), || fs::write(path, bytes))
+}
+
+// Moves the fitted file over the original, which is backed up first. The
+// original keeps its name, permissions and access/modification times
+pub fn replace_in_place(original: &Path, fitted: &Path, opts: &Options) -> io::Result<()> {
+    let meta = fs::metadata(original)?;
+    let copy = backup(original, &opts.backup_dir)?;
+    if let Some(copy) = copy.filter(|_| !opts.quiet) {
+        println!("{}", tr!(BackedUp, original.display(), copy.display()));
+    }
+    let bytes = opts.retry.run(fitted.display(), || fs::read(fitted))?;
+    // Writing into the existing file rather than renaming keeps its inode
+    opts.retry
+        .run(original.display(), || fs::write(original, &bytes))?;
+    attrs::copy_times(&meta, original)?;
+    fs::remove_file(fitted)
+}
+
+fn read_index(dir: &Path) -> Vec<(u64, PathBuf, PathBuf)> {
+    fs::read_to_string(index_path(dir))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            match fields[..] {
+                [time, original, copy] => Some((
+                    time.parse().ok()?,
+                    unescape_path(original),
+                    unescape_path(copy),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// `autosize restore [<file>] [--list] [--backup-dir <dir>]` puts back the
+// most recent backup of file (or of anything, with no file given)
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut file, mut list, mut dir) = (None, false, default_dir());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        match flag {
+            "--list" => list = true,
+            "--backup-dir" => {
+                dir = PathBuf::from(
+                    inline
+                        .or_else(|| args.next().cloned())
+                        .ok_or("--backup-dir expects a value")?,
+                )
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument '{}'", arg).into()),
+            _ => file = Some(PathBuf::from(arg)),
+        }
+    }
+    let mut entries = read_index(&dir);
+    // Match on the full path where possible, the given path otherwise
+    if let Some(file) = &file {
+        let wanted = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+        entries.retain(|(_, original, _)| *original == wanted || original.ends_with(file));
+    }
+    if list {
+        if entries.is_empty() {
+            println!("No backups found in {}", dir.display());
+        }
+        for (time, original, copy) in &entries {
+            println!(
+                "{}  {} <- {}",
+                history::format_time(*time),
+                original.display(),
+                copy.display()
+            );
+        }
+        return Ok(());
+    }
+    let (_, original, copy) = entries
+        .last()
+        .ok_or(format!("No backups to restore in {}", dir.display()))?;
+    fs::copy(copy, original)?;
+    // Drop the entry so repeated restores walk further back
+    let index = read_index(&dir)
+        .into_iter()
+        .filter(|(_, _, c)| c != copy)
+        .map(|(t, o, c)| format!("{}\t{}\t{}\n", t, escape_path(&o), escape_path(&c)))
+        .collect::<String>();
+    fs::write(index_path(&dir), index)?;
+    fs::remove_file(copy)?;
+    println!("Restored {} from {}", original.display(), copy.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::temp_path;
+
+    #[test]
+    fn index_paths_round_trip() {
+        for path in [
+            "/photos/a b.png",
+            "/100%/50%25.png",
+            "/tab\there/new\nline.png",
+        ] {
+            let escaped = escape_path(Path::new(path));
+            assert!(!escaped.contains(['\t', '\n']), "{:?}", escaped);
+            assert_eq!(unescape_path(&escaped), Path::new(path));
+        }
+        // Plain names are left readable
+        assert_eq!(
+            escape_path(Path::new("/photos/café.png")),
+            "/photos/café.png"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restores_names_that_are_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let (folder, dir) = (temp_path("restore", "d"), temp_path("backups", "d"));
+        fs::create_dir_all(&folder).unwrap();
+        let file = folder.join(OsStr::from_bytes(b"caf\xe9 %41.png"));
+        fs::write(&file, b"original").unwrap();
+        let copy = backup(&file, &dir).unwrap().unwrap();
+        assert_eq!(read_index(&dir)[0].1, fs::canonicalize(&file).unwrap());
+        assert_eq!(read_index(&dir)[0].2, copy);
+        fs::write(&file, b"fitted").unwrap();
+        run(&["--backup-dir".to_string(), dir.display().to_string()]).unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"original");
+        assert!(!copy.exists() && read_index(&dir).is_empty());
+        fs::remove_dir_all(&folder).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,922 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{Instant, UNIX_EPOCH},
+};
+
+use glob::Pattern;
+
+use crate::{
+    attrs, backup, budget, checksums, decode_ahead, fit_input, formats, history, jpeg, listed,
+    load_input,
+    messages::tr,
+    options::Options,
+    output_name, output_stem,
+    pipeline::Stage,
+    print_savings, progress, prompt_settings, record_history, tools, units,
+    usage::{Meter, Usage},
+    Decoded, Fit,
+};
+
+fn images_in(
+    dir: &Path,
+    recursive: bool,
+    opts: &Options,
+    seen: &mut HashSet<PathBuf>,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    // A symlink back up the tree would otherwise be followed forever
+    if !seen.insert(fs::canonicalize(dir)?) {
+        return Ok(Vec::new());
+    }
+    let mut entries = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|path| listed(path, opts))
+        .collect::<Vec<_>>();
+    entries.sort();
+    let mut files = Vec::new();
+    for path in entries {
+        if path.is_dir() && recursive {
+            files.extend(images_in(&path, recursive, opts, seen)?);
+        } else if path.is_file() && formats::readable(&path) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// Directories contribute every image inside them, sorted by name, and with
+// `recursive` the images in their subdirectories too. Each file comes with
+// the subdirectory it was found in, relative to the directory given
+pub fn collect_inputs(
+    inputs: &[PathBuf],
+    recursive: bool,
+    opts: &Options,
+) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    for input in inputs {
+        if input.is_dir() {
+            for file in images_in(input, recursive, opts, &mut seen)? {
+                let relative = file
+                    .parent()
+                    .and_then(|p| p.strip_prefix(input).ok())
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf();
+                files.push((file, relative));
+            }
+        } else {
+            files.push((input.clone(), PathBuf::new()));
+        }
+    }
+    Ok(files)
+}
+
+// EXIF sits near the start of a JPEG, in a segment of at most 64 KB
+const EXIF_SEARCH: u64 = 1 << 17;
+
+// `YYYY/MM` for when a photo was taken, from its EXIF, or else when the
+// file was last modified
+pub fn date_folder(file: &Path) -> PathBuf {
+    let mut head = Vec::new();
+    let taken = fs::File::open(file)
+        .and_then(|f| f.take(EXIF_SEARCH).read_to_end(&mut head))
+        .ok()
+        .and_then(|_| jpeg::exif_date(&head));
+    let (year, month) = taken.unwrap_or_else(|| {
+        let modified = fs::metadata(file)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        // `YYYY-MM-DD HH:MM`
+        let time = history::format_time(modified);
+        (
+            time[..4].parse().unwrap_or(1970),
+            time[5..7].parse().unwrap_or(1),
+        )
+    });
+    PathBuf::from(format!("{:04}", year)).join(format!("{:02}", month))
+}
+
+// Folder a target's results go in with several --targets, e.g. `8MB`
+fn target_folder(target: u64) -> String {
+    units::format_size(target).replace(' ', "")
+}
+
+// Status of files under --skip-under
+const SKIPPED: &str = "skipped, already small";
+
+// How one file of the batch went
+struct Row {
+    file: PathBuf,
+    // What it was fitted into, from --targets
+    target: u64,
+    original: u64,
+    // The written output and its size, if there is one
+    output: Option<PathBuf>,
+    size: Option<u64>,
+    scale: Option<f64>,
+    status: String,
+    // What each of the planner's steps saved
+    steps: Vec<String>,
+    // What fitting it cost, for the files that were fitted
+    usage: Option<Usage>,
+}
+
+impl Row {
+    fn reduction(&self) -> Option<f64> {
+        let size = self.size?;
+        (self.original > 0).then(|| (1.0 - size as f64 / self.original as f64) * 100.0)
+    }
+}
+
+// Fits one file and records it in the history, returning how it went and
+// the output's size
+pub fn fit_file(
+    file: &Path,
+    opts: &Options,
+    args: &[String],
+) -> Result<(Fit, u64), Box<dyn Error>> {
+    fit_decoded(file, None, opts, args).map(|(fit, size, _)| (fit, size))
+}
+
+// fit_file for a file that may have been decoded already, also returning
+// what fitting it cost
+fn fit_decoded(
+    file: &Path,
+    decoded: Option<Decoded>,
+    opts: &Options,
+    args: &[String],
+) -> Result<(Fit, u64, Option<Usage>), Box<dyn Error>> {
+    let meter = Meter::start();
+    progress::emit(
+        opts,
+        "file",
+        &[("input", file.display().to_string().into())],
+    );
+    let fitted = load_input(file, opts, decoded).and_then(|input| {
+        let now = Instant::now();
+        Ok((fit_input(&input, opts)?, now))
+    });
+    let (fit, now) = match fitted {
+        Ok(fitted) => fitted,
+        Err(e) => {
+            progress::emit(opts, "failed", &[("error", e.to_string().into())]);
+            return Err(e);
+        }
+    };
+    let millis = now.elapsed().as_millis();
+    let usage = meter.and_then(|m| m.usage());
+    let size = opts
+        .retry
+        .run(fit.output.display(), || fs::metadata(&fit.output))?
+        .len();
+    record_history(
+        &file.display().to_string(),
+        &fit.output.display().to_string(),
+        size,
+        opts,
+        fit.scale,
+        fit.encodes,
+        millis,
+        usage,
+        args,
+    );
+    progress::emit(
+        opts,
+        "written",
+        &[
+            ("output", fit.output.display().to_string().into()),
+            ("bytes", size.into()),
+            ("scale", fit.scale.into()),
+        ],
+    );
+    Ok((fit, size, usage))
+}
+
+// Lines the rows up under a header, with the totals underneath
+fn print_summary(rows: &[Row], several: bool) {
+    let table = rows
+        .iter()
+        .map(|row| {
+            [
+                row.file.display().to_string(),
+                units::format_size(row.target),
+                units::format_size(row.original),
+                row.size.map(units::format_size).unwrap_or_default(),
+                row.reduction()
+                    .map(|r| format!("{:.1}%", r))
+                    .unwrap_or_default(),
+                row.scale.map(|s| format!("{:.2}", s)).unwrap_or_default(),
+                row.usage
+                    .map(|u| format!("{:.2}s", u.cpu.as_secs_f64()))
+                    .unwrap_or_default(),
+                row.usage
+                    .and_then(|u| u.peak)
+                    .map(units::format_size)
+                    .unwrap_or_default(),
+                row.status.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        "File",
+        "Target",
+        "Original",
+        "New",
+        "Reduction",
+        "Scale",
+        "CPU",
+        "Peak",
+        "Status",
+    ];
+    let mut widths = header.map(str::len);
+    for cells in &table {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: &[String]| {
+        let padded = cells
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, width))| match i {
+                // Numbers read better right-aligned
+                1..=7 => format!("{:>w$}", cell, w = width),
+                _ => format!("{:<w$}", cell, w = width),
+            })
+            .collect::<Vec<_>>();
+        // The target column only says something with several targets
+        let padded = padded
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| several || i != 1)
+            .map(|(_, cell)| cell)
+            .collect::<Vec<_>>();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    println!();
+    line(&header.map(String::from));
+    line(&widths.map(|w| "-".repeat(w)));
+    for cells in &table {
+        line(cells);
+    }
+    let written = rows.iter().filter(|r| r.size.is_some()).collect::<Vec<_>>();
+    let (before, after) = written.iter().fold((0, 0), |(b, a), r| {
+        (b + r.original, a + r.size.unwrap_or(0))
+    });
+    let over = written
+        .iter()
+        .filter(|r| r.size.unwrap_or(0) > r.target)
+        .count();
+    let skipped = rows.iter().filter(|r| r.status == SKIPPED).count();
+    println!(
+        "\n{} files: {} written ({} over the target), {} skipped, {} failed",
+        rows.len(),
+        written.len() - skipped,
+        over,
+        skipped,
+        rows.len() - written.len()
+    );
+    if before > 0 {
+        println!(
+            "Total: {} -> {} ({:.1}% smaller)",
+            units::format_size(before),
+            units::format_size(after),
+            (1.0 - after as f64 / before as f64) * 100.0
+        );
+    }
+    let usages = rows.iter().filter_map(|r| r.usage).collect::<Vec<_>>();
+    if !usages.is_empty() {
+        let cpu = usages.iter().map(|u| u.cpu.as_secs_f64()).sum::<f64>();
+        match usages.iter().filter_map(|u| u.peak).max() {
+            Some(peak) => println!(
+                "CPU time: {:.2}s, peak memory: {} (the most any one file took)",
+                cpu,
+                units::format_size(peak)
+            ),
+            None => println!("CPU time: {:.2}s", cpu),
+        }
+    }
+}
+
+// Quotes a CSV field when it has anything that would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// One line per file, with sizes in plain bytes so spreadsheets can sum them
+fn write_report(path: &Path, rows: &[Row]) -> Result<(), Box<dyn Error>> {
+    let mut csv = String::from(
+        "file,output,original_bytes,new_bytes,reduction_percent,scale,status,steps,target_bytes,cpu_ms,peak_bytes\n",
+    );
+    for row in rows {
+        let fields = [
+            row.file.display().to_string(),
+            row.output
+                .as_ref()
+                .map(|o| o.display().to_string())
+                .unwrap_or_default(),
+            row.original.to_string(),
+            row.size.map(|s| s.to_string()).unwrap_or_default(),
+            row.reduction()
+                .map(|r| format!("{:.2}", r))
+                .unwrap_or_default(),
+            row.scale.map(|s| format!("{:.4}", s)).unwrap_or_default(),
+            row.status.clone(),
+            row.steps.join("; "),
+            row.target.to_string(),
+            row.usage
+                .map(|u| u.cpu.as_millis().to_string())
+                .unwrap_or_default(),
+            row.usage
+                .and_then(|u| u.peak)
+                .map(|p| p.to_string())
+                .unwrap_or_default(),
+        ];
+        let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    fs::write(path, csv)?;
+    Ok(())
+}
+
+// Patterns with a slash match the whole path, others just the file name, so
+// `thumb_*` works wherever the thumbnails are
+pub fn matches(pattern: &Pattern, file: &Path) -> bool {
+    if pattern.as_str().contains('/') {
+        pattern.matches_path(file)
+    } else {
+        file.file_name()
+            .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+    }
+}
+
+// For every file, the earlier file with exactly the same content, if any.
+// Files are only compared byte for byte when their size and CRC match
+fn find_duplicates(files: &[PathBuf]) -> Vec<Option<usize>> {
+    let mut seen = HashMap::<(u64, u32), Vec<usize>>::new();
+    let mut duplicates = vec![None; files.len()];
+    for (i, file) in files.iter().enumerate() {
+        let Ok(bytes) = fs::read(file) else {
+            continue;
+        };
+        let candidates = seen
+            .entry((bytes.len() as u64, crc32fast::hash(&bytes)))
+            .or_default();
+        duplicates[i] = candidates
+            .iter()
+            .copied()
+            .find(|&j| fs::read(&files[j]).is_ok_and(|other| other == bytes));
+        if duplicates[i].is_none() {
+            candidates.push(i);
+        }
+    }
+    duplicates
+}
+
+// Reuses the result already fitted for an identical file rather than
+// running the whole search again
+fn copy_result(file: &Path, fitted: &Path, opts: &Options) -> Result<PathBuf, Box<dyn Error>> {
+    let bytes = opts.retry.run(fitted.display(), || fs::read(fitted))?;
+    let ext = fitted.extension().unwrap_or_default();
+    let output = output_name(&output_stem(file, opts), &bytes, ext, opts);
+    backup::write_output(&output, &bytes, opts)?;
+    if opts.in_place {
+        backup::replace_in_place(file, &output, opts)?;
+        return Ok(file.to_path_buf());
+    }
+    if opts.preserve_attrs {
+        if let Err(e) = attrs::copy_all(file, &output) {
+            println!(
+                "Couldn't copy the file attributes of {}: {}",
+                file.display(),
+                e
+            );
+        }
+    }
+    Ok(output)
+}
+
+// Files under --skip-under are copied through byte for byte, or with
+// --in-place just left alone
+fn pass_through(file: &Path, opts: &Options) -> Result<PathBuf, Box<dyn Error>> {
+    if opts.in_place {
+        println!(
+            "Already under the --skip-under size, leaving {} as it is",
+            file.display()
+        );
+        return Ok(file.to_path_buf());
+    }
+    let bytes = opts.retry.run(file.display(), || fs::read(file))?;
+    let ext = file.extension().unwrap_or_default();
+    let output = output_name(&output_stem(file, opts), &bytes, ext, opts);
+    backup::write_output(&output, &bytes, opts)?;
+    if opts.preserve_attrs {
+        if let Err(e) = attrs::copy_all(file, &output) {
+            println!(
+                "Couldn't copy the file attributes of {}: {}",
+                file.display(),
+                e
+            );
+        }
+    }
+    println!(
+        "Already under the --skip-under size, copied it to {} untouched",
+        output.display()
+    );
+    Ok(output)
+}
+
+// `autosize batch <files or dirs>... [--report <results.csv>] [--include
+// <glob>] [--exclude <glob>]` fits every image into the target size one
+// after another, with the settings asked for once up front. Both filters can
+// be repeated, and a file has to match an include (if any) and no exclude.
+// Files smaller than --skip-under <size> aren't re-encoded at all.
+// Files with the same content as an earlier one get a copy of its result.
+// --targets 8MB,2MB fits every file into each of the sizes, with each
+// size's results in a folder of its own like `8MB/`.
+// --budget <manifest> gives files matching its patterns their own target
+// and format instead, see budget::load.
+// Directories are searched recursively, and with --output-dir their
+// structure is recreated there. A file that fails is recorded and the rest
+// carry on, unless --fail-fast. Any other flag is a normal option and
+// applies to every file
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut report, mut include, mut exclude) = (None, Vec::new(), Vec::new());
+    let (mut skip_under, mut fail_fast, mut targets) = (None, false, Vec::new());
+    let mut budget = Vec::new();
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--fail-fast" {
+            fail_fast = true;
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if ![
+            "--report",
+            "--include",
+            "--exclude",
+            "--skip-under",
+            "--targets",
+            "--budget",
+        ]
+        .contains(&flag)
+        {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = inline
+            .or_else(|| iter.next().cloned())
+            .ok_or(format!("{} expects a value", flag))?;
+        match flag {
+            "--report" => report = Some(PathBuf::from(value)),
+            "--budget" => budget = budget::load(Path::new(&value))?,
+            "--skip-under" => {
+                skip_under = Some(units::parse_size(&value).ok_or(format!(
+                    "--skip-under expects a size like 100k or 2MB, got '{}'",
+                    value
+                ))?)
+            }
+            "--targets" => {
+                targets = value
+                    .split(',')
+                    .map(|size| {
+                        units::parse_size(size.trim())
+                            .filter(|&size| size >= 128)
+                            .ok_or(format!(
+                                "--targets expects sizes of at least 128 B like 8MB,2MB, got '{}'",
+                                size
+                            ))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            _ => {
+                let pattern = Pattern::new(&value)
+                    .map_err(|e| format!("{} got an invalid pattern '{}': {}", flag, value, e))?;
+                match flag {
+                    "--include" => include.push(pattern),
+                    _ => exclude.push(pattern),
+                }
+            }
+        }
+    }
+    if !budget.is_empty() && !targets.is_empty() {
+        return Err("--budget and --targets don't go together".into());
+    }
+    let mut opts = Options::parse(&rest)?;
+    // With --targets there's no single target to ask for
+    if let Some(&first) = targets.first() {
+        opts.given_target = Some(first);
+    }
+    let files = collect_inputs(&opts.inputs, true, &opts)?;
+    if files.is_empty() {
+        return Err(
+            "Usage: autosize batch <files or directories>... [--report <results.csv>] [--include <glob>] [--exclude <glob>] [--skip-under <size>] [--targets <sizes>] [--budget <manifest>] [--fail-fast]".into(),
+        );
+    }
+    let files = files
+        .into_iter()
+        .filter(|(file, _)| {
+            (include.is_empty() || include.iter().any(|p| matches(p, file)))
+                && !exclude.iter().any(|p| matches(p, file))
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Err("No files match the --include/--exclude filters".into());
+    }
+    // Only ask for the GIF settings when there's a GIF to use them on
+    let (files, subdirs): (Vec<_>, Vec<_>) = files.into_iter().unzip();
+    let rules = files
+        .iter()
+        .zip(&subdirs)
+        .map(|(file, subdir)| budget::rule_for(&budget, file, subdir))
+        .collect::<Vec<_>>();
+    let animated = files
+        .iter()
+        .any(|f| f.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif")));
+    let duplicates = find_duplicates(&files);
+    prompt_settings(&mut opts, animated)?;
+    println!("{}", tr!(OneMoment));
+    let now = Instant::now();
+    let targets = match targets.is_empty() {
+        true => vec![opts.target],
+        false => targets,
+    };
+    // Decoding is a stage of its own, a file ahead of the fitting, so the
+    // cores the encoder leaves idle get the next file ready. Files that will
+    // likely be copied through or reused aren't worth decoding
+    let mut ahead = Stage::spawn(files.clone().into_iter().enumerate(), {
+        let (opts, duplicates) = (opts.clone(), duplicates.clone());
+        move |(i, file)| {
+            let small =
+                skip_under.is_some_and(|limit| fs::metadata(&file).is_ok_and(|m| m.len() < limit));
+            match duplicates[i].is_none() && !small {
+                true => decode_ahead(&file, &opts),
+                false => None,
+            }
+        }
+    });
+    let mut rows: Vec<Row> = Vec::new();
+    let mut stopped = None;
+    'files: for (i, file) in files.iter().enumerate() {
+        println!("\n[{}/{}] {}", i + 1, files.len(), file.display());
+        let original = fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+        let mut decoded = ahead.next().flatten();
+        for (t, &target) in targets.iter().enumerate() {
+            let (target, format) = match rules[i] {
+                Some(r) => {
+                    let rule = &budget[r];
+                    let format = rule.format.clone().or(opts.format.clone());
+                    println!(
+                        "{} from the budget ({})",
+                        units::format_size(rule.target),
+                        rule.pattern
+                    );
+                    (rule.target, format)
+                }
+                None => (target, opts.format.clone()),
+            };
+            let mut opts = Options {
+                target,
+                format,
+                ..opts.clone()
+            };
+            // Each target gets a folder of its own, e.g. out/8MB/
+            if targets.len() > 1 {
+                println!("{}:", units::format_size(target));
+                let base = opts.output_dir.take().unwrap_or_default();
+                opts.output_dir = Some(base.join(target_folder(target)));
+            }
+            // Nested inputs keep their place in the tree under --output-dir,
+            // unless they're sorted by date instead
+            if opts.by_date {
+                let base = opts.output_dir.take().unwrap_or_default();
+                opts.output_dir = Some(base.join(date_folder(file)));
+            } else if let Some(dir) = &mut opts.output_dir {
+                dir.push(&subdirs[i]);
+            }
+            let same_format = opts.format.as_ref().is_none_or(|format| {
+                file.extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case(format))
+            });
+            if skip_under.is_some_and(|limit| original < limit) && same_format {
+                let status = SKIPPED.to_string();
+                match pass_through(file, &opts) {
+                    Ok(output) => {
+                        rows.push(Row {
+                            file: file.clone(),
+                            target,
+                            original,
+                            output: Some(output),
+                            size: Some(original),
+                            scale: None,
+                            status,
+                            steps: Vec::new(),
+                            usage: None,
+                        });
+                        continue;
+                    }
+                    Err(e) => println!("Couldn't copy {} through: {}", file.display(), e),
+                }
+            }
+            // Only when the earlier file actually produced something to copy,
+            // for the same target
+            let earlier = duplicates[i]
+                .filter(|&j| rules[j] == rules[i])
+                .and_then(|j| {
+                    let j = j * targets.len() + t;
+                    Some((j, rows[j].output.clone()?))
+                });
+            if let Some((j, fitted)) = earlier {
+                let first = &rows[j];
+                let (scale, status, steps) = (
+                    first.scale,
+                    format!("duplicate of {}", first.file.display()),
+                    first.steps.clone(),
+                );
+                match copy_result(file, &fitted, &opts) {
+                    Ok(output) => {
+                        println!(
+                            "Same content as {}, reusing its result",
+                            rows[j].file.display()
+                        );
+                        let size = opts
+                            .retry
+                            .run(output.display(), || fs::metadata(&output))?
+                            .len();
+                        rows.push(Row {
+                            file: file.clone(),
+                            target,
+                            original,
+                            output: Some(output),
+                            size: Some(size),
+                            scale,
+                            status,
+                            steps,
+                            usage: None,
+                        });
+                        continue;
+                    }
+                    Err(e) => println!(
+                        "Couldn't reuse the result of {}: {}",
+                        rows[j].file.display(),
+                        e
+                    ),
+                }
+            }
+            // Each target starts from the same pixels, the last one can
+            // have them
+            let decoded = match t + 1 < targets.len() {
+                true => decoded.clone(),
+                false => decoded.take(),
+            };
+            let row = match fit_decoded(file, decoded, &opts, args) {
+                Ok((fit, size, usage)) => {
+                    print_savings(&fit.savings);
+                    Row {
+                        file: file.clone(),
+                        target,
+                        original,
+                        output: Some(fit.output),
+                        size: Some(size),
+                        scale: Some(fit.scale),
+                        status: if size > opts.target {
+                            "over target".to_string()
+                        } else {
+                            "ok".to_string()
+                        },
+                        steps: fit.savings,
+                        usage,
+                    }
+                }
+                Err(e) => {
+                    println!("Failed: {}", e);
+                    Row {
+                        file: file.clone(),
+                        target,
+                        original,
+                        output: None,
+                        size: None,
+                        scale: None,
+                        status: format!("failed: {}", e),
+                        steps: Vec::new(),
+                        usage: None,
+                    }
+                }
+            };
+            let failed = row.output.is_none();
+            rows.push(row);
+            if failed && fail_fast {
+                stopped = Some((i, file));
+                break 'files;
+            }
+        }
+    }
+    print_summary(&rows, targets.len() > 1 || !budget.is_empty());
+    if let Some(report) = report {
+        match write_report(&report, &rows) {
+            Ok(()) => println!("Wrote the results to {}", report.display()),
+            Err(e) => println!("Couldn't write the report {}: {}", report.display(), e),
+        }
+    }
+    if opts.checksums {
+        let outputs = rows
+            .iter()
+            .filter_map(|r| r.output.clone())
+            .collect::<Vec<_>>();
+        if !outputs.is_empty() {
+            match checksums::write(&outputs) {
+                Ok(manifest) => println!("Wrote the checksums to {}", manifest.display()),
+                Err(e) => println!("Couldn't write the checksums: {}", e),
+            }
+        }
+    }
+    println!("{}", tr!(FinishedIn, now.elapsed().as_millis()));
+    // A window per file would be too many, so it's the folder they went to
+    if opts.open {
+        let folder = opts.output_dir.clone().unwrap_or(PathBuf::from("."));
+        if let Err(e) = tools::open(&folder) {
+            println!("Couldn't open {}: {}", folder.display(), e);
+        }
+    }
+    match stopped {
+        Some((i, file)) => Err(format!(
+            "Stopped at {} because of --fail-fast, {} of {} files were done",
+            file.display(),
+            i,
+            files.len()
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tools::temp_path;
+
+    use super::*;
+
+    // A fresh folder with these files written into it, subfolders and all
+    fn folder(files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = temp_path("batch", "d");
+        fs::create_dir_all(&dir).unwrap();
+        for (name, bytes) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, bytes).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("photo.jpg"), "photo.jpg");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a, b.png"), "\"a, b.png\"");
+        assert_eq!(csv_field("say \"cheese\""), "\"say \"\"cheese\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn reports_have_a_row_per_file() {
+        let dir = folder(&[]);
+        let rows = [
+            Row {
+                file: PathBuf::from("a, b.png"),
+                target: 1_000,
+                original: 4_000,
+                output: Some(PathBuf::from("a, b_1KB.png")),
+                size: Some(1_000),
+                scale: Some(0.5),
+                status: "ok".into(),
+                steps: vec!["strip".into(), "downscale".into()],
+                usage: None,
+            },
+            Row {
+                file: PathBuf::from("broken.png"),
+                target: 1_000,
+                original: 10,
+                output: None,
+                size: None,
+                scale: None,
+                status: "failed: bad".into(),
+                steps: Vec::new(),
+                usage: None,
+            },
+        ];
+        let path = dir.join("report.csv");
+        write_report(&path, &rows).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("file,output,original_bytes,new_bytes"));
+        assert_eq!(
+            lines[1],
+            "\"a, b.png\",\"a, b_1KB.png\",4000,1000,75.00,0.5000,ok,strip; downscale,1000,,"
+        );
+        assert_eq!(lines[2], "broken.png,,10,,,,failed: bad,,1000,,");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn patterns_match_names_or_whole_paths() {
+        let pattern = |p: &str| Pattern::new(p).unwrap();
+        let file = Path::new("assets/icons/thumb_cat.png");
+        assert!(matches(&pattern("thumb_*"), file));
+        assert!(matches(&pattern("*.png"), file));
+        assert!(!matches(&pattern("*.jpg"), file));
+        // With a slash the directories have to match too
+        assert!(matches(&pattern("assets/icons/*"), file));
+        assert!(!matches(&pattern("icons/*"), file));
+        assert!(!matches(&pattern("assets/*.png/x"), file));
+    }
+
+    #[test]
+    fn duplicates_point_at_the_first_copy() {
+        let dir = folder(&[
+            ("a.png", b"same"),
+            ("b.png", b"diff"),
+            ("c.png", b"same"),
+            ("d.png", b"same"),
+            ("e.png", b"longer"),
+        ]);
+        let files = ["a.png", "b.png", "c.png", "missing.png", "d.png", "e.png"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            find_duplicates(&files),
+            vec![None, None, Some(0), None, Some(0), None]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn inputs_come_with_the_folder_they_were_found_in() {
+        let dir = folder(&[
+            ("b.png", b""),
+            ("a.jpg", b""),
+            ("notes.txt", b""),
+            ("sub/deeper/c.gif", b""),
+            ("sub/d.webp", b""),
+        ]);
+        let opts = Options::default();
+        let single = dir.join("notes.txt");
+        let found = collect_inputs(&[dir.clone(), single.clone()], true, &opts).unwrap();
+        let found = found
+            .iter()
+            .map(|(file, relative)| (file.strip_prefix(&dir).unwrap(), relative.as_path()))
+            .collect::<Vec<_>>();
+        let p = Path::new;
+        assert_eq!(
+            found,
+            vec![
+                (p("a.jpg"), p("")),
+                (p("b.png"), p("")),
+                (p("sub/d.webp"), p("sub")),
+                (p("sub/deeper/c.gif"), p("sub/deeper")),
+                // Named outright, so it's kept whatever it is
+                (p("notes.txt"), p("")),
+            ]
+        );
+        let shallow = collect_inputs(std::slice::from_ref(&dir), false, &opts).unwrap();
+        assert_eq!(shallow.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn small_files_pass_through_untouched() {
+        let dir = folder(&[("in/small.png", b"tiny png")]);
+        let opts = Options {
+            output_dir: Some(dir.join("out")),
+            quiet: true,
+            ..Options::default()
+        };
+        let output = pass_through(&dir.join("in/small.png"), &opts).unwrap();
+        assert_eq!(output, dir.join("out/small_8B.png"));
+        assert_eq!(fs::read(&output).unwrap(), b"tiny png");
+        // In place there's nothing to write
+        let opts = Options {
+            in_place: true,
+            ..opts
+        };
+        let file = dir.join("in/small.png");
+        assert_eq!(pass_through(&file, &opts).unwrap(), file);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
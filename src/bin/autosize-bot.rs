@@ -0,0 +1,156 @@
+// A Discord bot that replies to images over a size limit with a copy fitted
+// under it, e.g. for a server where most people can only upload 10 MB.
+// Built with `cargo build --release --features bot --bin autosize-bot`
+//
+//   DISCORD_TOKEN        the bot's token, required
+//   AUTOSIZE_BOT_LIMIT   attachments larger than this get fitted (10MB)
+//   AUTOSIZE_BOT_TARGET  size to fit them to (the limit)
+//   AUTOSIZE_BOT_JOBS    how many fits run at once (2)
+//
+// The bot needs the Message Content intent enabled, attachments are part of
+// the content as far as Discord is concerned
+
+use std::{env, error::Error, path::Path, process, sync::Arc};
+
+use autosize::{decode, fit, format_size, parse_size, FitOptions};
+use serenity::{
+    all::{CreateAttachment, CreateMessage},
+    async_trait,
+    model::channel::{Attachment, Message},
+    prelude::*,
+};
+use tokio::{sync::Semaphore, task};
+
+struct Handler {
+    limit: u64,
+    target: u64,
+    // Each fit keeps a few cores busy for a while, so only so many run at
+    // once and the rest wait their turn
+    jobs: Arc<Semaphore>,
+}
+
+// Formats the bot can write back out, by extension. Anything else is fitted
+// as a JPEG
+fn output_format(filename: &str) -> String {
+    let ext = Path::new(filename)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "png" | "webp" | "jpg" | "jpeg" => ext,
+        _ => "jpg".to_string(),
+    }
+}
+
+// Decoding and the search are plain blocking code, bytes in and bytes out
+fn shrink(bytes: &[u8], filename: &str, target: u64) -> Result<(Vec<u8>, String), String> {
+    let img = decode(bytes).map_err(|e| e.to_string())?;
+    let format = output_format(filename);
+    let options = FitOptions::new(target).format(&format).quiet(true);
+    let fitted = fit(&img, &options).map_err(|e| e.to_string())?;
+    if fitted.bytes.len() as u64 > target {
+        return Err(format!("couldn't get it under {}", format_size(target)));
+    }
+    let stem = Path::new(filename)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "image".to_string());
+    Ok((fitted.bytes, format!("{}_fitted.{}", stem, format)))
+}
+
+impl Handler {
+    async fn fit_attachment(&self, attachment: &Attachment) -> Result<CreateAttachment, String> {
+        let bytes = attachment.download().await.map_err(|e| e.to_string())?;
+        let _turn = self.jobs.acquire().await.map_err(|e| e.to_string())?;
+        let (filename, target) = (attachment.filename.clone(), self.target);
+        let (fitted, name) = task::spawn_blocking(move || shrink(&bytes, &filename, target))
+            .await
+            .map_err(|e| e.to_string())??;
+        Ok(CreateAttachment::bytes(fitted, name))
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, ctx: Context, msg: Message) {
+        if msg.author.bot {
+            return;
+        }
+        let oversized = msg.attachments.iter().filter(|a| {
+            a.size as u64 > self.limit
+                && a.content_type
+                    .as_deref()
+                    .is_some_and(|t| t.starts_with("image/"))
+        });
+        for attachment in oversized {
+            let reply = match self.fit_attachment(attachment).await {
+                Ok(file) => CreateMessage::new()
+                    .content(format!(
+                        "{} was {}, here it is under {}",
+                        attachment.filename,
+                        format_size(attachment.size as u64),
+                        format_size(self.target)
+                    ))
+                    .add_file(file),
+                Err(e) => CreateMessage::new()
+                    .content(format!("Couldn't fit {}: {}", attachment.filename, e)),
+            };
+            let reply = reply.reference_message(&msg);
+            if let Err(e) = msg.channel_id.send_message(&ctx.http, reply).await {
+                eprintln!("Couldn't reply in {}: {}", msg.channel_id, e);
+            }
+        }
+    }
+}
+
+fn size_var(name: &str, default: u64) -> Result<u64, String> {
+    match env::var(name) {
+        Ok(value) => {
+            parse_size(&value).ok_or(format!("{} expects a size like 8MB, got '{}'", name, value))
+        }
+        Err(_) => Ok(default),
+    }
+}
+
+async fn run() -> Result<(), Box<dyn Error>> {
+    let token = env::var("DISCORD_TOKEN").map_err(|_| "Set DISCORD_TOKEN to the bot's token")?;
+    let limit = size_var("AUTOSIZE_BOT_LIMIT", 10_000_000)?;
+    let target = size_var("AUTOSIZE_BOT_TARGET", limit)?;
+    let jobs = match env::var("AUTOSIZE_BOT_JOBS") {
+        Ok(jobs) => jobs
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or(format!(
+                "AUTOSIZE_BOT_JOBS expects a number, got '{}'",
+                jobs
+            ))?,
+        Err(_) => 2,
+    };
+    let intents = GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT;
+    let handler = Handler {
+        limit,
+        target,
+        jobs: Arc::new(Semaphore::new(jobs)),
+    };
+    let mut client = Client::builder(&token, intents)
+        .event_handler(handler)
+        .await?;
+    println!(
+        "Fitting images over {} to {}",
+        format_size(limit),
+        format_size(target)
+    );
+    client.start().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+use image::{imageops, DynamicImage};
+
+use crate::color;
+
+// Width the image is brought down to first. The hash only keeps a few
+// cosines' worth of it, so every pixel past this is wasted work
+const SIDE: u32 = 64;
+
+// Cosines along the longer side and the shorter one, the usual 4x3
+const LONG: usize = 4;
+const SHORT: usize = 3;
+
+const DIGITS: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn base83(value: usize, length: u32) -> String {
+    (1..=length)
+        .map(|i| DIGITS[value / 83usize.pow(length - i) % 83] as char)
+        .collect()
+}
+
+fn to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    match v <= 0.04045 {
+        true => v / 12.92,
+        false => ((v + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn to_srgb(value: f64) -> usize {
+    let v = value.clamp(0.0, 1.0);
+    let v = match v <= 0.0031308 {
+        true => v * 12.92,
+        false => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+    };
+    (v * 255.0 + 0.5) as usize
+}
+
+// The BlurHash (https://blurha.sh) of an image: a short string apps decode
+// into a blurry placeholder while the real image loads. Transparent parts
+// are put on the background colour first, BlurHash has no alpha
+pub fn encode(img: &DynamicImage, background: [u8; 3]) -> String {
+    let small = match img.width() > SIDE || img.height() > SIDE {
+        true => img.resize(SIDE, SIDE, imageops::FilterType::Triangle),
+        false => img.clone(),
+    };
+    let rgb = match small.color().has_alpha() {
+        true => color::flatten(&small, background).to_rgb8(),
+        false => small.to_rgb8(),
+    };
+    let (w, h) = rgb.dimensions();
+    let (nx, ny) = match w >= h {
+        true => (LONG, SHORT),
+        false => (SHORT, LONG),
+    };
+    let linear = rgb.pixels().map(|p| p.0.map(to_linear)).collect::<Vec<_>>();
+    // How much of each cosine there is, per channel
+    let mut factors = Vec::with_capacity(nx * ny);
+    for j in 0..ny {
+        for i in 0..nx {
+            let mut sum = [0.0; 3];
+            for y in 0..h {
+                let along_y = (PI * j as f64 * y as f64 / h as f64).cos();
+                for x in 0..w {
+                    let basis = (PI * i as f64 * x as f64 / w as f64).cos() * along_y;
+                    let pixel = linear[(y * w + x) as usize];
+                    for c in 0..3 {
+                        sum[c] += basis * pixel[c];
+                    }
+                }
+            }
+            let norm = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            factors.push(sum.map(|s| s * norm / (w * h) as f64));
+        }
+    }
+    let (dc, ac) = factors
+        .split_first()
+        .expect("there's always the one factor");
+    let mut hash = base83((nx - 1) + (ny - 1) * 9, 1);
+    let largest = ac.iter().flatten().fold(0.0f64, |m, v| m.max(v.abs()));
+    let (quantised, max) = match ac.is_empty() {
+        true => (0, 1.0),
+        false => {
+            let quantised = ((largest * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as usize;
+            (quantised, (quantised + 1) as f64 / 166.0)
+        }
+    };
+    hash.push_str(&base83(quantised, 1));
+    let [r, g, b] = dc.map(to_srgb);
+    hash.push_str(&base83((r << 16) + (g << 8) + b, 4));
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| {
+            let v = v / max;
+            let v = v.signum() * v.abs().sqrt();
+            (v * 9.0 + 9.5).floor().clamp(0.0, 18.0) as usize
+        });
+        hash.push_str(&base83(r * 19 * 19 + g * 19 + b, 2));
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    // A gradient with a red block in the top right corner
+    fn sample(w: u32, h: u32) -> RgbImage {
+        RgbImage::from_fn(w, h, |x, y| match x >= w / 2 && y < h / 2 {
+            true => Rgb([230, 40, 60]),
+            false => Rgb([(x * 255 / (w - 1)) as u8, (y * 255 / (h - 1)) as u8, 128]),
+        })
+    }
+
+    #[test]
+    fn matches_the_reference_encoder() {
+        // What the reference C encoder (woltapp/blurhash) gives for the
+        // same pixels, 4x3 cosines wide and 3x4 tall
+        let wide = DynamicImage::ImageRgb8(sample(32, 24));
+        assert_eq!(encode(&wide, [255; 3]), "L.I;z=2[j[xDupS6SNr?kla#a|j?");
+        let tall = DynamicImage::ImageRgb8(sample(20, 30));
+        assert_eq!(encode(&tall, [255; 3]), "T,I;z=2[W;upWGSNk+a#azjvafn%");
+    }
+
+    #[test]
+    fn transparency_is_put_on_the_background() {
+        let clear = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 0])));
+        let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([255; 3])));
+        assert_eq!(encode(&clear, [255; 3]), encode(&white, [255; 3]));
+    }
+
+    #[test]
+    fn base83_digits() {
+        assert_eq!(base83(0, 1), "0");
+        assert_eq!(base83(82, 1), "~");
+        assert_eq!(base83(83, 2), "10");
+        assert_eq!(base83(6869, 2), "~$");
+    }
+}
@@ -0,0 +1,91 @@
+use std::{error::Error, fs, path::Path};
+
+use glob::Pattern;
+
+use crate::{batch::matches, formats, units};
+
+// One line of a --budget manifest: files matching the pattern get their own
+// target, and format if it gives one
+pub struct Rule {
+    pub pattern: Pattern,
+    pub target: u64,
+    pub format: Option<String>,
+}
+
+// Reads a --budget manifest, one rule per line in any of these forms:
+//   hero_*.png, 300KB, webp
+//   icons/* -> 20KB png
+//   "banners/*" = "150KB jpg"
+// the last one so it can live in a TOML file. The format is optional.
+// Blank lines, lines starting with # or [ and a `pattern,target,format`
+// header are skipped. Rules are tried in order and the first match wins,
+// so specific patterns go before general ones
+pub fn load(path: &Path) -> Result<Vec<Rule>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read the budget {}: {}", path.display(), e))?;
+    let mut rules = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', '[']) || line.starts_with("pattern,") {
+            continue;
+        }
+        let bad = |why: String| format!("{} line {}: {}", path.display(), n + 1, why);
+        let quoted = line
+            .strip_prefix('"')
+            .and_then(|l| l.split_once('"'))
+            .and_then(|(pattern, rest)| Some((pattern, rest.trim().strip_prefix('=')?)));
+        let (pattern, rest) = match (quoted, line.split_once("->")) {
+            (Some((pattern, rest)), _) => (
+                pattern,
+                rest.trim()
+                    .trim_matches('"')
+                    .split_whitespace()
+                    .collect::<Vec<_>>(),
+            ),
+            (None, Some((pattern, rest))) => (pattern, rest.split_whitespace().collect()),
+            (None, None) => match line.split_once(',') {
+                Some((pattern, rest)) => (pattern, rest.split(',').map(str::trim).collect()),
+                None => return Err(bad("expected `pattern, target, format`".to_string()).into()),
+            },
+        };
+        let pattern = Pattern::new(pattern.trim())
+            .map_err(|e| bad(format!("invalid pattern '{}': {}", pattern.trim(), e)))?;
+        let (target, format) = match rest.as_slice() {
+            [target] => (target, None),
+            [target, format] if !format.is_empty() => (target, Some(format)),
+            [target, _] => (target, None),
+            _ => return Err(bad("expected a target and at most a format".to_string()).into()),
+        };
+        let target = units::parse_size(target)
+            .filter(|&size| size >= 128)
+            .ok_or_else(|| {
+                bad(format!(
+                    "expected a size of at least 128 B, got '{}'",
+                    target
+                ))
+            })?;
+        let format = format.map(|f| f.trim_start_matches('.').to_ascii_lowercase());
+        if let Some(format) = &format {
+            formats::check_write(format).map_err(|e| bad(e.to_string()))?;
+        }
+        rules.push(Rule {
+            pattern,
+            target,
+            format,
+        });
+    }
+    if rules.is_empty() {
+        return Err(format!("The budget {} has no rules", path.display()).into());
+    }
+    Ok(rules)
+}
+
+// The first rule for a file found in subdir of the directory it was given
+// as. Patterns with a / can also match from that directory, so `icons/*`
+// works for `autosize batch assets`
+pub fn rule_for(rules: &[Rule], file: &Path, subdir: &Path) -> Option<usize> {
+    let relative = subdir.join(file.file_name().unwrap_or_default());
+    rules
+        .iter()
+        .position(|r| matches(&r.pattern, file) || matches(&r.pattern, &relative))
+}
@@ -0,0 +1,105 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use crate::{
+    batch::{collect_inputs, fit_file},
+    budget,
+    options::Options,
+    units,
+};
+
+// `autosize check --manifest <budgets> [<files or directories>...] [--fix]`:
+// whether every image the budget has a rule for is within its target, for
+// gating a repository in CI. Offenders are listed and the run fails if
+// there are any. With --fix they're fitted in place to their target first
+// (backed up like any --in-place run), and only the ones that still don't
+// fit fail it. Only sizes are checked, a rule's format is for batch
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut manifest, mut fix) = (None, false);
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.split_once('=') {
+            Some(("--manifest", value)) => manifest = Some(PathBuf::from(value)),
+            _ if arg == "--manifest" => {
+                let value = iter.next().ok_or("--manifest expects a value")?;
+                manifest = Some(PathBuf::from(value));
+            }
+            _ if arg == "--fix" => fix = true,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let manifest = manifest
+        .ok_or("Usage: autosize check --manifest <budgets> [<files or directories>...] [--fix]")?;
+    let rules = budget::load(&manifest)?;
+    let mut opts = Options::parse(&rest)?;
+    if opts.inputs.is_empty() {
+        opts.inputs.push(PathBuf::from("."));
+    }
+    // A repository's .git, and the backups --fix leaves in .autosize
+    opts.skip_hidden = true;
+    let files = collect_inputs(&opts.inputs, true, &opts)?;
+    let (mut checked, mut offenders) = (0, Vec::new());
+    for (file, subdir) in &files {
+        let Some(r) = budget::rule_for(&rules, file, subdir) else {
+            continue;
+        };
+        checked += 1;
+        let size = fs::metadata(file)?.len();
+        let target = rules[r].target;
+        if size > target {
+            println!(
+                "{}: {}, over its {} budget by {} ({})",
+                file.display(),
+                units::format_size(size),
+                units::format_size(target),
+                units::format_size(size - target),
+                rules[r].pattern
+            );
+            offenders.push((file.clone(), target));
+        }
+    }
+    if !fix || offenders.is_empty() {
+        println!(
+            "\n{} of {} budgeted files over their budget",
+            offenders.len(),
+            checked
+        );
+        return match offenders.len() {
+            0 => Ok(()),
+            n => Err(format!("{} file(s) over their budget", n).into()),
+        };
+    }
+    println!("\nFixing {} file(s)...", offenders.len());
+    let mut left = 0;
+    for (file, target) in &offenders {
+        println!("\n{}", file.display());
+        let opts = Options {
+            target: *target,
+            given_target: Some(*target),
+            in_place: true,
+            ..opts.clone()
+        };
+        match fit_file(file, &opts, args) {
+            Ok((_, size)) if size <= *target => {
+                println!("Fixed, now {}", units::format_size(size))
+            }
+            Ok((_, size)) => {
+                println!("Still over, now {}", units::format_size(size));
+                left += 1;
+            }
+            Err(e) => {
+                println!("Couldn't fix it: {}", e);
+                left += 1;
+            }
+        }
+    }
+    println!(
+        "\n{} of {} offenders fixed",
+        offenders.len() - left,
+        offenders.len()
+    );
+    match left {
+        0 => Ok(()),
+        n => Err(format!("{} file(s) still over their budget", n).into()),
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+pub const MANIFEST: &str = "SHA256SUMS";
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+// The deepest folder every output is in
+fn common_dir(outputs: &[PathBuf]) -> PathBuf {
+    let mut dirs = outputs
+        .iter()
+        .map(|o| o.parent().unwrap_or(Path::new("")).to_path_buf());
+    let mut common = dirs.next().unwrap_or_default();
+    for dir in dirs {
+        while !dir.starts_with(&common) {
+            if !common.pop() {
+                break;
+            }
+        }
+    }
+    common
+}
+
+// Writes a SHA256SUMS next to the outputs, in their deepest common folder,
+// in the format `sha256sum -c` reads, so a pipeline can check the files
+// arrived intact after copying them to a CDN. Entries already in the
+// manifest are kept, and replaced when the same file was written again, so
+// runs into the same folder build up one manifest. Returns where it is
+pub fn write(outputs: &[PathBuf]) -> io::Result<PathBuf> {
+    let dir = common_dir(outputs);
+    let manifest = dir.join(MANIFEST);
+    let mut entries = BTreeMap::new();
+    if let Ok(existing) = fs::read_to_string(&manifest) {
+        for line in existing.lines() {
+            if let Some((sum, name)) = line.split_once("  ") {
+                entries.insert(name.to_string(), sum.to_string());
+            }
+        }
+    }
+    for output in outputs {
+        let name = output.strip_prefix(&dir).unwrap_or(output);
+        // Forward slashes on every platform, the way sha256sum writes them
+        let name = name
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        entries.insert(name, sha256(output)?);
+    }
+    let text = entries
+        .iter()
+        .map(|(name, sum)| format!("{}  {}\n", sum, name))
+        .collect::<String>();
+    fs::write(&manifest, text)?;
+    Ok(manifest)
+}
@@ -0,0 +1,214 @@
+use std::{error::Error, fs::File, io::BufReader, path::Path};
+
+use image::{
+    codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder},
+    DynamicImage, ImageDecoder, ImageFormat, RgbImage,
+};
+use qcms::{DataType, Intent, Profile, Transform};
+
+// The embedded ICC profile, for the formats that can carry one
+pub fn read_icc(path: &Path) -> Option<Vec<u8>> {
+    let format = ImageFormat::from_path(path).ok()?;
+    let reader = BufReader::new(File::open(path).ok()?);
+    match format {
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+// Converts pixels described by an ICC profile (Adobe RGB, Display P3, ...)
+// to sRGB. Encoders drop the profile, and viewers then assume sRGB, so
+// without this wide-gamut inputs come out washed out. Returns None when
+// the profile is already sRGB
+pub fn to_srgb(img: &DynamicImage, icc: &[u8]) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    let input = Profile::new_from_slice(icc, false).ok_or("Couldn't read the ICC profile")?;
+    if input.is_sRGB() {
+        return Ok(None);
+    }
+    let mut srgb = Profile::new_sRGB();
+    srgb.precache_output_transform();
+    let transform = |ty| {
+        Transform::new(&input, &srgb, ty, Intent::Perceptual)
+            .ok_or("Unsupported ICC profile, only RGB profiles can be converted")
+    };
+    // Don't add an alpha channel the input didn't have
+    if img.color().has_alpha() {
+        let mut rgba = img.to_rgba8();
+        transform(DataType::RGBA8)?.apply(&mut rgba);
+        Ok(Some(DynamicImage::ImageRgba8(rgba)))
+    } else {
+        let mut rgb = img.to_rgb8();
+        transform(DataType::RGB8)?.apply(&mut rgb);
+        Ok(Some(DynamicImage::ImageRgb8(rgb)))
+    }
+}
+
+// Accepts `#RRGGBB`, `RRGGBB` and the short `#RGB`
+pub fn parse_hex(value: &str) -> Option<[u8; 3]> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let digits = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()?;
+    match digits[..] {
+        [r, g, b] => Some([r * 17, g * 17, b * 17]),
+        [r1, r0, g1, g0, b1, b0] => Some([r1 * 16 + r0, g1 * 16 + g0, b1 * 16 + b0]),
+        _ => None,
+    }
+}
+
+// Alpha-composites the image over a solid colour
+pub fn flatten(img: &DynamicImage, background: [u8; 3]) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    DynamicImage::ImageRgb8(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        let a = p[3] as u32;
+        image::Rgb(
+            [0, 1, 2]
+                .map(|c| ((p[c] as u32 * a + background[c] as u32 * (255 - a) + 127) / 255) as u8),
+        )
+    }))
+}
+
+// sRGB to CIELAB, against the D65 white sRGB is defined with
+pub fn lab(rgb: [u8; 3]) -> [f64; 3] {
+    let linear = rgb.map(|v| {
+        let c = v as f64 / 255.0;
+        match c <= 0.04045 {
+            true => c / 12.92,
+            false => ((c + 0.055) / 1.055).powf(2.4),
+        }
+    });
+    let [r, g, b] = linear;
+    let xyz = [
+        (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883,
+    ];
+    let [fx, fy, fz] = xyz.map(|t| match t > (6.0f64 / 29.0).powi(3) {
+        true => t.cbrt(),
+        false => t / (3.0 * (6.0f64 / 29.0).powi(2)) + 4.0 / 29.0,
+    });
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+// CIEDE2000 colour difference between two CIELAB colours, after Sharma, Wu
+// and Dalal's notes on implementing it. Unlike distances in RGB, equal
+// values look about equally different anywhere in the gamut; 1 is roughly
+// the smallest difference anyone notices side by side
+pub fn ciede2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let ([l1, a1, b1], [l2, a2, b2]) = (lab1, lab2);
+    let pow25_7 = 25f64.powi(7);
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    // Hue angles in degrees, 0 for greys
+    let hue = |b: f64, a: f64| match b == 0.0 && a == 0.0 {
+        true => 0.0,
+        false => b.atan2(a).to_degrees().rem_euclid(360.0),
+    };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+    let grey = c1 * c2 == 0.0;
+    let dh = match h2 - h1 {
+        _ if grey => 0.0,
+        d if d > 180.0 => d - 360.0,
+        d if d < -180.0 => d + 360.0,
+        d => d,
+    };
+    let (dl, dc) = (l2 - l1, c2 - c1);
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+    let (l_bar, c_bar) = ((l1 + l2) / 2.0, (c1 + c2) / 2.0);
+    let h_bar = match (h1 - h2).abs() {
+        _ if grey => h1 + h2,
+        d if d <= 180.0 => (h1 + h2) / 2.0,
+        _ if h1 + h2 < 360.0 => (h1 + h2 + 360.0) / 2.0,
+        _ => (h1 + h2 - 360.0) / 2.0,
+    };
+    let cos = |deg: f64| deg.to_radians().cos();
+    let t =
+        1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+            - 0.20 * cos(4.0 * h_bar - 63.0);
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let rc = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt();
+    let sl = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let sc = 1.0 + 0.045 * c_bar;
+    let sh = 1.0 + 0.015 * c_bar * t;
+    let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+    let (l, c, h) = (dl / sl, dc / sc, dh / sh);
+    (l * l + c * c + h * h + rt * c * h).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pairs from the test data in Sharma, Wu and Dalal's paper, which cover
+    // the hue wrap-around and mean-hue cases that are easy to get wrong
+    const SHARMA: &[([f64; 3], [f64; 3], f64)] = &[
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0011], 7.2195),
+        ([50.0, -0.001, 2.49], [50.0, 0.0009, -2.49], 4.8045),
+        ([50.0, -0.001, 2.49], [50.0, 0.0011, -2.49], 4.7461),
+        ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+        ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+        ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+        ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+        (
+            [60.2574, -34.0099, 36.2677],
+            [60.4626, -34.1751, 39.4387],
+            1.2644,
+        ),
+        (
+            [63.0109, -31.0961, -5.8663],
+            [62.8187, -29.7946, -4.0864],
+            1.2630,
+        ),
+        (
+            [22.7233, 20.0904, -46.6940],
+            [23.0331, 14.9730, -42.5619],
+            2.0373,
+        ),
+        (
+            [90.9257, -0.5406, -0.9208],
+            [88.6381, -0.8985, -0.7239],
+            1.5381,
+        ),
+        (
+            [6.7747, -0.2908, -2.4247],
+            [5.8714, -0.0985, -2.2286],
+            0.6377,
+        ),
+        (
+            [2.0776, 0.0795, -1.1350],
+            [0.9033, -0.0636, -0.5514],
+            0.9082,
+        ),
+    ];
+
+    #[test]
+    fn ciede2000_matches_sharmas_test_data() {
+        for &(lab1, lab2, want) in SHARMA {
+            let got = ciede2000(lab1, lab2);
+            assert!(
+                (got - want).abs() < 1e-4,
+                "{:?} to {:?}: {} instead of {}",
+                lab1,
+                lab2,
+                got,
+                want
+            );
+            assert!((ciede2000(lab2, lab1) - got).abs() < 1e-9);
+        }
+        assert_eq!(ciede2000([40.0, 10.0, -5.0], [40.0, 10.0, -5.0]), 0.0);
+    }
+}
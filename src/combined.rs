@@ -0,0 +1,329 @@
+use std::{error::Error, fs, path::PathBuf, time::Instant};
+
+use rayon::prelude::*;
+
+use crate::{
+    batch::collect_inputs, compare, fit_input, load_input, messages::tr, options::Options, plan,
+    profile, prompt_settings, units, Input,
+};
+
+// Sizes each image is tried at to learn how its quality grows with bytes,
+// spread evenly in log size from the floor to the most it could be given
+const PROBES: usize = 4;
+
+// The bytes above the floors are handed out in this many pieces per image
+const STEPS: u64 = 32;
+
+// Rounds of measuring each image at the share it was given and handing
+// the bytes out again. A few probes can miss a sudden jump, like a palette
+// PNG turning lossless, and the measurements fill that in
+const REFINE: usize = 2;
+
+// How an image's SSIM grows with its size, from the probes: (bytes, SSIM)
+// pairs sorted by bytes. Between them it's interpolated in log size, past
+// the last it stays put (the image was already at full size)
+struct Curve(Vec<(u64, f64)>);
+
+impl Curve {
+    fn add(&mut self, point: (u64, f64)) {
+        let at = self.0.partition_point(|&(b, _)| b < point.0);
+        match self.0.get(at) {
+            Some(&(b, _)) if b == point.0 => (),
+            _ => self.0.insert(at, point),
+        }
+    }
+
+    fn at(&self, bytes: u64) -> f64 {
+        let points = &self.0;
+        let after = points.partition_point(|&(b, _)| b <= bytes);
+        match after {
+            0 => points[0].1,
+            n if n == points.len() => points[n - 1].1,
+            n => {
+                let ((b0, s0), (b1, s1)) = (points[n - 1], points[n]);
+                let t = (bytes as f64 / b0 as f64).ln() / (b1 as f64 / b0 as f64).ln();
+                s0 + (s1 - s0) * t
+            }
+        }
+    }
+}
+
+// Fits an image quietly, written nowhere, in the format and with the
+// settings the real fit will use
+struct Probe<'a> {
+    input: &'a Input,
+    ext: String,
+    opts: Options,
+}
+
+impl Probe<'_> {
+    fn new<'a>(input: &'a Input, opts: &Options) -> Probe<'a> {
+        let mut opts = Options {
+            quiet: true,
+            progress_json: false,
+            ..input.working(opts)
+        };
+        let ext = match profile::choose(&opts, &input.image) {
+            Some(profile) => {
+                profile.apply(&mut opts, &input.in_ext, &input.ext, &input.image, false)
+            }
+            None => input.ext.clone(),
+        };
+        Probe { input, ext, opts }
+    }
+
+    // The size the fit came to and its SSIM. A fit that fails, like one
+    // held under --min-ssim, counts as the worst quality at that size, so
+    // the image is given more instead of the whole run stopping
+    fn measure(&self, size: u64) -> (u64, f64) {
+        let opts = Options {
+            target: size,
+            ..self.opts.clone()
+        };
+        let image = &self.input.image;
+        plan::fit_still(image, &self.ext, None, &opts)
+            .ok()
+            .and_then(|plan| {
+                let ssim = compare::encoded_ssim(image, &plan.bytes)?;
+                Some((plan.bytes.len() as u64, ssim))
+            })
+            .unwrap_or((size, 0.0))
+    }
+
+    fn curve(&self, sizes: &[u64]) -> Curve {
+        let mut curve = Curve(Vec::new());
+        for &size in sizes {
+            curve.add(self.measure(size));
+        }
+        curve
+    }
+}
+
+// Hands out total bytes, floor to each image first, then the rest a piece
+// at a time to whichever image looks worst at what it has so far. That
+// evens out quality rather than bytes: a flat screenshot stops getting more
+// once more wouldn't show, and the busy photo next to it gets the rest.
+// Images that have stopped improving are passed over, so some of the total
+// can go unused
+fn allocate(curves: &[Curve], total: u64, floor: u64) -> Vec<u64> {
+    let mut sizes = vec![floor; curves.len()];
+    let mut left = total - floor * curves.len() as u64;
+    let step = (left / (STEPS * curves.len() as u64)).max(1);
+    while left > 0 {
+        let step = step.min(left);
+        let worst = (0..curves.len())
+            .filter(|&i| curves[i].at(sizes[i] + step) > curves[i].at(sizes[i]))
+            .min_by(|&a, &b| curves[a].at(sizes[a]).total_cmp(&curves[b].at(sizes[b])));
+        let Some(worst) = worst else {
+            break;
+        };
+        sizes[worst] += step;
+        left -= step;
+    }
+    sizes
+}
+
+// `autosize combined <files or dirs>... --total <size> [--floor <size>]`
+// fits several images into one combined size, for posts that take a few
+// attachments under a shared limit. Each image gets at least the floor (a
+// quarter of an even share by default), and the rest is spread so they end
+// up at similar SSIM, see allocate. Any other flag is a normal option
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let usage =
+        "Usage: autosize combined <files or directories>... --total <size> [--floor <size>]";
+    let (mut total, mut floor) = (None, None);
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if flag != "--total" && flag != "--floor" {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = inline
+            .or_else(|| args.next().cloned())
+            .ok_or(format!("{} expects a value", flag))?;
+        let size = units::parse_size(&value)
+            .filter(|&size| size >= 128)
+            .ok_or(format!(
+                "{} expects a size of at least 128 B like 10MB, got '{}'",
+                flag, value
+            ))?;
+        match flag {
+            "--total" => total = Some(size),
+            _ => floor = Some(size),
+        }
+    }
+    let total = total.ok_or(usage)?;
+    let mut opts = Options::parse(&rest)?;
+    let files = collect_inputs(&opts.inputs, false, &opts)?
+        .into_iter()
+        .map(|(file, _)| file)
+        .collect::<Vec<PathBuf>>();
+    if files.is_empty() {
+        return Err(usage.into());
+    }
+    let n = files.len() as u64;
+    let floor = floor.unwrap_or((total / n / 4).max(128));
+    if floor * n > total {
+        return Err(format!(
+            "{} images at a floor of {} need {}, more than the total of {}",
+            n,
+            units::format_size(floor),
+            units::format_size(floor * n),
+            units::format_size(total)
+        )
+        .into());
+    }
+    // The total stands in for the target, so nothing is asked for it
+    opts.given_target = Some(total);
+    prompt_settings(&mut opts, false)?;
+    let inputs = files
+        .iter()
+        .map(|file| {
+            let input = load_input(file, &opts, None)?;
+            match input.anim {
+                Some(_) => {
+                    Err(format!("{} is animated, combined only fits stills", file.display()).into())
+                }
+                None => Ok(input),
+            }
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    println!("{}", tr!(OneMoment));
+    let now = Instant::now();
+    let most = total - floor * (n - 1);
+    let sizes = (0..PROBES)
+        .map(|i| {
+            let t = i as f64 / (PROBES - 1) as f64;
+            (floor as f64 * (most as f64 / floor as f64).powf(t)) as u64
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "Trying each image at {} sizes from {} to {}",
+        PROBES,
+        units::format_size(floor),
+        units::format_size(most)
+    );
+    let probes = inputs
+        .iter()
+        .map(|input| Probe::new(input, &opts))
+        .collect::<Vec<_>>();
+    let mut curves = probes
+        .par_iter()
+        .map(|probe| probe.curve(&sizes))
+        .collect::<Vec<_>>();
+    let mut shares = allocate(&curves, total, floor);
+    for _ in 0..REFINE {
+        let measured = probes
+            .par_iter()
+            .zip(&shares)
+            .map(|(probe, &share)| probe.measure(share))
+            .collect::<Vec<_>>();
+        for (curve, point) in curves.iter_mut().zip(measured) {
+            curve.add(point);
+        }
+        let again = allocate(&curves, total, floor);
+        if again == shares {
+            break;
+        }
+        shares = again;
+    }
+    let mut written = 0;
+    println!();
+    for ((input, curve), share) in inputs.iter().zip(&curves).zip(shares) {
+        let opts = Options {
+            target: share,
+            quiet: true,
+            ..opts.clone()
+        };
+        let fit =
+            fit_input(input, &opts).map_err(|e| format!("{}: {}", input.path.display(), e))?;
+        let bytes = fs::read(&fit.output)?;
+        written += bytes.len() as u64;
+        let ssim = compare::encoded_ssim(&input.image, &bytes).unwrap_or(curve.at(share));
+        println!(
+            "{}: {} of {} given, SSIM {:.4}, scale {:.2} -> {}",
+            input.path.display(),
+            units::format_size(bytes.len() as u64),
+            units::format_size(share),
+            ssim,
+            fit.scale,
+            fit.output.display()
+        );
+    }
+    println!(
+        "\n{} of {} used in {}ms",
+        units::format_size(written),
+        units::format_size(total),
+        now.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_interpolate_in_log_size() {
+        let curve = Curve(vec![(1_000, 0.5), (4_000, 0.9)]);
+        assert_eq!(curve.at(1_000), 0.5);
+        assert_eq!(curve.at(4_000), 0.9);
+        // Halfway in log size is twice the bytes, not halfway between
+        assert!((curve.at(2_000) - 0.7).abs() < 1e-9);
+        assert!(curve.at(2_500) < 0.9 && curve.at(2_500) > 0.7);
+        // Flat either side of the points
+        assert_eq!(curve.at(10), 0.5);
+        assert_eq!(curve.at(1_000_000), 0.9);
+    }
+
+    #[test]
+    fn adding_keeps_points_sorted_and_unique() {
+        let mut curve = Curve(Vec::new());
+        for point in [(300, 0.8), (100, 0.2), (200, 0.5), (200, 0.6)] {
+            curve.add(point);
+        }
+        assert_eq!(curve.0, vec![(100, 0.2), (200, 0.5), (300, 0.8)]);
+    }
+
+    #[test]
+    fn flat_curves_stop_getting_bytes() {
+        // Already at full quality from the floor on, next to a photo that
+        // keeps improving
+        let flat = Curve(vec![(1_000, 1.0), (50_000, 1.0)]);
+        let photo = Curve(vec![(1_000, 0.3), (100_000, 0.95)]);
+        let shares = allocate(&[flat, photo], 60_000, 1_000);
+        assert_eq!(shares[0], 1_000);
+        assert_eq!(shares[1], 59_000);
+        // Nothing improves, so everything stays at the floor
+        let shares = allocate(&[Curve(vec![(1_000, 0.9)])], 60_000, 1_000);
+        assert_eq!(shares, vec![1_000]);
+    }
+
+    #[test]
+    fn shares_even_out_quality_within_the_total() {
+        let curves = [
+            Curve(vec![(500, 0.2), (5_000, 0.9), (20_000, 0.99)]),
+            Curve(vec![(500, 0.6), (5_000, 0.95), (20_000, 0.99)]),
+            Curve(vec![(500, 0.1), (50_000, 0.9)]),
+        ];
+        for total in [1_500, 1_501, 7_777, 30_000, 123_456] {
+            let shares = allocate(&curves, total, 500);
+            assert!(shares.iter().sum::<u64>() <= total, "{:?}", shares);
+            assert!(shares.iter().all(|&share| share >= 500));
+        }
+        let shares = allocate(&curves, 30_000, 500);
+        let ssims = curves
+            .iter()
+            .zip(&shares)
+            .map(|(curve, &share)| curve.at(share))
+            .collect::<Vec<_>>();
+        let spread =
+            ssims.iter().cloned().fold(0.0, f64::max) - ssims.iter().cloned().fold(1.0, f64::min);
+        assert!(spread < 0.05, "{:?} from {:?}", ssims, shares);
+    }
+}
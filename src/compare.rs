@@ -0,0 +1,293 @@
+use std::{error::Error, fs};
+
+use image::{imageops, DynamicImage, GrayImage, Rgb, RgbImage, RgbaImage};
+
+use rayon::prelude::*;
+
+use crate::{color, units};
+
+// SSIM stabilising constants for 8-bit samples
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+const SSIM_WINDOW: u32 = 8;
+
+// PSNR over the RGB channels, infinite when the images are identical
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let mut sum = 0.0;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        for c in 0..3 {
+            let d = pa[c] as f64 - pb[c] as f64;
+            sum += d * d;
+        }
+    }
+    let mse = sum / (a.width() as f64 * a.height() as f64 * 3.0);
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+fn luma(img: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        let l = 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64;
+        image::Luma([l.round() as u8])
+    })
+}
+
+// Mean SSIM of the luma channel over non-overlapping 8x8 windows
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    let (la, lb) = (luma(a), luma(b));
+    let (w, h) = (a.width(), a.height());
+    let (mut total, mut windows) = (0.0, 0);
+    for wy in (0..h).step_by(SSIM_WINDOW as usize) {
+        for wx in (0..w).step_by(SSIM_WINDOW as usize) {
+            let (ww, wh) = (SSIM_WINDOW.min(w - wx), SSIM_WINDOW.min(h - wy));
+            let n = (ww * wh) as f64;
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in wy..wy + wh {
+                for x in wx..wx + ww {
+                    let (va, vb) = (la.get_pixel(x, y)[0] as f64, lb.get_pixel(x, y)[0] as f64);
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let (va, vb) = (saa / n - ma * ma, sbb / n - mb * mb);
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+// SSIM of an encoded result against the image it was made from, scaled
+// back up to the original's size the way it would be viewed. None when the
+// bytes aren't something the image crate decodes
+pub fn encoded_ssim(original: &DynamicImage, bytes: &[u8]) -> Option<f64> {
+    let decoded = image::load_from_memory(bytes).ok()?.to_rgba8();
+    let (w, h) = (original.width(), original.height());
+    let decoded = if decoded.dimensions() != (w, h) {
+        imageops::resize(&decoded, w, h, imageops::FilterType::Lanczos3)
+    } else {
+        decoded
+    };
+    Some(ssim(&original.to_rgba8(), &decoded))
+}
+
+// Bins per channel of a colour histogram, centred on 0, 255 and the levels
+// 255/7 apart between
+const HISTOGRAM_BINS: usize = 8;
+
+// Colour histogram of the visible pixels, as shares that add up to 1. Each
+// pixel is spread over the two nearest bins of each channel by how close it
+// is to them, so a colour nudged across a bin edge only moves a little
+// weight, and only colours that really shift count
+pub fn histogram(img: &RgbaImage) -> Vec<f64> {
+    let n = HISTOGRAM_BINS;
+    let mut bins = vec![0.0; n * n * n];
+    let mut total = 0.0;
+    for p in img.pixels().filter(|p| p[3] > 0) {
+        let weight = p[3] as f64 / 255.0;
+        let split = |v: u8| {
+            let x = (v as f64 / 255.0 * (n - 1) as f64).clamp(0.0, (n - 1) as f64);
+            let lo = (x.floor() as usize).min(n - 2);
+            [(lo, 1.0 - (x - lo as f64)), (lo + 1, x - lo as f64)]
+        };
+        let (r, g, b) = (split(p[0]), split(p[1]), split(p[2]));
+        for (ri, rw) in r {
+            for (gi, gw) in g {
+                for (bi, bw) in b {
+                    bins[(ri * n + gi) * n + bi] += weight * rw * gw * bw;
+                }
+            }
+        }
+        total += weight;
+    }
+    if total > 0.0 {
+        bins.iter_mut().for_each(|b| *b /= total);
+    }
+    bins
+}
+
+// The share of the histogram that moved between two images, from 0 for the
+// same colours in the same amounts to 1 for nothing in common. Sizes don't
+// matter, so it works on candidates at any scale
+pub fn histogram_shift(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f64>() / 2.0
+}
+
+// histogram_shift of an encoded result against the histogram of what it was
+// made from. None when the bytes aren't something the image crate decodes
+pub fn encoded_shift(original: &[f64], bytes: &[u8]) -> Option<f64> {
+    let decoded = image::load_from_memory(bytes).ok()?.to_rgba8();
+    Some(histogram_shift(original, &histogram(&decoded)))
+}
+
+// Viridis at evenly spaced points, dark purple to yellow. Its lightness
+// climbs steadily and it needs no red-green distinction, so it reads the
+// same with colour blindness and in greyscale
+const RAMP: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 45, 123],
+    [59, 82, 139],
+    [44, 114, 142],
+    [33, 145, 140],
+    [40, 174, 128],
+    [94, 201, 98],
+    [173, 220, 48],
+    [253, 231, 37],
+];
+
+// Difference the heatmap's ramp tops out at, where two colours are plainly
+// different at a glance
+const HEATMAP_FULL: f64 = 10.0;
+
+fn ramp(t: f64) -> Rgb<u8> {
+    let t = t.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let (i, f) = (t.floor() as usize, t.fract());
+    let (lo, hi) = (RAMP[i], RAMP[(i + 1).min(RAMP.len() - 1)]);
+    Rgb([0, 1, 2].map(|c| (lo[c] as f64 + (hi[c] as f64 - lo[c] as f64) * f).round() as u8))
+}
+
+// CIEDE2000 difference of each pixel, see color::ciede2000, over white so
+// differences in transparency show too. Returns the heatmap, with the mean
+// and largest difference
+fn heatmap(a: &RgbaImage, b: &RgbaImage) -> (RgbImage, f64, f64) {
+    let flat = |img: &RgbaImage| color::flatten(&DynamicImage::ImageRgba8(img.clone()), [255; 3]);
+    let (a, b) = (flat(a).to_rgb8(), flat(b).to_rgb8());
+    let (a, b) = (&a, &b);
+    let differences = (0..a.height())
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..a.width()).map(move |x| {
+                color::ciede2000(
+                    color::lab(a.get_pixel(x, y).0),
+                    color::lab(b.get_pixel(x, y).0),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let map = RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        ramp(differences[(y * a.width() + x) as usize] / HEATMAP_FULL)
+    });
+    let mean = differences.iter().sum::<f64>() / differences.len().max(1) as f64;
+    let max = differences.iter().copied().fold(0.0, f64::max);
+    (map, mean, max)
+}
+
+// `autosize diff a.png b.jpg [--heatmap out.png]`
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut heatmap_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some(("--heatmap", v)) => heatmap_path = Some(v.to_string()),
+            _ if arg == "--heatmap" => {
+                heatmap_path = Some(args.next().ok_or("--heatmap expects a value")?.clone())
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument '{}'", arg).into()),
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.len() != 2 {
+        return Err("Usage: autosize diff <a> <b> [--heatmap <out.png>]".into());
+    }
+    let (a, b) = (
+        image::open(&files[0])?.to_rgba8(),
+        image::open(&files[1])?.to_rgba8(),
+    );
+    for (name, img) in files.iter().zip([&a, &b]) {
+        println!(
+            "{}: {}, {}x{}",
+            name,
+            units::format_size(fs::metadata(name)?.len()),
+            img.width(),
+            img.height()
+        );
+    }
+    // Compare at a's resolution, which is usually the original
+    let b = if a.dimensions() != b.dimensions() {
+        println!(
+            "Dimensions differ, resizing {} to {}x{} for comparison",
+            files[1],
+            a.width(),
+            a.height()
+        );
+        imageops::resize(&b, a.width(), a.height(), imageops::FilterType::Lanczos3)
+    } else {
+        b
+    };
+    println!("PSNR: {:.2} dB", psnr(&a, &b));
+    println!("SSIM: {:.4}", ssim(&a, &b));
+    if let Some(path) = heatmap_path {
+        let (map, mean, max) = heatmap(&a, &b);
+        map.save(&path)?;
+        println!("CIEDE2000: mean {:.2}, largest {:.2}", mean, max);
+        println!(
+            "Wrote difference heatmap to {} (dark purple for none, yellow for {} or more)",
+            path, HEATMAP_FULL
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    fn flat(rgb: [u8; 3]) -> Vec<f64> {
+        histogram(&RgbaImage::from_pixel(
+            8,
+            8,
+            Rgba([rgb[0], rgb[1], rgb[2], 255]),
+        ))
+    }
+
+    #[test]
+    fn histograms_add_up_to_one_over_visible_pixels() {
+        let mut img = RgbaImage::from_pixel(4, 4, Rgba([200, 10, 90, 255]));
+        img.put_pixel(0, 0, Rgba([0, 255, 0, 0]));
+        let bins = histogram(&img);
+        assert!((bins.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        // The see-through pixel's colour isn't counted
+        assert!(histogram_shift(&bins, &flat([200, 10, 90])) < 1e-9);
+    }
+
+    #[test]
+    fn identical_images_have_no_shift() {
+        let img = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 77, 255]));
+        assert_eq!(histogram_shift(&histogram(&img), &histogram(&img)), 0.0);
+    }
+
+    #[test]
+    fn disjoint_colours_shift_completely() {
+        let shift = histogram_shift(&flat([0, 0, 0]), &flat([255, 255, 255]));
+        assert!((shift - 1.0).abs() < 1e-9, "{}", shift);
+        let shift = histogram_shift(&flat([255, 0, 0]), &flat([0, 0, 255]));
+        assert!((shift - 1.0).abs() < 1e-9, "{}", shift);
+    }
+
+    #[test]
+    fn a_nudge_across_a_bin_edge_shifts_a_little() {
+        // 36 and 37 sit either side of the second bin's centre at 255/7
+        let shift = histogram_shift(&flat([36, 36, 36]), &flat([37, 37, 37]));
+        assert!(shift > 0.0 && shift < 0.1, "{}", shift);
+        // Far less than a real change of colour
+        let moved = histogram_shift(&flat([36, 36, 36]), &flat([90, 36, 36]));
+        assert!(moved > 10.0 * shift, "{} against {}", moved, shift);
+    }
+}
@@ -0,0 +1,111 @@
+use image::{imageops, DynamicImage};
+
+// Part of an image, in its pixels
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+// Detail is measured on a copy about this wide, enough to tell a subject
+// from an empty sky or wall
+const ENERGY_WIDTH: u32 = 256;
+// Where the middle of the faces goes from the top of the crop, a little
+// above centre so there's room over the heads rather than under the chins
+const FACE_LINE: f64 = 0.4;
+
+// Accepts aspect ratios like `1:1`, `16:9` or `4/5`
+pub fn parse_aspect(input: &str) -> Option<(u32, u32)> {
+    let (w, h) = input.split_once([':', '/'])?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+// How much each column (or row, with rows) of the image changes from its
+// neighbours, on a reduced copy. The returned factor maps copy pixels back
+fn energy(img: &DynamicImage, rows: bool) -> (Vec<u64>, f64) {
+    let factor = (img.width() as f64 / ENERGY_WIDTH as f64).max(1.0);
+    let gray = imageops::resize(
+        &img.to_luma8(),
+        ((img.width() as f64 / factor) as u32).max(1),
+        ((img.height() as f64 / factor) as u32).max(1),
+        imageops::FilterType::Triangle,
+    );
+    let (w, h) = gray.dimensions();
+    let mut lines = vec![0u64; if rows { h } else { w } as usize];
+    for y in 0..h {
+        for x in 0..w {
+            let here = gray.get_pixel(x, y)[0];
+            let right = gray.get_pixel((x + 1).min(w - 1), y)[0];
+            let below = gray.get_pixel(x, (y + 1).min(h - 1))[0];
+            let change = here.abs_diff(right) as u64 + here.abs_diff(below) as u64;
+            lines[if rows { y } else { x } as usize] += change;
+        }
+    }
+    (lines, factor)
+}
+
+// Start of the window of length span along an axis of length total, over
+// the stretch with the most detail
+fn busiest(img: &DynamicImage, rows: bool, span: u32, total: u32) -> u32 {
+    let (lines, factor) = energy(img, rows);
+    let window = ((span as f64 / factor).round() as usize).clamp(1, lines.len());
+    let mut sum = lines[..window].iter().sum::<u64>();
+    let (mut best, mut best_sum) = (0, sum);
+    for start in 1..=lines.len() - window {
+        sum = sum + lines[start + window - 1] - lines[start - 1];
+        if sum > best_sum {
+            (best, best_sum) = (start, sum);
+        }
+    }
+    ((best as f64 * factor).round() as u32).min(total - span)
+}
+
+// Start of the window along an axis so it takes in the faces, from lo to
+// hi, with their middle at line of the way along. When they don't all fit
+// the start of them (the tops of the heads, going down) is kept
+fn around(lo: u32, hi: u32, line: f64, span: u32, total: u32) -> u32 {
+    let start = if hi - lo > span {
+        lo as f64
+    } else {
+        (lo + hi) as f64 / 2.0 - span as f64 * line
+    };
+    (start.max(0.0) as u32).min(total - span)
+}
+
+// The largest part of the image with the given aspect ratio, None when it
+// already has it. The cut comes off the long side, placed around the faces
+// when any were found or else where there's the most going on, so a
+// portrait cropped square for an avatar keeps the head instead of losing
+// it to a centre crop
+pub fn region(img: &DynamicImage, aspect: (u32, u32), faces: &[Rect]) -> Option<Rect> {
+    let (w, h) = (img.width(), img.height());
+    let (aw, ah) = (aspect.0 as u64, aspect.1 as u64);
+    let (cw, ch) = if w as u64 * ah > h as u64 * aw {
+        ((h as u64 * aw / ah) as u32, h)
+    } else {
+        (w, (w as u64 * ah / aw) as u32)
+    };
+    let (cw, ch) = (cw.max(1), ch.max(1));
+    if (cw, ch) == (w, h) {
+        return None;
+    }
+    let bounds = faces
+        .iter()
+        .fold(None, |bounds: Option<(u32, u32, u32, u32)>, f| {
+            let (x1, y1) = (f.x + f.w, f.y + f.h);
+            Some(match bounds {
+                Some((x0, y0, bx1, by1)) => (x0.min(f.x), y0.min(f.y), bx1.max(x1), by1.max(y1)),
+                None => (f.x, f.y, x1, y1),
+            })
+        });
+    let (x, y) = match bounds {
+        Some((x0, _, x1, _)) if cw < w => (around(x0, x1, 0.5, cw, w), 0),
+        Some((_, y0, _, y1)) => (0, around(y0, y1, FACE_LINE, ch, h)),
+        None if cw < w => (busiest(img, false, cw, w), 0),
+        None => (0, busiest(img, true, ch, h)),
+    };
+    Some(Rect { x, y, w: cw, h: ch })
+}
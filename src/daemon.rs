@@ -0,0 +1,355 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::{options::Options, prompt_settings, settings, units};
+
+// How many files are fitted at once, and how many can wait their turn before
+// new jobs are turned away with `busy`
+const WORKERS: usize = 2;
+const QUEUE: usize = 64;
+
+// How many finished jobs are remembered for `status`, older ones are
+// forgotten so a long running daemon doesn't grow without end
+const KEEP: usize = 1024;
+
+// How many clients can be connected at once, more are answered `busy` and
+// disconnected rather than each getting a thread
+const CONNECTIONS: usize = 32;
+
+enum Status {
+    Waiting,
+    Running,
+    Done { output: PathBuf, size: u64 },
+    Failed(String),
+}
+
+struct Job {
+    file: PathBuf,
+    target: u64,
+    status: Status,
+}
+
+// The jobs waiting, running and the last KEEP finished, by id, with the
+// ids still waiting and the finished ones oldest first. Ids keep counting
+// up from the start, as do the totals for `stats`
+#[derive(Default)]
+struct State {
+    jobs: BTreeMap<usize, Job>,
+    next: usize,
+    queue: VecDeque<usize>,
+    finished: VecDeque<usize>,
+    done: usize,
+    failed: usize,
+    connections: usize,
+}
+
+impl State {
+    fn finish(&mut self, id: usize, status: Status) {
+        match status {
+            Status::Done { .. } => self.done += 1,
+            _ => self.failed += 1,
+        }
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.status = status;
+        }
+        self.finished.push_back(id);
+        while self.finished.len() > KEEP {
+            let old = self.finished.pop_front().unwrap();
+            self.jobs.remove(&old);
+        }
+    }
+}
+
+type Shared = Arc<(Mutex<State>, Condvar)>;
+
+fn worker(shared: Shared, opts: Options) {
+    let (state, ready) = &*shared;
+    loop {
+        let (id, file, target) = {
+            let mut state = state.lock().unwrap();
+            let id = loop {
+                match state.queue.pop_front() {
+                    Some(id) => break id,
+                    None => state = ready.wait(state).unwrap(),
+                }
+            };
+            let job = state.jobs.get_mut(&id).unwrap();
+            job.status = Status::Running;
+            (id, job.file.clone(), job.target)
+        };
+        let opts = Options {
+            target,
+            ..opts.clone()
+        };
+        // A panic in a decoder or encoder fails the job instead of taking
+        // the worker down with it and leaving the job running forever
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::batch::fit_file(&file, &opts, &["daemon".to_string()]).map_err(|e| e.to_string())
+        }))
+        .unwrap_or_else(|panic| {
+            let why = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("crashed: {}", why))
+        });
+        let status = match result {
+            Ok((fit, size)) => {
+                // Clients don't know the daemon's working directory
+                let output = std::fs::canonicalize(&fit.output).unwrap_or(fit.output);
+                println!(
+                    "[{}] {} -> {} ({})",
+                    id,
+                    file.display(),
+                    output.display(),
+                    units::format_size(size)
+                );
+                Status::Done { output, size }
+            }
+            Err(e) => {
+                println!("[{}] {} failed: {}", id, file.display(), e);
+                Status::Failed(e.to_string().replace('\n', " "))
+            }
+        };
+        state.lock().unwrap().finish(id, status);
+    }
+}
+
+// One request line to its response line:
+//   fit <size> <file>  ->  queued <id> | busy | error <why>
+//   status <id>        ->  waiting <ahead> | running | done <bytes> <output>
+//                          | failed <why> | error <why>
+//   stats              ->  waiting <n> running <n> done <n> failed <n>
+// Done and failed in stats count every job since the start
+fn respond(line: &str, shared: &Shared, queue_limit: usize) -> String {
+    let (state, ready) = &**shared;
+    let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+    match command {
+        "fit" => {
+            let Some((size, file)) = rest.trim_start().split_once(' ') else {
+                return "error usage: fit <size> <file>".into();
+            };
+            let Some(target) = units::parse_size(size).filter(|&t| t >= 128) else {
+                return format!("error expected a size like 500k, got '{}'", size);
+            };
+            let file = PathBuf::from(file);
+            if !file.is_file() {
+                return format!("error {} isn't a file", file.display());
+            }
+            let mut state = state.lock().unwrap();
+            if state.queue.len() >= queue_limit {
+                return "busy".into();
+            }
+            let id = state.next;
+            state.next += 1;
+            state.jobs.insert(
+                id,
+                Job {
+                    file,
+                    target,
+                    status: Status::Waiting,
+                },
+            );
+            state.queue.push_back(id);
+            ready.notify_one();
+            format!("queued {}", id)
+        }
+        "status" => {
+            let state = state.lock().unwrap();
+            let Some((&id, job)) = rest
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| state.jobs.get_key_value(&id))
+            else {
+                return format!("error no job '{}'", rest.trim());
+            };
+            match &job.status {
+                Status::Waiting => {
+                    let ahead = state.queue.iter().position(|&i| i == id).unwrap_or(0);
+                    format!("waiting {}", ahead)
+                }
+                Status::Running => "running".into(),
+                Status::Done { output, size } => format!("done {} {}", size, output.display()),
+                Status::Failed(e) => format!("failed {}", e),
+            }
+        }
+        "stats" => {
+            let state = state.lock().unwrap();
+            let running = state
+                .jobs
+                .values()
+                .filter(|j| matches!(j.status, Status::Running))
+                .count();
+            format!(
+                "waiting {} running {} done {} failed {}",
+                state.queue.len(),
+                running,
+                state.done,
+                state.failed
+            )
+        }
+        _ => format!("error unknown command '{}'", command),
+    }
+}
+
+#[cfg(unix)]
+fn serve(
+    socket: &std::path::Path,
+    shared: Shared,
+    queue_limit: usize,
+) -> Result<(), Box<dyn Error>> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        thread,
+    };
+
+    // A socket left behind by a daemon that didn't shut down cleanly is
+    // replaced, one that still answers belongs to a running daemon
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(format!("A daemon is already listening on {}", socket.display()).into());
+        }
+        std::fs::remove_file(socket)?;
+    }
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    println!("Listening on {}", socket.display());
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let Ok(mut writer) = stream.try_clone() else {
+            continue;
+        };
+        {
+            let mut state = shared.0.lock().unwrap();
+            if state.connections >= CONNECTIONS {
+                let _ = writeln!(writer, "busy");
+                continue;
+            }
+            state.connections += 1;
+        }
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = respond(&line, &shared, queue_limit);
+                if writeln!(writer, "{}", response).is_err() {
+                    break;
+                }
+            }
+            shared.0.lock().unwrap().connections -= 1;
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve(_: &std::path::Path, _: Shared, _: usize) -> Result<(), Box<dyn Error>> {
+    Err("The daemon listens on a unix socket, which isn't available on this system".into())
+}
+
+// `autosize daemon [--socket <path>] [--workers <n>] [--queue <n>] [options]`:
+// takes jobs over a unix socket, one request per line, and fits them a few
+// at a time in the background. For upload servers that want images fitted
+// without starting a process per file. Options apply to every job, the
+// rest of the settings are the ones entered last time
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut socket = settings::data_dir().join("daemon.sock");
+    let (mut workers, mut queue) = (WORKERS, QUEUE);
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if !["--socket", "--workers", "--queue"].contains(&flag) {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = inline
+            .or_else(|| iter.next().cloned())
+            .ok_or(format!("{} expects a value", flag))?;
+        match flag {
+            "--socket" => socket = PathBuf::from(value),
+            _ => {
+                let n = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or(format!("{} expects a number, got '{}'", flag, value))?;
+                match flag {
+                    "--workers" => workers = n,
+                    _ => queue = n,
+                }
+            }
+        }
+    }
+    let mut opts = Options::parse(&rest)?;
+    if let Some(input) = opts.inputs.first() {
+        return Err(format!("Unknown argument '{}'", input.display()).into());
+    }
+    // Jobs bring their own target, this only fills in the other settings
+    opts.given_target = Some(opts.given_target.unwrap_or(opts.target));
+    prompt_settings(&mut opts, false)?;
+    // Searches running side by side would garble each other's progress, and
+    // there's no one at the terminal to review their results
+    opts.quiet = true;
+    opts.review = false;
+    let shared: Shared = Arc::default();
+    for _ in 0..workers {
+        let (shared, opts) = (shared.clone(), opts.clone());
+        std::thread::spawn(move || worker(shared, opts));
+    }
+    serve(&socket, shared, queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_finished_jobs_are_forgotten() {
+        let shared: Shared = Arc::default();
+        let file = file!();
+        for i in 0..KEEP + 3 {
+            assert_eq!(
+                respond(&format!("fit 1MB {}", file), &shared, usize::MAX),
+                format!("queued {}", i)
+            );
+        }
+        {
+            let mut state = shared.0.lock().unwrap();
+            while let Some(id) = state.queue.pop_front() {
+                state.finish(id, Status::Failed("no".into()));
+            }
+            assert_eq!(state.jobs.len(), KEEP);
+        }
+        assert_eq!(respond("status 0", &shared, 1), "error no job '0'");
+        assert_eq!(respond("status 3", &shared, 1), "failed no");
+        assert_eq!(
+            respond("stats", &shared, 1),
+            format!("waiting 0 running 0 done 0 failed {}", KEEP + 3)
+        );
+        assert_eq!(
+            respond(&format!("fit 1MB {}", file), &shared, 1),
+            format!("queued {}", KEEP + 3)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::{borrow::Cow, error::Error};
+
+use color_quant::NeuQuant;
+use image::{DynamicImage, GrayImage, Luma, Rgb, Rgba};
+
+use crate::dither::{self, Dither};
+
+// Extreme bit-depth reductions for brutally small targets (tiny embedded
+// icons and the like). They're applied to every candidate at encode time,
+// since resizing brings back all the in-between values
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    Full,
+    // 5 bits of red, 6 of green, 5 of blue
+    Rgb565,
+    // 16 grey levels, written as a real 4-bit PNG
+    Gray4,
+}
+
+impl Depth {
+    pub fn parse(value: &str) -> Option<Depth> {
+        match value {
+            "full" => Some(Depth::Full),
+            "rgb565" | "565" => Some(Depth::Rgb565),
+            "gray4" | "grey4" => Some(Depth::Gray4),
+            _ => None,
+        }
+    }
+}
+
+// Keeps the top `bits` of v, repeating them into the low bits so full
+// white stays full white
+fn truncate(v: u8, bits: u32) -> u8 {
+    let top = v >> (8 - bits);
+    (top << (8 - bits)) | (top >> (2 * bits - 8))
+}
+
+fn gray4(v: u8) -> u8 {
+    ((v as u32 * 15 + 127) / 255 * 17) as u8
+}
+
+pub fn reduce(img: &DynamicImage, depth: Depth) -> Cow<'_, DynamicImage> {
+    match depth {
+        Depth::Full => Cow::Borrowed(img),
+        Depth::Rgb565 if img.color().has_alpha() => {
+            let mut rgba = img.to_rgba8();
+            for p in rgba.pixels_mut() {
+                *p = Rgba([
+                    truncate(p[0], 5),
+                    truncate(p[1], 6),
+                    truncate(p[2], 5),
+                    p[3],
+                ]);
+            }
+            Cow::Owned(DynamicImage::ImageRgba8(rgba))
+        }
+        Depth::Rgb565 => {
+            let mut rgb = img.to_rgb8();
+            for p in rgb.pixels_mut() {
+                *p = Rgb([truncate(p[0], 5), truncate(p[1], 6), truncate(p[2], 5)]);
+            }
+            Cow::Owned(DynamicImage::ImageRgb8(rgb))
+        }
+        Depth::Gray4 if img.color().has_alpha() => {
+            let mut gray = img.to_luma_alpha8();
+            for p in gray.pixels_mut() {
+                p[0] = gray4(p[0]);
+            }
+            Cow::Owned(DynamicImage::ImageLumaA8(gray))
+        }
+        Depth::Gray4 => {
+            let mut gray = img.to_luma8();
+            for p in gray.pixels_mut() {
+                *p = Luma([gray4(p[0])]);
+            }
+            Cow::Owned(DynamicImage::ImageLuma8(gray))
+        }
+    }
+}
+
+// Packs a gray4-reduced image two pixels to a byte, which the image crate's
+// PNG encoder can't do
+pub fn encode_gray4_png(gray: &GrayImage) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (w, h) = gray.dimensions();
+    let mut packed = Vec::with_capacity((w as usize).div_ceil(2) * h as usize);
+    for row in gray.rows() {
+        let levels = row.map(|p| p[0] / 17).collect::<Vec<_>>();
+        packed.extend(levels.chunks(2).map(|c| c[0] << 4 | c.get(1).unwrap_or(&0)));
+    }
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, w, h);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Four);
+    encoder.set_compression(png::Compression::Best);
+    encoder.write_header()?.write_image_data(&packed)?;
+    Ok(bytes)
+}
+
+// Quantizes to at most `colors` entries with NeuQuant and writes an indexed
+// PNG, with alpha in a tRNS chunk. Fully transparent pixels get an entry of
+// their own so they stay fully transparent. speed is NeuQuant's sampling
+// factor, as for GIF palettes
+pub fn encode_palette_png(
+    img: &DynamicImage,
+    colors: u16,
+    speed: i32,
+    dither: Dither,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let rgba = img.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let clear = rgba.pixels().any(|p| p[3] == 0);
+    let colors = (colors as usize).clamp(2, 256) - clear as usize;
+    let mut visible = rgba
+        .pixels()
+        .filter(|p| p[3] != 0)
+        .flat_map(|p| p.0)
+        .collect::<Vec<_>>();
+    if visible.is_empty() {
+        visible = vec![0, 0, 0, 255];
+    }
+    let nq = NeuQuant::new(speed, colors, &visible);
+    let mut palette = nq.color_map_rgba();
+    let entries = palette
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect::<Vec<_>>();
+    let clear_index = clear.then_some(colors as u8);
+    let indices = dither::indices(&rgba, &entries, clear_index, dither, |p| {
+        nq.index_of(p) as u8
+    });
+    if clear {
+        palette.extend([0, 0, 0, 0]);
+    }
+    let rgb = palette
+        .chunks_exact(4)
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect::<Vec<_>>();
+    let alpha = palette.chunks_exact(4).map(|c| c[3]).collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, w, h);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(rgb);
+    if alpha.iter().any(|&a| a != 255) {
+        encoder.set_trns(alpha);
+    }
+    encoder.set_compression(png::Compression::Best);
+    encoder.write_header()?.write_image_data(&indices)?;
+    Ok(bytes)
+}
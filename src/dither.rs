@@ -0,0 +1,227 @@
+use std::sync::OnceLock;
+
+use image::RgbaImage;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// How colours between palette entries are drawn when a palette is applied,
+// for GIFs and indexed PNGs. Dithering hides banding in gradients at the
+// cost of noise, which compresses worse, so it's off unless asked for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dither {
+    // Every pixel gets its nearest entry
+    None,
+    // A repeating 8x8 Bayer pattern: a regular crosshatch, but it stays put
+    // from frame to frame and LZW compresses it well
+    Ordered,
+    // Each pixel's error is pushed onto its neighbours. The smoothest
+    // gradients, and the most bytes
+    FloydSteinberg,
+    // A 64x64 blue-noise pattern: no visible structure, and still the
+    // same from frame to frame
+    BlueNoise,
+}
+
+impl Dither {
+    pub fn parse(name: &str) -> Option<Dither> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Some(Dither::None),
+            "ordered" | "bayer" => Some(Dither::Ordered),
+            "floyd-steinberg" | "floyd" | "fs" => Some(Dither::FloydSteinberg),
+            "blue-noise" | "blue" => Some(Dither::BlueNoise),
+            _ => None,
+        }
+    }
+}
+
+// How far the ordered and blue-noise patterns move a channel either way,
+// relative to the distance between levels of a palette of this many
+// colours spread evenly over the RGB cube
+fn spread(colors: usize) -> f32 {
+    255.0 / (colors.max(2) as f32).cbrt()
+}
+
+// 8x8 Bayer matrix, values 0-63, built up from the 2x2 one
+fn bayer() -> [[u8; 8]; 8] {
+    let mut m = [[0u8; 8]; 8];
+    let mut size = 1;
+    while size < 8 {
+        for y in 0..size {
+            for x in 0..size {
+                let v = m[y][x] * 4;
+                m[y][x] = v;
+                m[y][x + size] = v + 2;
+                m[y + size][x] = v + 3;
+                m[y + size][x + size] = v + 1;
+            }
+        }
+        size *= 2;
+    }
+    m
+}
+
+const BLUE: usize = 64;
+
+// Thresholds 0-4095 in a BLUE x BLUE tile by Ulichney's void-and-cluster:
+// points are ranked by adding each where the pattern so far leaves the
+// biggest gap, found by a Gaussian "energy" that wraps around the tile.
+// Made once, with a fixed seed so the pattern never changes between runs
+fn blue_noise() -> &'static [u16] {
+    static TILE: OnceLock<Vec<u16>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        let n = BLUE * BLUE;
+        // The Gaussian's contribution at each offset, wrapping around
+        let kernel = (0..n)
+            .map(|i| {
+                let d = |v: usize| v.min(BLUE - v) as f32;
+                let (dx, dy) = (d(i % BLUE), d(i / BLUE));
+                (-(dx * dx + dy * dy) / (2.0 * 1.5 * 1.5)).exp()
+            })
+            .collect::<Vec<_>>();
+        let mut energy = vec![0.0f32; n];
+        let mut on = vec![false; n];
+        let set = |energy: &mut [f32], on: &mut [bool], i: usize, value: bool| {
+            on[i] = value;
+            let sign = if value { 1.0 } else { -1.0 };
+            let (x, y) = (i % BLUE, i / BLUE);
+            for (j, e) in energy.iter_mut().enumerate() {
+                let (dx, dy) = ((j % BLUE + BLUE - x) % BLUE, (j / BLUE + BLUE - y) % BLUE);
+                *e += sign * kernel[dy * BLUE + dx];
+            }
+        };
+        // The point with the most or least energy among those on or off:
+        // the tightest cluster, or the biggest void
+        let extreme = |energy: &[f32], on: &[bool], want: bool, most: bool| {
+            (0..n)
+                .filter(|&i| on[i] == want)
+                .max_by(|&a, &b| match most {
+                    true => energy[a].total_cmp(&energy[b]),
+                    false => energy[b].total_cmp(&energy[a]),
+                })
+                .unwrap()
+        };
+        // A random start, then points moved from the tightest cluster to
+        // the biggest void until that changes nothing
+        let mut rng = StdRng::seed_from_u64(BLUE as u64);
+        let initial = n / 10;
+        while on.iter().filter(|&&o| o).count() < initial {
+            let i = rng.gen_range(0..n);
+            if !on[i] {
+                set(&mut energy, &mut on, i, true);
+            }
+        }
+        loop {
+            let cluster = extreme(&energy, &on, true, true);
+            set(&mut energy, &mut on, cluster, false);
+            let void = extreme(&energy, &on, false, false);
+            set(&mut energy, &mut on, void, true);
+            if void == cluster {
+                break;
+            }
+        }
+        let mut rank = vec![0u16; n];
+        // The start's points get the lowest ranks, tightest cluster last
+        let (start_on, start_energy) = (on.clone(), energy.clone());
+        for r in (0..initial).rev() {
+            let cluster = extreme(&energy, &on, true, true);
+            set(&mut energy, &mut on, cluster, false);
+            rank[cluster] = r as u16;
+        }
+        // Then every other point, into the biggest void left
+        (on, energy) = (start_on, start_energy);
+        for r in initial..n {
+            let void = extreme(&energy, &on, false, false);
+            set(&mut energy, &mut on, void, true);
+            rank[void] = r as u16;
+        }
+        rank
+    })
+}
+
+// sRGB to linear light, and back
+fn linear(v: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|i| {
+            let c = i as f32 / 255.0;
+            match c <= 0.04045 {
+                true => c / 12.92,
+                false => ((c + 0.055) / 1.055).powf(2.4),
+            }
+        })
+    })[v as usize]
+}
+
+fn encoded(l: f32) -> u8 {
+    let l = l.clamp(0.0, 1.0);
+    let c = match l <= 0.0031308 {
+        true => l * 12.92,
+        false => 1.055 * l.powf(1.0 / 2.4) - 0.055,
+    };
+    (c * 255.0).round() as u8
+}
+
+// Picks a palette entry for every pixel of img, by nearest (the palette's
+// own lookup), dithered. palette holds the entries' colours for the error
+// Floyd-Steinberg carries, which it does in linear light so a dithered
+// area keeps its brightness; the patterns are offsets in sRGB, which is
+// what they're even in. Fully transparent pixels get clear when there's
+// one, and alpha is never dithered
+pub fn indices(
+    img: &RgbaImage,
+    palette: &[[u8; 3]],
+    clear: Option<u8>,
+    dither: Dither,
+    nearest: impl Fn(&[u8; 4]) -> u8,
+) -> Vec<u8> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let mut indices = Vec::with_capacity(w * h);
+    let amount = spread(palette.len());
+    let (bayer, blue) = (bayer(), (dither == Dither::BlueNoise).then(blue_noise));
+    let offset = |x: usize, y: usize| match (dither, blue) {
+        (Dither::BlueNoise, Some(blue)) => {
+            (blue[y % BLUE * BLUE + x % BLUE] as f32 + 0.5) / (BLUE * BLUE) as f32 - 0.5
+        }
+        _ => (bayer[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5,
+    };
+    // Error for this row and the next, in linear light, one pixel of
+    // padding either side
+    let (mut this, mut next) = (vec![[0.0f32; 3]; w + 2], vec![[0.0f32; 3]; w + 2]);
+    for y in 0..h {
+        for x in 0..w {
+            let p = img.get_pixel(x as u32, y as u32).0;
+            if let (0, Some(clear)) = (p[3], clear) {
+                indices.push(clear);
+                continue;
+            }
+            let index = match dither {
+                Dither::None => nearest(&p),
+                Dither::Ordered | Dither::BlueNoise => {
+                    let t = offset(x, y) * amount;
+                    let c = |v: u8| (v as f32 + t).round().clamp(0.0, 255.0) as u8;
+                    nearest(&[c(p[0]), c(p[1]), c(p[2]), p[3]])
+                }
+                Dither::FloydSteinberg => {
+                    let want: [f32; 3] = std::array::from_fn(|c| linear(p[c]) + this[x + 1][c]);
+                    let index =
+                        nearest(&[encoded(want[0]), encoded(want[1]), encoded(want[2]), p[3]]);
+                    let got = palette
+                        .get(index as usize)
+                        .copied()
+                        .unwrap_or([p[0], p[1], p[2]]);
+                    for c in 0..3 {
+                        let e = want[c] - linear(got[c]);
+                        this[x + 2][c] += e * 7.0 / 16.0;
+                        next[x][c] += e * 3.0 / 16.0;
+                        next[x + 1][c] += e * 5.0 / 16.0;
+                        next[x + 2][c] += e / 16.0;
+                    }
+                    index
+                }
+            };
+            indices.push(index);
+        }
+        std::mem::swap(&mut this, &mut next);
+        next.fill([0.0; 3]);
+    }
+    indices
+}
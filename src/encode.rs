@@ -0,0 +1,189 @@
+use std::{error::Error, num::NonZeroU8, path::Path};
+
+use image::DynamicImage;
+use jpeg_encoder::SamplingFactor;
+
+use crate::{
+    backend,
+    depth::{self, Depth},
+    dither::Dither,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Subsampling {
+    // Pick per image, see resolve_subsampling
+    Auto,
+    S444,
+    S422,
+    S420,
+}
+
+impl Subsampling {
+    pub fn parse(value: &str) -> Option<Subsampling> {
+        match value {
+            "auto" => Some(Subsampling::Auto),
+            "444" | "4:4:4" => Some(Subsampling::S444),
+            "422" | "4:2:2" => Some(Subsampling::S422),
+            "420" | "4:2:0" => Some(Subsampling::S420),
+            _ => None,
+        }
+    }
+
+    pub fn factor(self) -> SamplingFactor {
+        match self {
+            Subsampling::S444 => SamplingFactor::R_4_4_4,
+            Subsampling::S422 => SamplingFactor::R_4_2_2,
+            Subsampling::Auto | Subsampling::S420 => SamplingFactor::R_4_2_0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsampling::Auto => "auto",
+            Subsampling::S444 => "4:4:4",
+            Subsampling::S422 => "4:2:2",
+            Subsampling::S420 => "4:2:0",
+        }
+    }
+}
+
+// How candidates get encoded, carried unchanged through the whole search
+#[derive(Clone, Copy)]
+pub struct EncoderSettings {
+    pub jpeg_quality: u8,
+    pub subsampling: Subsampling,
+    pub progressive: bool,
+    // Adam7 for PNG, 4-pass row order for GIF
+    pub interlaced: bool,
+    // NeuQuant sampling factor for GIF palettes, 1 (best) to 30 (fastest)
+    pub gif_speed: i32,
+    // Encode JPEGs with mozjpeg (needs the `mozjpeg` feature)
+    pub mozjpeg: bool,
+    // oxipng preset (0-6) for the final PNG, None to skip the pass
+    pub png_level: Option<u8>,
+    pub zopfli: bool,
+    // Frame differencing pass over the final animation
+    pub gif_optimize: bool,
+    // One palette shared by every frame of an animation instead of one
+    // each, see animation::GlobalPalette
+    pub gif_global_palette: bool,
+    pub depth: Depth,
+    // Palette size, set by the planner's quantize step: PNGs are written
+    // indexed with this many colours and GIFs get it instead of 256
+    pub palette: Option<u16>,
+    // How a palette is applied to GIFs and indexed PNGs
+    pub dither: Dither,
+    // JPEG XL butteraugli distance (0 is lossless, 1 visually lossless) and
+    // encoder effort 1-9 (needs the `jxl` feature)
+    pub jxl_distance: f32,
+    pub jxl_effort: u8,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            jpeg_quality: 75,
+            subsampling: Subsampling::S420,
+            progressive: false,
+            interlaced: false,
+            gif_speed: 10,
+            mozjpeg: false,
+            png_level: Some(2),
+            zopfli: false,
+            gif_optimize: true,
+            gif_global_palette: false,
+            depth: Depth::Full,
+            palette: None,
+            dither: Dither::None,
+            jxl_distance: 1.0,
+            jxl_effort: 7,
+        }
+    }
+}
+
+pub fn is_jpeg(ext: &str) -> bool {
+    ["jpg", "jpeg"].contains(&ext.to_ascii_lowercase().as_str())
+}
+
+// image::open, plus the formats we decode ourselves
+pub fn open(path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+    #[cfg(feature = "jxl")]
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("jxl"))
+    {
+        return crate::jxl::decode(path);
+    }
+    // By content, so a PNG saved as .jpg still opens
+    Ok(image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()?)
+}
+
+// A JPEG at the given quality, whatever settings.jpeg_quality says
+pub fn encode_jpeg(
+    img: &DynamicImage,
+    quality: u8,
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let settings = EncoderSettings {
+        jpeg_quality: quality,
+        ..*settings
+    };
+    backend::find(img, "jpg", &settings)?.encode(img, "jpg", &settings)
+}
+
+// Encodes to the format implied by ext, with the backend for it, see
+// backend::BACKENDS
+pub fn encode(
+    img: &DynamicImage,
+    ext: &str,
+    settings: &EncoderSettings,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let reduced = depth::reduce(img, settings.depth);
+    let img = reduced.as_ref();
+    backend::find(img, ext, settings)?.encode(img, ext, settings)
+}
+
+// Full chroma costs bytes, which the search pays for in resolution. Keep
+// 4:4:4 when that costs less than 10% of the linear resolution (flat
+// screenshots, coloured text), otherwise fall back to 4:2:0 (photographs)
+pub fn resolve_subsampling(
+    img: &DynamicImage,
+    settings: &EncoderSettings,
+) -> Result<Subsampling, Box<dyn Error>> {
+    let mut probe = *settings;
+    probe.subsampling = Subsampling::S444;
+    let full = encode_jpeg(img, probe.jpeg_quality, &probe)?.len() as f64;
+    probe.subsampling = Subsampling::S420;
+    let half = encode_jpeg(img, probe.jpeg_quality, &probe)?.len() as f64;
+    // Encoded size grows roughly with pixel count, i.e. with scale squared
+    if (half / full).sqrt() >= 0.9 {
+        Ok(Subsampling::S444)
+    } else {
+        Ok(Subsampling::S420)
+    }
+}
+
+// Lossless oxipng pass over the chosen PNG: tries every filter strategy and
+// recompresses, optionally with the much slower zopfli deflater
+pub fn optimize_png(bytes: Vec<u8>, settings: &EncoderSettings) -> Result<Vec<u8>, Box<dyn Error>> {
+    let level = match settings.png_level {
+        Some(level) => level,
+        None => return Ok(bytes),
+    };
+    let mut options = oxipng::Options::from_preset(level);
+    if settings.zopfli {
+        options.deflate = oxipng::Deflaters::Zopfli {
+            iterations: NonZeroU8::new(15).unwrap(),
+        };
+    }
+    // Keep whatever interlacing the encoder chose
+    options.interlace = None;
+    let optimized = oxipng::optimize_from_memory(&bytes, &options)?;
+    Ok(if optimized.len() < bytes.len() {
+        optimized
+    } else {
+        bytes
+    })
+}
@@ -0,0 +1,70 @@
+use std::{error::Error, path::Path};
+
+use image::DynamicImage;
+
+use crate::{crop::Rect, onnx::Model};
+
+// What Ultra-Light-Fast-Generic-Face-Detector's 320 model takes, when the
+// model doesn't say
+const DEFAULT_SIZE: (u32, u32) = (320, 240);
+// Confidence a detection needs to count as a face
+const THRESHOLD: f32 = 0.7;
+// Detections overlapping a better one by more than this are the same face
+const OVERLAP: f32 = 0.3;
+
+// Intersection over union of two boxes given as [x1, y1, x2, y2]
+fn iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+    let w = (a[2].min(b[2]) - a[0].max(b[0])).max(0.0);
+    let h = (a[3].min(b[3]) - a[1].max(b[1])).max(0.0);
+    let both = w * h;
+    let area = |r: &[f32; 4]| (r[2] - r[0]) * (r[3] - r[1]);
+    both / (area(a) + area(b) - both).max(f32::EPSILON)
+}
+
+// Finds faces with an ONNX detector shaped like Ultra-Light-Fast (an RGB
+// input scaled to -1..1, then per-anchor scores for background and face and
+// boxes as fractions of the image). Only used to place --crop, so a missed
+// face costs a worse crop rather than a failed run
+pub fn detect(img: &DynamicImage, model: &Path) -> Result<Vec<Rect>, Box<dyn Error>> {
+    let mut onnx = Model::load(model, DEFAULT_SIZE)?;
+    let input = onnx.input(img, |_, v| (v as f32 - 127.0) / 128.0)?;
+    let outputs = onnx.session.run(ort::inputs![input])?;
+    if outputs.len() < 2 {
+        return Err(format!(
+            "The model {} doesn't give scores and boxes",
+            model.display()
+        )
+        .into());
+    }
+    let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+    let (_, boxes) = outputs[1].try_extract_tensor::<f32>()?;
+    let mut found = scores
+        .chunks_exact(2)
+        .zip(boxes.chunks_exact(4))
+        .filter(|(score, _)| score[1] >= THRESHOLD)
+        .map(|(score, b)| (score[1], [b[0], b[1], b[2], b[3]]))
+        .collect::<Vec<_>>();
+    found.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut kept: Vec<[f32; 4]> = Vec::new();
+    for (_, b) in found {
+        if kept.iter().all(|k| iou(k, &b) <= OVERLAP) {
+            kept.push(b);
+        }
+    }
+    let (iw, ih) = (img.width() as f32, img.height() as f32);
+    Ok(kept
+        .into_iter()
+        .map(|b| {
+            let x = (b[0].clamp(0.0, 1.0) * iw) as u32;
+            let y = (b[1].clamp(0.0, 1.0) * ih) as u32;
+            let x2 = (b[2].clamp(0.0, 1.0) * iw) as u32;
+            let y2 = (b[3].clamp(0.0, 1.0) * ih) as u32;
+            Rect {
+                x,
+                y,
+                w: x2.saturating_sub(x),
+                h: y2.saturating_sub(y),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,190 @@
+use std::{borrow::Cow, error::Error};
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::{animation::Animation, color, encode, formats, options::Options, plan};
+
+// Settings for fitting an image from code. Knobs get added over time, so
+// this can only be made through `new` and adjusted with the setters, which
+// keeps code using it compiling across releases
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct FitOptions {
+    // Largest allowed size of the encoded image, in bytes
+    pub target: u64,
+    // Output format by extension, e.g. "jpg", "png", "webp" or "qoi"
+    pub format: String,
+    // Stop once the result is within this many bytes under the target
+    pub tolerance: u64,
+    pub iterations: u32,
+    // Never resize below/above these fractions of the original resolution
+    pub min_scale: f64,
+    pub max_scale: f64,
+    // JPEG quality the search encodes at
+    pub quality: u8,
+    // Fail rather than return a result whose SSIM against the input is lower
+    pub min_ssim: Option<f64>,
+    // Fail rather than return a result that moves more than this share of
+    // the input's colours, and leave out palettes that would
+    pub max_color_shift: Option<f64>,
+    // What transparent pixels are composited onto for JPEG output
+    pub background: [u8; 3],
+    // Predict the scale of large images on a small copy first, which takes
+    // far fewer full-size encodes for a result slightly further under target
+    pub presearch: bool,
+    // Try the same scales on every run, so the same input gives the same bytes
+    pub reproducible: bool,
+    // Print nothing, rather than the progress the command line shows
+    pub quiet: bool,
+}
+
+impl FitOptions {
+    // JPEG output with the same defaults as the command line
+    pub fn new(target: u64) -> FitOptions {
+        let defaults = Options::default();
+        FitOptions {
+            target,
+            format: "jpg".to_string(),
+            tolerance: defaults.byte_halt,
+            iterations: defaults.iters as u32,
+            min_scale: defaults.min_scale,
+            max_scale: defaults.max_scale,
+            quality: defaults.encoder.jpeg_quality,
+            min_ssim: defaults.min_ssim,
+            max_color_shift: defaults.max_color_shift,
+            background: defaults.background,
+            presearch: defaults.presearch,
+            reproducible: defaults.reproducible,
+            quiet: defaults.quiet,
+        }
+    }
+
+    pub fn format(mut self, format: &str) -> FitOptions {
+        self.format = format.trim_start_matches('.').to_ascii_lowercase();
+        self
+    }
+
+    pub fn tolerance(mut self, bytes: u64) -> FitOptions {
+        self.tolerance = bytes;
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u32) -> FitOptions {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn min_scale(mut self, scale: f64) -> FitOptions {
+        self.min_scale = scale;
+        self
+    }
+
+    pub fn max_scale(mut self, scale: f64) -> FitOptions {
+        self.max_scale = scale;
+        self
+    }
+
+    pub fn quality(mut self, quality: u8) -> FitOptions {
+        self.quality = quality.clamp(1, 100);
+        self
+    }
+
+    pub fn min_ssim(mut self, ssim: f64) -> FitOptions {
+        self.min_ssim = Some(ssim);
+        self
+    }
+
+    pub fn max_color_shift(mut self, shift: f64) -> FitOptions {
+        self.max_color_shift = Some(shift.clamp(0.0, 1.0));
+        self
+    }
+
+    pub fn background(mut self, rgb: [u8; 3]) -> FitOptions {
+        self.background = rgb;
+        self
+    }
+
+    pub fn presearch(mut self, presearch: bool) -> FitOptions {
+        self.presearch = presearch;
+        self
+    }
+
+    pub fn reproducible(mut self, reproducible: bool) -> FitOptions {
+        self.reproducible = reproducible;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> FitOptions {
+        self.quiet = quiet;
+        self
+    }
+
+    fn to_options(&self) -> Options {
+        let mut opts = Options {
+            target: self.target,
+            byte_halt: self.tolerance,
+            iters: self.iterations.min(i32::MAX as u32) as i32,
+            min_scale: self.min_scale,
+            max_scale: self.max_scale,
+            min_ssim: self.min_ssim,
+            max_color_shift: self.max_color_shift,
+            format: Some(self.format.clone()),
+            background: self.background,
+            presearch: self.presearch,
+            reproducible: self.reproducible,
+            quiet: self.quiet,
+            ..Options::default()
+        };
+        opts.encoder.jpeg_quality = self.quality;
+        opts
+    }
+}
+
+// The result of a fit, kept in memory for the caller to write wherever
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Fitted {
+    pub bytes: Vec<u8>,
+    // Fraction of the input's resolution the result was encoded at
+    pub scale: f64,
+    // How many candidates the search encoded, at most a few more than
+    // FitOptions::iterations
+    pub encodes: u32,
+}
+
+// Finds the largest scale of img whose encoding fits options.target. The
+// same search the command line runs, minus the files: bytes in, bytes out
+pub fn fit(img: &DynamicImage, options: &FitOptions) -> Result<Fitted, Box<dyn Error>> {
+    if options.min_scale > options.max_scale {
+        return Err("min_scale can't be larger than max_scale".into());
+    }
+    formats::check_write(&options.format)?;
+    let opts = options.to_options();
+    let img = if encode::is_jpeg(&options.format) && img.color().has_alpha() {
+        Cow::Owned(color::flatten(img, options.background))
+    } else {
+        Cow::Borrowed(img)
+    };
+    let plan = plan::fit_still(&img, &options.format, None, &opts)?;
+    Ok(Fitted {
+        bytes: plan.bytes,
+        scale: plan.scale,
+        encodes: plan.encodes,
+    })
+}
+
+// Decodes an image held in memory, going by its first bytes rather than a
+// file name. GIFs are decoded frame by frame like animated input and come
+// back as their first frame. Malformed data is an error, never a panic
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    if image::guess_format(bytes)? == ImageFormat::Gif {
+        let anim = Animation::read_gif(bytes)?;
+        let first = anim
+            .frames
+            .into_iter()
+            .next()
+            .ok_or("The GIF has no frames")?;
+        return Ok(DynamicImage::ImageRgba8(first));
+    }
+    Ok(image::load_from_memory(bytes)?)
+}
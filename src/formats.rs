@@ -0,0 +1,205 @@
+use std::{error::Error, path::Path};
+
+use image::ImageFormat;
+
+use crate::backend::BACKENDS;
+
+// What autosize can do with a format in this build. --format, the inputs
+// batch picks up and load_input all go by this, so a format that isn't
+// compiled in is turned down up front instead of failing at the first
+// candidate
+pub struct Format {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub read: bool,
+    pub write: bool,
+    // Knobs the output has: a quality to trade for bytes, a way to keep
+    // every pixel, more than one frame, see-through pixels
+    pub quality: bool,
+    pub lossless: bool,
+    pub animation: bool,
+    pub alpha: bool,
+    // How to get it when it isn't compiled in
+    pub needs: Option<&'static str>,
+}
+
+// Formats the image crate handles, as far as its features in this build go
+fn library(
+    format: ImageFormat,
+    name: &'static str,
+    (quality, lossless, alpha): (bool, bool, bool),
+    needs: &'static str,
+) -> Format {
+    let read = format.can_read() && format.reading_enabled();
+    let write = format.can_write() && format.writing_enabled();
+    Format {
+        name,
+        extensions: format.extensions_str(),
+        read,
+        write,
+        quality,
+        lossless,
+        animation: false,
+        alpha,
+        needs: (!read && !write).then_some(needs),
+    }
+}
+
+pub fn all() -> Vec<Format> {
+    let jxl = cfg!(feature = "jxl");
+    vec![
+        library(ImageFormat::Jpeg, "JPEG", (true, false, false), ""),
+        library(ImageFormat::Png, "PNG", (false, true, true), ""),
+        Format {
+            animation: true,
+            ..library(ImageFormat::Gif, "GIF", (false, false, true), "")
+        },
+        library(ImageFormat::WebP, "WebP", (false, true, true), ""),
+        Format {
+            name: "JPEG XL",
+            extensions: &["jxl"],
+            read: jxl,
+            write: jxl,
+            quality: true,
+            lossless: true,
+            animation: false,
+            alpha: true,
+            needs: (!jxl).then_some("`--features jxl` and libjxl's cjxl and djxl"),
+        },
+        library(
+            ImageFormat::Avif,
+            "AVIF",
+            (true, false, true),
+            "the image crate's avif feature",
+        ),
+        library(ImageFormat::Ico, "ICO", (false, true, true), ""),
+        library(ImageFormat::Qoi, "QOI", (false, true, true), ""),
+        library(ImageFormat::Bmp, "BMP", (false, true, true), ""),
+        library(ImageFormat::Tiff, "TIFF", (false, true, true), ""),
+        library(ImageFormat::Tga, "TGA", (false, true, true), ""),
+        library(ImageFormat::Pnm, "PNM", (false, true, false), ""),
+        library(ImageFormat::Farbfeld, "Farbfeld", (false, true, true), ""),
+        library(ImageFormat::OpenExr, "OpenEXR", (false, true, true), ""),
+        library(ImageFormat::Hdr, "Radiance HDR", (false, true, false), ""),
+    ]
+}
+
+pub fn find(ext: &str) -> Option<Format> {
+    let ext = ext.trim_start_matches('.');
+    all()
+        .into_iter()
+        .find(|f| f.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+// Whether batch picks the file up as an image
+pub fn readable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| find(&ext.to_string_lossy()))
+        .is_some_and(|f| f.read)
+}
+
+// Ok when the build can `what` (read or write) the format ext is for
+fn check(ext: &str, what: &str, can: fn(&Format) -> bool) -> Result<(), Box<dyn Error>> {
+    let format = find(ext).ok_or_else(|| {
+        format!(
+            "autosize doesn't know the format '{}' (see `autosize formats`)",
+            ext
+        )
+    })?;
+    if can(&format) {
+        return Ok(());
+    }
+    let needs = match format.needs {
+        Some(needs) => format!(", it needs {}", needs),
+        None => String::new(),
+    };
+    Err(format!(
+        "This build of autosize can't {} {}{} (see `autosize formats`)",
+        what, format.name, needs
+    )
+    .into())
+}
+
+pub fn check_read(ext: &str) -> Result<(), Box<dyn Error>> {
+    check(ext, "read", |f| f.read)
+}
+
+pub fn check_write(ext: &str) -> Result<(), Box<dyn Error>> {
+    check(ext, "write", |f| f.write)
+}
+
+// `autosize formats`: which formats this build reads and writes, what each
+// can do, and the encoders behind them
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(arg) = args.first() {
+        return Err(format!("Unknown argument '{}'", arg).into());
+    }
+    let mark = |b: bool| if b { "yes" } else { "-" };
+    let formats = all();
+    let rows = formats
+        .iter()
+        .map(|f| {
+            [
+                f.name.to_string(),
+                f.extensions.join(", "),
+                mark(f.read).to_string(),
+                mark(f.write).to_string(),
+                mark(f.quality).to_string(),
+                mark(f.lossless).to_string(),
+                mark(f.animation).to_string(),
+                mark(f.alpha).to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        "Format",
+        "Extensions",
+        "Read",
+        "Write",
+        "Quality",
+        "Lossless",
+        "Animation",
+        "Alpha",
+    ];
+    let widths = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|r| r[i].len())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let line = |cells: &[&str]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", line(&header));
+    for row in &rows {
+        println!(
+            "{}",
+            line(&row.iter().map(String::as_str).collect::<Vec<_>>())
+        );
+    }
+    println!("\nEncoders, the first that fits the settings is used:");
+    for backend in BACKENDS {
+        println!("  {}: {}", backend.name(), backend.formats().join(", "));
+    }
+    let missing = formats
+        .iter()
+        .filter_map(|f| f.needs.map(|needs| format!("  {}: {}", f.name, needs)))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        println!("\nNot in this build:");
+        for line in missing {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
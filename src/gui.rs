@@ -0,0 +1,304 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use eframe::egui::{self, ColorImage, TextureHandle, TextureOptions};
+use image::{DynamicImage, RgbaImage};
+
+use crate::{
+    backup, encode, fit, options::Options, output_name, output_stem, settings::LastSettings, units,
+    FitOptions, Fitted,
+};
+
+// Targets offered in the window, next to a box for any other size
+const PRESETS: [(&str, u64); 5] = [
+    ("100 KB", 100_000),
+    ("500 KB", 500_000),
+    ("1 MB", 1_000_000),
+    ("8 MB (Discord)", 8_000_000),
+    ("25 MB (email)", 25_000_000),
+];
+
+// Longest side of the before/after previews, in pixels
+const PREVIEW: u32 = 320;
+
+// A file dropped on the window and how far it's got
+struct Job {
+    path: PathBuf,
+    target: u64,
+    original: u64,
+    status: Status,
+}
+
+enum Status {
+    Waiting,
+    Fitting,
+    Done {
+        output: PathBuf,
+        size: u64,
+        scale: f64,
+        before: TextureHandle,
+        after: TextureHandle,
+    },
+    Failed(String),
+}
+
+// What the worker thread sends back, by index into App::jobs
+enum Update {
+    Started(usize),
+    Fitted(usize, Result<Finished, String>),
+}
+
+struct Finished {
+    output: PathBuf,
+    size: u64,
+    scale: f64,
+    before: RgbaImage,
+    after: RgbaImage,
+}
+
+struct App {
+    jobs: Vec<Job>,
+    target: u64,
+    custom: String,
+    selected: usize,
+    queue: Sender<(usize, PathBuf, u64)>,
+    updates: Receiver<Update>,
+}
+
+fn preview(img: &DynamicImage) -> RgbaImage {
+    img.thumbnail(PREVIEW, PREVIEW).to_rgba8()
+}
+
+// Fits one file through the same library call as `autosize::fit`, then
+// names and writes the output the way the command line does
+fn fit_file(path: &Path, target: u64, opts: &Options) -> Result<Finished, Box<dyn Error>> {
+    let img = encode::open(path)?;
+    let ext = opts.format.clone().unwrap_or_else(|| {
+        path.extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    });
+    let options = FitOptions::new(opts.aim(target))
+        .format(&ext)
+        .background(opts.background)
+        .presearch(opts.presearch)
+        .reproducible(opts.reproducible);
+    let Fitted { bytes, scale, .. } = fit(&img, &options)?;
+    let output = output_name(&output_stem(path, opts), &bytes, &ext, opts);
+    backup::write_output(&output, &bytes, opts)?;
+    let after = encode::open(&output)?;
+    Ok(Finished {
+        output,
+        size: bytes.len() as u64,
+        scale,
+        before: preview(&img),
+        after: preview(&after),
+    })
+}
+
+// One file at a time in the background, so the window stays responsive and
+// the searches don't fight over the cores
+fn worker(
+    ctx: egui::Context,
+    opts: Options,
+    queue: Receiver<(usize, PathBuf, u64)>,
+    updates: Sender<Update>,
+) {
+    for (i, path, target) in queue {
+        let _ = updates.send(Update::Started(i));
+        ctx.request_repaint();
+        let result = fit_file(&path, target, &opts).map_err(|e| e.to_string());
+        let _ = updates.send(Update::Fitted(i, result));
+        ctx.request_repaint();
+    }
+}
+
+fn texture(ctx: &egui::Context, name: String, img: &RgbaImage) -> TextureHandle {
+    let size = [img.width() as usize, img.height() as usize];
+    let pixels = ColorImage::from_rgba_unmultiplied(size, img.as_raw());
+    ctx.load_texture(name, pixels, TextureOptions::default())
+}
+
+impl App {
+    fn new(ctx: &egui::Context, opts: Options) -> App {
+        let (queue, jobs) = mpsc::channel();
+        let (sender, updates) = mpsc::channel();
+        // Starts on the last target used from the command line
+        let target = LastSettings::load().target;
+        let ctx = ctx.clone();
+        thread::spawn(move || worker(ctx, opts, jobs, sender));
+        App {
+            jobs: Vec::new(),
+            target,
+            custom: units::format_size(target),
+            selected: PRESETS
+                .iter()
+                .position(|(_, size)| *size == target)
+                .unwrap_or(PRESETS.len()),
+            queue,
+            updates,
+        }
+    }
+
+    fn add(&mut self, path: PathBuf) {
+        let i = self.jobs.len();
+        let original = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let _ = self.queue.send((i, path.clone(), self.target));
+        self.jobs.push(Job {
+            path,
+            target: self.target,
+            original,
+            status: Status::Waiting,
+        });
+    }
+
+    fn receive(&mut self, ctx: &egui::Context) {
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                Update::Started(i) => self.jobs[i].status = Status::Fitting,
+                Update::Fitted(i, Ok(done)) => {
+                    self.jobs[i].status = Status::Done {
+                        before: texture(ctx, format!("before{}", i), &done.before),
+                        after: texture(ctx, format!("after{}", i), &done.after),
+                        output: done.output,
+                        size: done.size,
+                        scale: done.scale,
+                    }
+                }
+                Update::Fitted(i, Err(e)) => self.jobs[i].status = Status::Failed(e),
+            }
+        }
+    }
+
+    fn target_picker(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Target:");
+            for (i, (name, size)) in PRESETS.iter().enumerate() {
+                if ui.selectable_label(self.selected == i, *name).clicked() {
+                    self.selected = i;
+                    self.target = *size;
+                    self.custom = units::format_size(*size);
+                }
+            }
+            let custom = ui.add(egui::TextEdit::singleline(&mut self.custom).desired_width(80.0));
+            if custom.changed() {
+                if let Some(size) = units::parse_size(&self.custom) {
+                    self.selected = PRESETS.len();
+                    self.target = size;
+                }
+            }
+        });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        self.receive(ctx);
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            if let Some(path) = file.path {
+                self.add(path);
+            }
+        }
+        egui::TopBottomPanel::top("target").show(ctx, |ui| {
+            self.target_picker(ui);
+            let done = self
+                .jobs
+                .iter()
+                .filter(|j| matches!(j.status, Status::Done { .. } | Status::Failed(_)))
+                .count();
+            if !self.jobs.is_empty() {
+                ui.add(
+                    egui::ProgressBar::new(done as f32 / self.jobs.len() as f32).text(format!(
+                        "{} of {} done",
+                        done,
+                        self.jobs.len()
+                    )),
+                );
+            }
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.jobs.is_empty() {
+                ui.centered_and_justified(|ui| {
+                    ui.heading(format!(
+                        "Drop images here to fit them in {}",
+                        units::format_size(self.target)
+                    ))
+                });
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for job in self.jobs.iter().rev() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.strong(job.path.display().to_string());
+                        ui.label(format!("to {}", units::format_size(job.target)));
+                    });
+                    match &job.status {
+                        Status::Waiting => {
+                            ui.label("Waiting");
+                        }
+                        Status::Fitting => {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label("Fitting...");
+                            });
+                        }
+                        Status::Failed(e) => {
+                            ui.colored_label(egui::Color32::RED, format!("Failed: {}", e));
+                        }
+                        Status::Done {
+                            output,
+                            size,
+                            scale,
+                            before,
+                            after,
+                        } => {
+                            ui.label(format!(
+                                "{} -> {} at a scale of {:.2}, saved as {}",
+                                units::format_size(job.original),
+                                units::format_size(*size),
+                                scale,
+                                output.display()
+                            ));
+                            ui.horizontal(|ui| {
+                                let max = egui::vec2(PREVIEW as f32, PREVIEW as f32);
+                                ui.add(egui::Image::new(before).max_size(max));
+                                ui.add(egui::Image::new(after).max_size(max));
+                            });
+                        }
+                    }
+                }
+            });
+        });
+    }
+}
+
+// `autosize gui [options]`: a window to drop files on, fitted one after
+// another to the picked target. The usual options (--output-dir, --format
+// and so on) apply to everything fitted from it
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = Options {
+        // The window has its own previews, --review would ask in a terminal
+        // that may not even be there
+        review: false,
+        ..Options::parse(args)?
+    };
+    let native = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size([760.0, 560.0])
+            .with_drag_and_drop(true),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "autosize",
+        native,
+        Box::new(|cc| Ok(Box::new(App::new(&cc.egui_ctx, opts)))),
+    )
+    .map_err(|e| e.to_string().into())
+}
@@ -0,0 +1,188 @@
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{settings, units};
+
+// One finished run. Stored as a tab-separated line in .autosize/history:
+// time, input, output, target, size, scale, milliseconds, flags, encodes,
+// iteration limit, byte threshold, CPU milliseconds, peak memory. Runs
+// recorded before the last five existed leave them out
+pub struct Entry {
+    pub time: u64,
+    pub input: String,
+    pub output: String,
+    pub target: u64,
+    pub size: u64,
+    pub scale: f64,
+    pub millis: u128,
+    pub flags: String,
+    // Candidates the scale search encoded, None when the output didn't come
+    // from a search (metadata stripping, JPEG XL recompression, ICO)
+    pub encodes: Option<u32>,
+    pub iters: Option<i32>,
+    pub byte_halt: Option<u64>,
+    // CPU time over all threads, and the most memory held in bytes, where
+    // the system tells (see usage.rs)
+    pub cpu_millis: Option<u128>,
+    pub peak: Option<u64>,
+}
+
+fn history_path() -> PathBuf {
+    settings::data_dir().join("history")
+}
+
+impl Entry {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn parse(line: &str) -> Option<Entry> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() < 8 {
+            return None;
+        }
+        Some(Entry {
+            time: fields[0].parse().ok()?,
+            input: fields[1].to_string(),
+            output: fields[2].to_string(),
+            target: fields[3].parse().ok()?,
+            size: fields[4].parse().ok()?,
+            scale: fields[5].parse().ok()?,
+            millis: fields[6].parse().ok()?,
+            flags: fields[7].to_string(),
+            encodes: fields.get(8).and_then(|f| f.parse().ok()),
+            iters: fields.get(9).and_then(|f| f.parse().ok()),
+            byte_halt: fields.get(10).and_then(|f| f.parse().ok()),
+            cpu_millis: fields.get(11).and_then(|f| f.parse().ok()),
+            peak: fields.get(12).and_then(|f| f.parse().ok()),
+        })
+    }
+
+    // Every recorded run, oldest first
+    pub fn load_all() -> Vec<Entry> {
+        fs::read_to_string(history_path())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(Entry::parse)
+            .collect()
+    }
+
+    pub fn append(&self) -> io::Result<()> {
+        let path = history_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.time,
+            self.input,
+            self.output,
+            self.target,
+            self.size,
+            self.scale,
+            self.millis,
+            self.flags,
+            self.encodes.map(|n| n.to_string()).unwrap_or_default(),
+            self.iters.map(|n| n.to_string()).unwrap_or_default(),
+            self.byte_halt.map(|n| n.to_string()).unwrap_or_default(),
+            self.cpu_millis.map(|n| n.to_string()).unwrap_or_default(),
+            self.peak.map(|n| n.to_string()).unwrap_or_default()
+        )
+    }
+}
+
+// Unix seconds as `YYYY-MM-DD HH:MM` UTC (Howard Hinnant's civil_from_days)
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    )
+}
+
+// `autosize history [<filter>] [--last <n>]`, where the filter matches any
+// part of the input name, output name or flags
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut filter = None;
+    let mut last = 20usize;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        match flag {
+            "--last" => {
+                let value = inline
+                    .or_else(|| args.next().cloned())
+                    .ok_or("--last expects a value")?;
+                last = value
+                    .parse()
+                    .map_err(|_| format!("--last expects a number, got '{}'", value))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument '{}'", arg).into()),
+            _ => filter = Some(arg.to_lowercase()),
+        }
+    }
+    let entries = Entry::load_all()
+        .into_iter()
+        .filter(|e| {
+            filter.as_ref().is_none_or(|f| {
+                [&e.input, &e.output, &e.flags]
+                    .iter()
+                    .any(|s| s.to_lowercase().contains(f.as_str()))
+            })
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        println!("No runs recorded yet");
+        return Ok(());
+    }
+    for e in &entries[entries.len().saturating_sub(last)..] {
+        let cpu = e
+            .cpu_millis
+            .map(|ms| format!(", {:.2}s CPU", ms as f64 / 1000.0))
+            .unwrap_or_default();
+        let peak = e
+            .peak
+            .map(|bytes| format!(", {} peak", units::format_size(bytes)))
+            .unwrap_or_default();
+        println!(
+            "{}  {} -> {} ({} of {}, scale {:.2}, {}ms{}{}){}{}",
+            format_time(e.time),
+            e.input,
+            e.output,
+            units::format_size(e.size),
+            units::format_size(e.target),
+            e.scale,
+            e.millis,
+            cpu,
+            peak,
+            if e.flags.is_empty() { "" } else { "  " },
+            e.flags
+        );
+    }
+    Ok(())
+}
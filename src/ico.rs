@@ -0,0 +1,111 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::ico::{IcoEncoder, IcoFrame},
+    imageops, ColorType, DynamicImage, RgbaImage,
+};
+
+use crate::{backup, encode, messages::tr, options::Options, output_name, resample, units};
+
+// Favicon sizes when --ico-sizes isn't given
+pub const DEFAULT_SIZES: [u32; 3] = [16, 32, 48];
+
+// A square icon with the image fitted inside and centred on transparency
+fn icon(img: &DynamicImage, size: u32) -> RgbaImage {
+    let fitted = resample::image(img, size, size, imageops::FilterType::Lanczos3).to_rgba8();
+    let mut square = RgbaImage::new(size, size);
+    let (x, y) = ((size - fitted.width()) / 2, (size - fitted.height()) / 2);
+    imageops::replace(&mut square, &fitted, x as i64, y as i64);
+    square
+}
+
+fn encode_ico(frames: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let frames = frames
+        .iter()
+        .map(|(size, png)| IcoFrame::with_encoded(png.as_slice(), *size, *size, ColorType::Rgba8))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bytes = Vec::new();
+    IcoEncoder::new(&mut bytes).encode_images(&frames)?;
+    Ok(bytes)
+}
+
+// ICO sizes are fixed, so instead of searching for a scale this embeds every
+// requested size as an optimized PNG and drops the largest ones until the
+// whole file fits the target
+pub fn fit(
+    img: &DynamicImage,
+    iname: &Path,
+    opts: &Options,
+) -> Result<(f64, PathBuf), Box<dyn Error>> {
+    let mut sizes = opts.ico_sizes.clone();
+    sizes.sort_unstable();
+    sizes.dedup();
+    let mut frames = Vec::new();
+    for size in sizes {
+        let square = DynamicImage::ImageRgba8(icon(img, size));
+        let png = encode::encode(&square, "png", &opts.encoder)?;
+        frames.push((size, encode::optimize_png(png, &opts.encoder)?));
+    }
+    let mut bytes = encode_ico(&frames)?;
+    while bytes.len() as u64 > opts.target && frames.len() > 1 {
+        let (size, _) = frames.pop().unwrap_or_default();
+        if !opts.quiet {
+            println!("{}", tr!(IconDropped, size, size));
+        }
+        bytes = encode_ico(&frames)?;
+    }
+    // Better no file than one over the target
+    if bytes.len() as u64 > opts.target {
+        let size = frames.first().map(|(size, _)| *size).unwrap_or(0);
+        return Err(tr!(IconTooLarge, size, size, units::format_size(opts.target)).into());
+    }
+    if !opts.quiet {
+        let sizes = frames
+            .iter()
+            .map(|(size, _)| size.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("{}", tr!(IconSizes, sizes));
+    }
+    let output = output_name(iname, &bytes, "ico", opts);
+    backup::write_output(&output, &bytes, opts)?;
+    let largest = frames.last().map(|(size, _)| *size).unwrap_or(0);
+    Ok((
+        largest as f64 / img.width().max(img.height()) as f64,
+        output,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::temp_path;
+
+    #[test]
+    fn drops_large_icons_and_never_writes_over_the_target() {
+        let dir = temp_path("ico", "d");
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 4) as u8, (x ^ y) as u8, 255])
+        }));
+        let opts = |target, sizes: &[u32]| Options {
+            target,
+            quiet: true,
+            ico_sizes: sizes.to_vec(),
+            ..Options::default()
+        };
+        let (_, alone) = fit(&img, &dir.join("alone"), &opts(u64::MAX, &[16])).unwrap();
+        let smallest = std::fs::metadata(&alone).unwrap().len();
+        // Only room for the 16x16 one, the rest are dropped
+        let (scale, output) = fit(&img, &dir.join("icon"), &opts(smallest, &[16, 32, 48])).unwrap();
+        assert_eq!(scale, 16.0 / 64.0);
+        assert_eq!(std::fs::metadata(&output).unwrap().len(), smallest);
+        // No room even for that, so nothing is written
+        assert!(fit(&img, &dir.join("tiny"), &opts(smallest - 1, &[16, 32])).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
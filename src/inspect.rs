@@ -0,0 +1,140 @@
+use std::{collections::HashSet, error::Error, fs, path::Path};
+
+use image::DynamicImage;
+
+use crate::{
+    animation::{self, Animation},
+    color, encode, find_compression_ratio, source, units,
+};
+
+// Markers that give away metadata wherever they sit in the file, by kind
+const METADATA: [(&str, &[&[u8]]); 3] = [
+    ("EXIF", &[b"Exif\0\0", b"eXIf"]),
+    ("XMP", &[b"http://ns.adobe.com/xap/1.0/"]),
+    ("text", &[b"tEXt", b"iTXt", b"zTXt"]),
+];
+
+// Distinct colours stop being counted past this, only whether they'd fit a
+// palette matters
+const COLOUR_LIMIT: usize = 1 << 16;
+
+fn colours(img: &DynamicImage) -> usize {
+    let mut seen = HashSet::new();
+    for p in img.to_rgba8().pixels() {
+        seen.insert(p.0);
+        if seen.len() > COLOUR_LIMIT {
+            break;
+        }
+    }
+    seen.len()
+}
+
+// How a QOI size over the raw size reads, see find_compression_ratio. QOI
+// only squeezes out flat areas and repeats, so it sorts images by how much
+// of either they have
+fn rating(ratio: f64) -> (&'static str, &'static str) {
+    match ratio {
+        r if r < 0.15 => (
+            "very compressible",
+            "flat colour or text, a palette or PNG should hold up well",
+        ),
+        r if r < 0.35 => (
+            "compressible",
+            "some flat areas, most targets should fit without much downscaling",
+        ),
+        r if r < 0.6 => (
+            "average",
+            "photo-like, a lower JPEG quality will do most of the work",
+        ),
+        _ => (
+            "hard to compress",
+            "noise or fine detail, expect small targets to need downscaling",
+        ),
+    }
+}
+
+// `autosize inspect <file>`: what's in an image before fitting it, to help
+// pick a target and strategies: its format, size, depth, frames, colours,
+// metadata and how well it's likely to compress
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let file = match args {
+        [file] if !file.starts_with("--") => Path::new(file),
+        [arg, ..] if arg.starts_with("--") => {
+            return Err(format!("Unknown argument '{}'", arg).into())
+        }
+        _ => return Err("Usage: autosize inspect <file>".into()),
+    };
+    let bytes = fs::read(file)?;
+    let format = source::format_of(file)
+        .or_else(|| file.extension().map(|e| e.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+        .to_ascii_lowercase();
+    let anim = match format == "gif" {
+        true => Some(Animation::load_gif(file)?),
+        false => None,
+    };
+    let img = match &anim {
+        Some(anim) => DynamicImage::ImageRgba8(anim.frames[0].clone()),
+        None => encode::open(file)?,
+    };
+    let color = img.color();
+    println!("{}", file.display());
+    println!("  Format:      {}", format);
+    println!("  File size:   {}", units::format_size(bytes.len() as u64));
+    println!("  Dimensions:  {}x{}", img.width(), img.height());
+    println!(
+        "  Bit depth:   {} bits x {} channels{}",
+        color.bits_per_pixel() / color.channel_count() as u16,
+        color.channel_count(),
+        if color.has_alpha() {
+            ", with alpha"
+        } else {
+            ""
+        }
+    );
+    if let Some(anim) = &anim {
+        let seconds = anim.delays.iter().map(|&d| d as f64).sum::<f64>() / 100.0;
+        println!("  Frames:      {} over {:.2}s", anim.len(), seconds);
+    }
+    let n = colours(&img);
+    println!(
+        "  Colours:     {}",
+        match n {
+            n if n > COLOUR_LIMIT => format!("over {}", COLOUR_LIMIT),
+            n if n <= 256 => format!("{}, fits a palette", n),
+            n => n.to_string(),
+        }
+    );
+    let mut metadata = METADATA
+        .iter()
+        .filter(|(_, markers)| {
+            markers
+                .iter()
+                .any(|m| bytes.windows(m.len()).any(|w| w == *m))
+        })
+        .map(|(kind, _)| *kind)
+        .collect::<Vec<_>>();
+    if color::read_icc(file).is_some() {
+        metadata.push("ICC profile");
+    }
+    println!(
+        "  Metadata:    {}",
+        match metadata.is_empty() {
+            true => "none".to_string(),
+            false => metadata.join(", "),
+        }
+    );
+    println!(
+        "  Entropy:     {:.2} bits per pixel",
+        animation::entropy(&img.to_rgba8())
+    );
+    let ratio = find_compression_ratio(&img)?;
+    let (rating, hint) = rating(ratio);
+    println!(
+        "  Compresses:  {} ({:.0}% of raw losslessly), {}",
+        rating,
+        ratio * 100.0,
+        hint
+    );
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::{error::Error, io::Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+use image::DynamicImage;
+
+// Adam7 passes as (x start, y start, x step, y step)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+// Reorders indexed GIF rows into the 4-pass interlaced order
+pub fn gif_rows(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len());
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        for y in (start..height).step_by(step) {
+            out.extend_from_slice(&pixels[y * width..(y + 1) * width]);
+        }
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Filters one scanline with whichever PNG filter gives the smallest sum of
+// absolute residuals, the usual heuristic for picking filters per row
+fn filter_row(row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+    for kind in 0u8..5 {
+        let filtered = row
+            .iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                match kind {
+                    0 => x,
+                    1 => x.wrapping_sub(a),
+                    2 => x.wrapping_sub(b),
+                    3 => x.wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+                    _ => x.wrapping_sub(paeth(a, b, c)),
+                }
+            })
+            .collect::<Vec<_>>();
+        let cost = filtered
+            .iter()
+            .map(|&v| (v as i8).unsigned_abs() as u64)
+            .sum();
+        if best.as_ref().is_none_or(|(c, _, _)| cost < *c) {
+            best = Some((cost, kind, filtered));
+        }
+    }
+    if let Some((_, kind, filtered)) = best {
+        out.push(kind);
+        out.extend_from_slice(&filtered);
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+// Writes an Adam7-interlaced 8-bit PNG, which the png crate can't produce
+pub fn encode_png(img: &DynamicImage) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let (color_type, bpp, raw) = match img {
+        DynamicImage::ImageLuma8(i) => (0u8, 1, i.as_raw().clone()),
+        DynamicImage::ImageLumaA8(i) => (4, 2, i.as_raw().clone()),
+        DynamicImage::ImageRgb8(i) => (2, 3, i.as_raw().clone()),
+        _ => (6, 4, img.to_rgba8().into_raw()),
+    };
+    let mut filtered = Vec::new();
+    for (x0, y0, dx, dy) in ADAM7 {
+        if x0 >= w || y0 >= h {
+            continue;
+        }
+        let pass_w = (w - x0).div_ceil(dx);
+        let mut prev = vec![0u8; pass_w * bpp];
+        for y in (y0..h).step_by(dy) {
+            let row = (x0..w)
+                .step_by(dx)
+                .flat_map(|x| &raw[(y * w + x) * bpp..(y * w + x + 1) * bpp])
+                .copied()
+                .collect::<Vec<_>>();
+            filter_row(&row, &prev, bpp, &mut filtered);
+            prev = row;
+        }
+    }
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::best());
+    zlib.write_all(&filtered)?;
+    let idat = zlib.finish()?;
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(w as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(h as u32).to_be_bytes());
+    // Bit depth, colour type, compression, filter method, interlace (Adam7)
+    ihdr.extend_from_slice(&[8, color_type, 0, 0, 1]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &idat);
+    write_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
@@ -0,0 +1,227 @@
+use qcms::Profile;
+
+const SOS: u8 = 0xDA;
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const APP14: u8 = 0xEE;
+const COM: u8 = 0xFE;
+
+// An ICC profile other than sRGB changes how the pixels look, so it has to
+// stay. Profiles split over several APP2 segments are never sRGB
+fn is_srgb_icc(segment: &[u8]) -> bool {
+    match segment.strip_prefix(b"ICC_PROFILE\0") {
+        Some([1, 1, icc @ ..]) => Profile::new_from_slice(icc, false).is_some_and(|p| p.is_sRGB()),
+        _ => false,
+    }
+}
+
+// Drops metadata segments (EXIF, XMP, comments, thumbnails, sRGB profiles)
+// while copying the compressed data untouched, so the pixels are exactly
+// the same. JFIF (APP0) and Adobe (APP14) stay since decoders use them to
+// pick the colour transform. Returns None for anything that doesn't parse
+pub fn strip_markers(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut out = vec![0xFF, 0xD8];
+    let mut i = 2;
+    loop {
+        // Markers may be padded with any number of 0xFF fill bytes
+        while *bytes.get(i)? == 0xFF && *bytes.get(i + 1)? == 0xFF {
+            i += 1;
+        }
+        if *bytes.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(i + 1)?;
+        let len = u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let segment = bytes.get(i..i + 2 + len)?;
+        if marker == SOS {
+            // Everything from the first scan on is entropy-coded data
+            out.extend_from_slice(&bytes[i..]);
+            return Some(out);
+        }
+        let payload = &segment[4..];
+        let keep = match marker {
+            APP0 | APP14 => true,
+            APP2 => !is_srgb_icc(payload),
+            m if (APP0..=0xEF).contains(&m) || m == COM => false,
+            _ => true,
+        };
+        if keep {
+            out.extend_from_slice(segment);
+        }
+        i += 2 + len;
+    }
+}
+
+// Year and month from a TIFF block's DateTimeOriginal, or its DateTime when
+// the camera didn't write the first. Both are text like `2021:06:30 14:02:11`
+fn tiff_date(tiff: &[u8]) -> Option<(u32, u32)> {
+    let little = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let b = tiff.get(at..at + 2)?;
+        Some(match little {
+            true => u16::from_le_bytes([b[0], b[1]]),
+            false => u16::from_be_bytes([b[0], b[1]]),
+        })
+    };
+    let u32_at = |at: usize| {
+        let b = tiff.get(at..at + 4)?;
+        Some(match little {
+            true => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            false => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    };
+    // The value (or where it is) of a tag in the directory at ifd
+    let find = |ifd: usize, tag: u16| {
+        (0..u16_at(ifd)? as usize)
+            .map(|n| ifd + 2 + n * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+            .and_then(|entry| u32_at(entry + 8))
+    };
+    let ifd0 = u32_at(4)? as usize;
+    let at = find(ifd0, 0x8769)
+        .and_then(|exif| find(exif as usize, 0x9003))
+        .or_else(|| find(ifd0, 0x0132))? as usize;
+    let text = std::str::from_utf8(tiff.get(at..at + 7)?).ok()?;
+    let year = text.get(..4)?.parse().ok()?;
+    let month = text.get(5..7)?.parse().ok()?;
+    (year > 0 && (1..=12).contains(&month)).then_some((year, month))
+}
+
+// Year and month the photo was taken, from its EXIF. bytes only needs to
+// go as far as the first scan
+pub fn exif_date(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        while *bytes.get(i)? == 0xFF && *bytes.get(i + 1)? == 0xFF {
+            i += 1;
+        }
+        let marker = *bytes.get(i + 1)?;
+        let len = u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize;
+        if marker == SOS || len < 2 {
+            return None;
+        }
+        if marker == APP1 {
+            if let Some(tiff) = bytes.get(i + 4..i + 2 + len)?.strip_prefix(b"Exif\0\0") {
+                return tiff_date(tiff);
+            }
+        }
+        i += 2 + len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A TIFF block in either byte order with DateTimeOriginal in an EXIF
+    // directory and DateTime in the first, when given
+    fn tiff(little: bool, original: Option<&str>, modified: Option<&str>) -> Vec<u8> {
+        let u16_bytes = |v: u16| match little {
+            true => v.to_le_bytes(),
+            false => v.to_be_bytes(),
+        };
+        let u32_bytes = |v: u32| match little {
+            true => v.to_le_bytes(),
+            false => v.to_be_bytes(),
+        };
+        let entries = original.is_some() as usize + modified.is_some() as usize;
+        let exif_at = 8 + 2 + 12 * entries + 4;
+        let strings_at = exif_at + original.map_or(0, |_| 2 + 12 + 4);
+        let mut out = match little {
+            true => b"II".to_vec(),
+            false => b"MM".to_vec(),
+        };
+        out.extend(u16_bytes(42));
+        out.extend(u32_bytes(8));
+        let entry = |out: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            out.extend(u16_bytes(tag));
+            out.extend(u16_bytes(kind));
+            out.extend(u32_bytes(count));
+            out.extend(u32_bytes(value));
+        };
+        let (mut strings, mut at) = (Vec::new(), strings_at);
+        let mut string = |text: &str| {
+            strings.extend(text.as_bytes());
+            strings.push(0);
+            at += text.len() + 1;
+            (at - text.len() - 1) as u32
+        };
+        out.extend(u16_bytes(entries as u16));
+        if let Some(modified) = modified {
+            entry(&mut out, 0x0132, 2, 20, string(modified));
+        }
+        let original = original.map(&mut string);
+        if original.is_some() {
+            entry(&mut out, 0x8769, 4, 1, exif_at as u32);
+        }
+        out.extend(u32_bytes(0));
+        if let Some(original) = original {
+            out.extend(u16_bytes(1));
+            entry(&mut out, 0x9003, 2, 20, original);
+            out.extend(u32_bytes(0));
+        }
+        out.extend(strings);
+        out
+    }
+
+    // The start of a JPEG with the TIFF block in its APP1 segment
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8, 0xFF, APP0, 0, 4, 0, 0, 0xFF, APP1];
+        out.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        out.extend(b"Exif\0\0");
+        out.extend(tiff);
+        out.extend([0xFF, SOS, 0, 2]);
+        out
+    }
+
+    #[test]
+    fn dates_read_in_either_byte_order() {
+        for little in [true, false] {
+            let both = tiff(
+                little,
+                Some("2021:06:30 14:02:11"),
+                Some("2023:01:02 00:00:00"),
+            );
+            assert_eq!(tiff_date(&both), Some((2021, 6)));
+            assert_eq!(exif_date(&jpeg(&both)), Some((2021, 6)));
+            // Without DateTimeOriginal the DateTime does
+            let modified = tiff(little, None, Some("2019:12:24 18:00:00"));
+            assert_eq!(tiff_date(&modified), Some((2019, 12)));
+            assert_eq!(tiff_date(&tiff(little, None, None)), None);
+            // Blank dates, as some cameras write, aren't dates
+            let blank = tiff(little, Some("0000:00:00 00:00:00"), None);
+            assert_eq!(tiff_date(&blank), None);
+        }
+    }
+
+    #[test]
+    fn truncated_blocks_give_no_date() {
+        for little in [true, false] {
+            let full = tiff(little, Some("2021:06:30 14:02:11"), None);
+            for len in 0..full.len() - 13 {
+                assert_eq!(tiff_date(&full[..len]), None, "cut at {}", len);
+            }
+            let jpeg = jpeg(&full);
+            let segment_end = jpeg.len() - 4;
+            for len in 0..segment_end {
+                assert_eq!(exif_date(&jpeg[..len]), None, "cut at {}", len);
+            }
+        }
+        assert_eq!(tiff_date(b"XX\0*\0\0\0\x08"), None);
+        assert_eq!(exif_date(b"\x89PNG\r\n"), None);
+    }
+}
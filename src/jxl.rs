@@ -0,0 +1,72 @@
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use image::{DynamicImage, ImageOutputFormat};
+
+use crate::{encode::EncoderSettings, tools::temp_path};
+
+// JPEG XL goes through the reference cjxl/djxl tools, which need to be on
+// the PATH. libjxl is a large C++ build, the tools are a package away
+
+fn run(tool: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = Command::new(tool)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| format!("Couldn't run {} (is libjxl installed?): {}", tool, e))?;
+    if !status.success() {
+        return Err(format!("{} failed with {}", tool, status).into());
+    }
+    Ok(())
+}
+
+fn cjxl(input: &Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = temp_path("jxl_out", "jxl");
+    let (input, output_str) = (input.to_string_lossy(), output.to_string_lossy());
+    let mut all = vec![input.as_ref(), output_str.as_ref(), "--quiet"];
+    all.extend_from_slice(args);
+    let result = run("cjxl", &all).and_then(|()| Ok(fs::read(&output)?));
+    let _ = fs::remove_file(&output);
+    result
+}
+
+// Lossy (or, at distance 0, lossless) encode from the decoded pixels
+pub fn encode(img: &DynamicImage, settings: &EncoderSettings) -> Result<Vec<u8>, Box<dyn Error>> {
+    // PNG keeps the pixels exact on their way to cjxl
+    let input = temp_path("jxl_in", "png");
+    let mut file = fs::File::create(&input)?;
+    img.write_to(&mut file, ImageOutputFormat::Png)?;
+    let (distance, effort) = (
+        settings.jxl_distance.to_string(),
+        settings.jxl_effort.to_string(),
+    );
+    let result = cjxl(&input, &["-d", &distance, "-e", &effort]);
+    let _ = fs::remove_file(&input);
+    result
+}
+
+// Repacks an existing JPEG's coefficients as JPEG XL, typically ~20% smaller
+// with no quality loss, and reversible back to the identical JPEG
+pub fn recompress_jpeg(jpeg: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    cjxl(jpeg, &["--lossless_jpeg=1"])
+}
+
+pub fn decode(path: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+    let output = temp_path("jxl_dec", "png");
+    run(
+        "djxl",
+        &[
+            path.to_string_lossy().as_ref(),
+            output.to_string_lossy().as_ref(),
+            "--quiet",
+        ],
+    )?;
+    let img = image::open(&output);
+    let _ = fs::remove_file(&output);
+    Ok(img?)
+}
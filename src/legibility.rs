@@ -0,0 +1,68 @@
+use image::{imageops, GrayImage, RgbaImage};
+
+// Gradient strength (0-255 luma steps) that counts as a hard edge
+const EDGE_THRESHOLD: i16 = 64;
+// Fraction of hard-edge pixels above which the image is treated as text-heavy
+const TEXT_EDGE_DENSITY: f64 = 0.04;
+// Strokes closer together than this many pixels blur into each other
+const MIN_STROKE_GAP: f64 = 2.0;
+
+#[derive(Clone, Copy)]
+pub enum TextCheck {
+    Off,
+    Warn,
+    Block,
+}
+
+// Horizontal gradient of the luma, which responds to the vertical strokes
+// that make up most glyphs
+fn edge_map(gray: &GrayImage) -> Vec<bool> {
+    let (w, h) = gray.dimensions();
+    let mut edges = vec![false; w as usize * h as usize];
+    for y in 0..h {
+        for x in 1..w {
+            let diff = gray.get_pixel(x, y)[0] as i16 - gray.get_pixel(x - 1, y)[0] as i16;
+            edges[y as usize * w as usize + x as usize] = diff.abs() >= EDGE_THRESHOLD;
+        }
+    }
+    edges
+}
+
+// Estimates the smallest scale that keeps text-like detail legible, or None
+// when the image doesn't look like it contains text.
+// Text shows up as dense, evenly spaced hard edges; the median spacing of
+// those edges along each row approximates the stroke width, which must stay
+// at least MIN_STROKE_GAP pixels apart after resizing
+pub fn min_legible_scale(img: &RgbaImage) -> Option<f64> {
+    let gray = imageops::grayscale(img);
+    let (w, h) = gray.dimensions();
+    if w < 2 || h == 0 {
+        return None;
+    }
+    let edges = edge_map(&gray);
+    let density = edges.iter().filter(|&&e| e).count() as f64 / edges.len() as f64;
+    if density < TEXT_EDGE_DENSITY {
+        return None;
+    }
+    let mut gaps = Vec::new();
+    for row in edges.chunks_exact(w as usize) {
+        let mut last = None;
+        for (x, &edge) in row.iter().enumerate() {
+            if edge {
+                if let Some(last) = last {
+                    // Adjacent edge pixels belong to the same transition
+                    if x - last > 1 {
+                        gaps.push(x - last);
+                    }
+                }
+                last = Some(x);
+            }
+        }
+    }
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort_unstable();
+    let median = gaps[gaps.len() / 2] as f64;
+    Some((MIN_STROKE_GAP / median).min(1.0))
+}
@@ -0,0 +1,1348 @@
+use std::{
+    borrow::Cow,
+    env,
+    error::Error,
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use image::{imageops, DynamicImage};
+use rand::{rngs::StdRng, SeedableRng};
+
+mod animation;
+mod attrs;
+mod backend;
+mod backup;
+mod batch;
+mod blurhash;
+mod budget;
+mod check;
+mod checksums;
+mod color;
+mod combined;
+mod compare;
+mod crop;
+mod daemon;
+mod depth;
+mod dither;
+mod encode;
+#[cfg(feature = "faces")]
+mod faces;
+mod fit;
+mod formats;
+#[cfg(feature = "gui")]
+mod gui;
+mod history;
+mod ico;
+mod inspect;
+mod interlace;
+mod jpeg;
+#[cfg(feature = "jxl")]
+mod jxl;
+mod legibility;
+mod lowmem;
+#[cfg(feature = "background")]
+mod matte;
+mod messages;
+mod noise;
+#[cfg(any(feature = "faces", feature = "background"))]
+mod onnx;
+mod options;
+mod oracle;
+mod pipeline;
+mod plan;
+mod precommit;
+mod preview;
+mod profile;
+mod progress;
+mod remote;
+mod resample;
+mod resume;
+mod retry;
+mod review;
+mod settings;
+mod sheet;
+mod shell;
+mod sink;
+mod source;
+mod stabilize;
+mod stats;
+mod strip;
+mod tools;
+#[cfg(feature = "tray")]
+mod tray;
+mod units;
+mod usage;
+pub use fit::{decode, fit, FitOptions, Fitted};
+pub use image;
+pub use units::{format_size, parse_size};
+
+use animation::Animation;
+use encode::{EncoderSettings, Subsampling};
+use legibility::TextCheck;
+use messages::tr;
+use options::Options;
+use settings::LastSettings;
+use sink::OutputSink;
+
+#[allow(dead_code)]
+enum FindType {
+    File,
+    Dir,
+}
+
+// Whether a directory entry is listed at all: dot files are left out with
+// --skip-hidden and symlinks with --no-symlinks
+fn listed(path: &Path, opts: &Options) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let link = path
+        .symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink());
+    !(hidden && opts.skip_hidden || link && !opts.follow_symlinks)
+}
+
+fn list_dir<P: AsRef<Path>>(
+    dir: P,
+    find_dirs: FindType,
+    opts: &Options,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::<PathBuf>::new();
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        if !listed(&path, opts) {
+            continue;
+        }
+        // is_file and is_dir look through symlinks to what they point at
+        let wanted = match &find_dirs {
+            FindType::File => path.is_file(),
+            FindType::Dir => path.is_dir(),
+        };
+        if wanted {
+            files.push(path);
+        }
+    }
+    // Same numbering on every system, so piped-in picks stay valid
+    files.sort();
+    Ok(files)
+}
+
+fn prompt_value(
+    bounds: Range<u64>,
+    message: &str,
+    def: Option<u64>,
+    parse: fn(&str) -> Option<u64>,
+    show: fn(u64) -> String,
+) -> io::Result<u64> {
+    let stdin = io::stdin();
+    let mut buffer = String::new();
+    // A remembered default may no longer be valid for these bounds
+    let def = def.filter(|d| bounds.contains(d));
+    // Tell the user to enter a value within the bounds
+    if !message.is_empty() {
+        if let Some(def) = def {
+            println!(
+                "{}",
+                tr!(
+                    InRangeDefault,
+                    message,
+                    show(bounds.start),
+                    show(bounds.end - 1),
+                    show(def)
+                )
+            );
+        } else {
+            println!(
+                "{}",
+                tr!(InRange, message, show(bounds.start), show(bounds.end - 1))
+            );
+        }
+    }
+    buffer.clear();
+    // Keep prompting until the user passes a value within the bounds
+    Ok(loop {
+        // Running out of input would otherwise ask forever
+        if stdin.read_line(&mut buffer)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                tr!(OutOfInput),
+            ));
+        }
+        print!("\r\u{8}");
+        io::stdout().flush().unwrap();
+        let input = buffer.trim();
+        // An empty line accepts the displayed default
+        if input.is_empty() {
+            if let Some(def) = def {
+                print!("\r\u{8}");
+                println!("{}", show(def));
+                io::stdout().flush().unwrap();
+                break def;
+            }
+        } else if let Some(value) = parse(input) {
+            if bounds.contains(&value) {
+                break value;
+            }
+        }
+        buffer.clear();
+    })
+}
+
+// What someone typing at a prompt groups thousands with, going by the
+// interface language, see units::normalize_number. Only for the prompts:
+// files and flags have to read the same for everyone
+fn typed_grouping() -> Option<char> {
+    match messages::lang() {
+        messages::Lang::En => Some(','),
+        messages::Lang::Es => Some('.'),
+    }
+}
+
+// Whole numbers, with thousands separators if wanted
+fn prompt_number(bounds: Range<u64>, message: &str, def: Option<u64>) -> io::Result<u64> {
+    let parse = |s: &str| units::normalize_number(s, typed_grouping())?.parse().ok();
+    prompt_value(bounds, message, def, parse, |v| v.to_string())
+}
+
+// Like prompt_number, but accepts and displays sizes such as `2MB` or `500k`
+fn prompt_size(bounds: Range<u64>, message: &str, def: Option<u64>) -> io::Result<u64> {
+    let parse = |s: &str| units::parse_size_grouped(s, typed_grouping());
+    prompt_value(bounds, message, def, parse, units::format_size)
+}
+
+fn input_prompt<P: AsRef<Path>>(
+    dir: P,
+    find_dirs: FindType,
+    message: &str,
+    opts: &Options,
+) -> std::io::Result<PathBuf> {
+    // Get files/dirs in dir
+    let files = list_dir(&dir, find_dirs, opts)?;
+    // Inform the user that they will need to enter a value
+    if !message.is_empty() {
+        println!("{}", message);
+    }
+    // Enumerate the names of the files/dirs
+    for (i, e) in files.iter().enumerate() {
+        println!("{}: {}", i, e.display());
+    }
+    // This is the range of values they can pick
+    let bound: Range<u64> = Range {
+        start: 0,
+        end: files.len() as u64,
+    };
+    // Return the path they picked
+    Ok(files[prompt_number(bound, "", None)? as usize].clone())
+}
+
+// Splits a byte count into the whole number and unit used in output names
+fn size_suffix(size: u64) -> (u64, &'static str) {
+    match size {
+        1_000..=999_999 => (size / 1_000, "KB"),
+        1_000_000..=999_999_999 => (size / 1_000_000, "MB"),
+        1_000_000_000..=999_999_999_999 => (size / 1_000_000_000, "GB"),
+        /* ??Could you IMAGINE?? */
+        1_000_000_000_000..=999_999_999_999_999 => (size / 1_000_000_000_000, "TB"),
+        _ => (size, "B"),
+    }
+}
+
+// How output names show the output's size, see --size-stamp
+#[derive(Clone, Copy, PartialEq)]
+pub enum SizeStamp {
+    // `_500KB`, the default
+    Rounded,
+    // `_499712B`
+    Exact,
+    None,
+}
+
+impl SizeStamp {
+    pub fn parse(value: &str) -> Option<SizeStamp> {
+        match value.to_ascii_lowercase().as_str() {
+            "rounded" => Some(SizeStamp::Rounded),
+            "exact" => Some(SizeStamp::Exact),
+            "none" => Some(SizeStamp::None),
+            _ => None,
+        }
+    }
+}
+
+// The file an output is written to: the stem from output_stem, the size
+// stamp, a hash of the content with --hash, then the extension. Built up as
+// an OsString so names that aren't valid UTF-8 come through intact
+fn output_name(stem: &Path, bytes: &[u8], ext: impl AsRef<OsStr>, opts: &Options) -> PathBuf {
+    let mut name = stem.as_os_str().to_os_string();
+    match opts.size_stamp {
+        SizeStamp::Rounded => {
+            let (size, unit) = size_suffix(bytes.len() as u64);
+            name.push(format!("_{}{}", size, unit));
+        }
+        SizeStamp::Exact => name.push(format!("_{}B", bytes.len())),
+        SizeStamp::None => (),
+    }
+    // Changes whenever the content does, for caches keyed on the URL
+    if opts.hash {
+        name.push(format!(".{:08x}", crc32fast::hash(bytes)));
+    }
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+// How compressible the image is, as its QOI size over its raw size. QOI is
+// lossless, deterministic and fast, so the baseline doesn't depend on the
+// output format's settings, and nothing has to go through the disk. Always
+// of 8-bit RGBA: QOI takes nothing else, and the ratio stays comparable
+// between grey, RGB and 16-bit inputs
+fn find_compression_ratio(img: &DynamicImage) -> Result<f64, Box<dyn Error>> {
+    let rgba = DynamicImage::ImageRgba8(img.to_rgba8());
+    let qoi = encode::encode(&rgba, "qoi", &EncoderSettings::default())?;
+    Ok(qoi.len() as f64 / rgba.as_bytes().len() as f64)
+}
+
+// Finds the highest JPEG quality whose encoding fits in target bytes
+fn fit_jpeg_quality(
+    img: &DynamicImage,
+    target: u64,
+    settings: &EncoderSettings,
+) -> Result<Option<u8>, Box<dyn Error>> {
+    let (mut lo, mut hi) = (1u8, 100u8);
+    let mut best = None;
+    while lo <= hi {
+        let quality = lo + (hi - lo) / 2;
+        if encode::encode_jpeg(img, quality, settings)?.len() as u64 <= target {
+            best = Some(quality);
+            lo = quality + 1;
+        } else if quality == 1 {
+            break;
+        } else {
+            hi = quality - 1;
+        }
+    }
+    Ok(best)
+}
+
+// Encodes a candidate and runs it through any configured external tools
+fn encode_candidate(
+    img: &DynamicImage,
+    ext: &str,
+    opts: &Options,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    tools::apply(&opts.tools, ext, encode::encode(img, ext, &opts.encoder)?)
+}
+
+// Resized straight from the decoded input, so every candidate (and the final
+// output) is a single generation away from the original pixels. At 1.0 the
+// pixels go to the encoder untouched
+fn resized<'a>(img: &'a DynamicImage, scale: f64, opts: &Options) -> Cow<'a, DynamicImage> {
+    let scale = opts.resize_scale(scale);
+    if scale == 1.0 {
+        return Cow::Borrowed(img);
+    }
+    let (w, h) = (img.width() as f64, img.height() as f64);
+    Cow::Owned(resample::image(
+        img,
+        (w * scale) as u32,
+        (h * scale) as u32,
+        opts.filter,
+    ))
+}
+
+// Seed for --reproducible, so the searches try the same scales every run
+const REPRODUCIBLE_SEED: u64 = 0x6175_746f_7369_7a65;
+
+// Where the searches get their random scales from
+fn search_rng(opts: &Options) -> StdRng {
+    if opts.reproducible {
+        StdRng::seed_from_u64(REPRODUCIBLE_SEED)
+    } else {
+        StdRng::from_entropy()
+    }
+}
+
+// What a search settled on
+struct Found {
+    scale: f64,
+    bytes: Vec<u8>,
+    // Candidates the search encoded to get there
+    encodes: u32,
+}
+
+// Searches for the largest scale whose encoding fits the target
+fn search(img: &DynamicImage, ext: &str, opts: &Options) -> Result<Found, Box<dyn Error>> {
+    let (target, settings) = (opts.target, &opts.encoder);
+    let ratio = find_compression_ratio(img)?;
+    let mut oracle = oracle::Encoder {
+        img,
+        ext,
+        opts,
+        encodes: 0,
+        too_slow: None,
+    };
+    let mut rng = search_rng(opts);
+    let presearched =
+        if opts.presearch && img.width() as u64 * img.height() as u64 >= oracle::PRESEARCH_PIXELS {
+            oracle::presearch(&mut oracle, ratio, &mut rng)?
+        } else {
+            None
+        };
+    let best = match presearched {
+        Some(best) => Some(best),
+        None => oracle::bracket(&mut oracle, opts, ratio, &mut rng)?,
+    };
+    let (best_scale, mut bytes) = match best {
+        Some((scale, bytes)) => (scale, bytes),
+        None if oracle.too_slow.is_some() => return Err(tr!(AllCandidatesTimedOut).into()),
+        // Nothing fit above the scale floor, so stay at the floor and
+        // reduce quality instead of shrinking any further
+        None if opts.min_scale > 0.0 => {
+            let floor_image = resized(img, opts.min_scale, opts);
+            let quality = match encode::is_jpeg(ext) {
+                true => fit_jpeg_quality(&floor_image, target, settings)?,
+                false => None,
+            };
+            let Some(quality) = quality else {
+                return Err(tr!(FloorUnreachable, opts.min_scale).into());
+            };
+            if !opts.quiet {
+                println!("{}", tr!(FloorJpegQuality, opts.min_scale, quality));
+            }
+            let bytes = encode::encode_jpeg(&floor_image, quality, settings)?;
+            (opts.min_scale, bytes)
+        }
+        // The target is a limit, so handing back something over it isn't
+        // a result
+        None => return Err(tr!(NothingFits, units::format_size(target)).into()),
+    };
+    if ext.eq_ignore_ascii_case("png") {
+        bytes = encode::optimize_png(bytes, settings)?;
+    }
+    Ok(Found {
+        scale: opts.resize_scale(best_scale),
+        bytes,
+        encodes: oracle.encodes,
+    })
+}
+
+// Runs the planner over a still and writes what it settles on. original is
+// the input file, see plan::fit_still
+fn find_largest_within(
+    img: &DynamicImage,
+    ext: &str,
+    original: Option<&[u8]>,
+    iname: &Path,
+    opts: &Options,
+) -> Result<(plan::Plan, PathBuf), Box<dyn Error>> {
+    let plan = review::until_accepted(ext, opts, |opts| plan::fit_still(img, ext, original, opts))?;
+    let output = output_name(iname, &plan.bytes, ext, opts);
+    backup::write_output(&output, &plan.bytes, opts)?;
+    Ok((plan, output))
+}
+
+// A JPEG can be repacked as JPEG XL without any loss, so when that alone
+// fits there's no reason to decode and re-encode it
+#[cfg(feature = "jxl")]
+fn recompress_to_jxl(
+    fname: &Path,
+    in_ext: &str,
+    ext: &str,
+    iname: &Path,
+    opts: &Options,
+) -> Result<Option<(f64, PathBuf)>, Box<dyn Error>> {
+    if !encode::is_jpeg(in_ext) || !ext.eq_ignore_ascii_case("jxl") {
+        return Ok(None);
+    }
+    let bytes = jxl::recompress_jpeg(fname)?;
+    if bytes.len() as u64 > opts.target {
+        return Ok(None);
+    }
+    println!("{}", tr!(JxlRecompressed));
+    let output = output_name(iname, &bytes, "jxl", opts);
+    backup::write_output(&output, &bytes, opts)?;
+    Ok(Some((1.0, output)))
+}
+
+#[cfg(not(feature = "jxl"))]
+fn recompress_to_jxl(
+    _: &Path,
+    _: &str,
+    _: &str,
+    _: &Path,
+    _: &Options,
+) -> Result<Option<(f64, PathBuf)>, Box<dyn Error>> {
+    Ok(None)
+}
+
+// Faces for --crop to keep in, with --face-model
+#[cfg(feature = "faces")]
+fn find_faces(img: &DynamicImage, opts: &Options) -> Result<Vec<crop::Rect>, Box<dyn Error>> {
+    match &opts.face_model {
+        Some(model) => faces::detect(img, model),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(not(feature = "faces"))]
+fn find_faces(_: &DynamicImage, _: &Options) -> Result<Vec<crop::Rect>, Box<dyn Error>> {
+    Ok(Vec::new())
+}
+
+// Asks for the search settings, offering the last run's values as defaults.
+// With --target nothing is asked and the rest are the last run's
+fn prompt_settings(opts: &mut Options, animated: bool) -> io::Result<()> {
+    // A resumed search carries on with what it was started with
+    if let Some(state) = opts.session.as_ref().and_then(|s| s.resumed) {
+        opts.target = state.target;
+        opts.byte_halt = state.byte_halt;
+        opts.iters = state.iters;
+        opts.encoder.gif_speed = state.gif_speed;
+        return Ok(());
+    }
+    let last = LastSettings::load();
+    if let Some(target) = opts.given_target {
+        opts.target = target;
+        opts.byte_halt = last.byte_halt;
+        opts.iters = last.iters as i32;
+        opts.encoder.gif_speed = last.gif_speed as i32;
+        return Ok(());
+    }
+    let target = prompt_size(
+        Range {
+            start: 128,
+            end: u64::MAX,
+        },
+        &tr!(EnterSize),
+        Some(last.target),
+    )?;
+    let byte_halt = prompt_size(
+        Range {
+            start: 0,
+            end: u64::MAX,
+        },
+        &tr!(EnterThreshold),
+        Some(last.byte_halt),
+    )?;
+    let iters = prompt_number(
+        Range {
+            start: 8,
+            end: 16384,
+        },
+        &tr!(EnterIterations),
+        Some(last.iters),
+    )?;
+    let gif_speed = if animated {
+        prompt_number(
+            Range { start: 1, end: 31 },
+            &tr!(EnterGifSpeed),
+            Some(last.gif_speed),
+        )?
+    } else {
+        last.gif_speed
+    };
+    let last = LastSettings {
+        target,
+        byte_halt,
+        iters,
+        gif_speed,
+    };
+    if let Err(e) = last.save() {
+        println!("{}", tr!(CantRememberSettings, e));
+    }
+    opts.target = target;
+    opts.byte_halt = byte_halt;
+    opts.iters = iters as i32;
+    opts.encoder.gif_speed = gif_speed as i32;
+    Ok(())
+}
+
+// Animations whose changing part is less than this share of the frame get
+// --crop-to-changes suggested
+const STATIC_SHARE: f64 = 0.8;
+
+// Where the outputs for fname go, minus the size suffix and extension
+fn output_stem(fname: &Path, opts: &Options) -> PathBuf {
+    let stem = fname.file_stem().unwrap_or_default();
+    match &opts.output_dir {
+        Some(dir) => dir.join(stem),
+        None => PathBuf::from(stem),
+    }
+}
+
+// One input file, decoded and ready to fit
+struct Input {
+    path: PathBuf,
+    name: PathBuf,
+    in_ext: String,
+    ext: String,
+    // Set when the frame-by-frame GIF search should run
+    anim: Option<Animation>,
+    image: DynamicImage,
+    // Size of image relative to the file, below 1 for a --low-memory copy
+    base_scale: f64,
+    // Set when load_input changed the pixels, so the file's own compressed
+    // data no longer holds them
+    edited: bool,
+}
+
+impl Input {
+    // Scales in opts are relative to the original. With a reduced working
+    // copy the searches scale the copy, so their bounds are converted. The
+    // copy is never scaled past its own size, which would only spend the
+    // memory --low-memory saved, unless --min-scale asks for more
+    fn working(&self, opts: &Options) -> Options {
+        let base = self.base_scale;
+        let min_scale = opts.min_scale / base;
+        let max_scale = match base < 1.0 {
+            true => (opts.max_scale / base).min(1.0).max(min_scale),
+            false => opts.max_scale,
+        };
+        Options {
+            min_scale,
+            max_scale,
+            ..opts.clone()
+        }
+    }
+}
+
+// A file decoded ahead of time, for load_input to take instead of decoding
+// it again
+#[derive(Clone)]
+enum Decoded {
+    Gif(Animation),
+    Still(DynamicImage),
+}
+
+// Decodes a file the way load_input will, for batch to do on another thread
+// while the file before it is fitted. Quietly, and None when it fails, so
+// load_input tries again and reports it where it belongs. Never with
+// --low-memory, which mustn't hold two images at once
+fn decode_ahead(fname: &Path, opts: &Options) -> Option<Decoded> {
+    if opts.low_memory {
+        return None;
+    }
+    let in_ext = source::format_of(fname)
+        .or_else(|| fname.extension().map(|e| e.to_string_lossy().to_string()))?;
+    if in_ext.eq_ignore_ascii_case("gif") {
+        Animation::load_gif(fname).ok().map(Decoded::Gif)
+    } else {
+        encode::open(fname).ok().map(Decoded::Still)
+    }
+}
+
+// Decodes an input and applies everything that doesn't depend on the
+// prompted settings: animation handling, colour conversion and flattening.
+// decoded is the file already decoded by decode_ahead, if it was
+fn load_input(
+    fname: &Path,
+    opts: &Options,
+    decoded: Option<Decoded>,
+) -> Result<Input, Box<dyn Error>> {
+    // By content first, the name only has the last word when it's something
+    // image can't recognize like JPEG XL
+    let in_ext = source::format_of(fname)
+        .or_else(|| fname.extension().map(|e| e.to_string_lossy().to_string()))
+        .ok_or_else(|| tr!(NoExtension, fname.display()))?;
+    formats::check_read(&in_ext)?;
+    let name = output_stem(fname, opts);
+    let ext = opts.format.clone().unwrap_or_else(|| in_ext.clone());
+    formats::check_write(&ext)?;
+    if opts.in_place && !ext.eq_ignore_ascii_case(&in_ext) {
+        return Err(tr!(InPlaceFormat).into());
+    }
+    // With no size stamp or hash the output can land right on the input
+    let unstamped = output_name(&name, &[], &ext, opts);
+    if !opts.in_place
+        && opts.size_stamp == SizeStamp::None
+        && !opts.hash
+        && fs::canonicalize(&unstamped)
+            .is_ok_and(|out| fs::canonicalize(fname).is_ok_and(|f| f == out))
+    {
+        return Err(tr!(OutputIsInput, fname.display()).into());
+    }
+    // Animated GIFs go through the frame-by-frame search instead, unless a
+    // single frame was asked for or the output format can't animate
+    let mut still = None;
+    let (ahead_gif, ahead_still) = match decoded {
+        Some(Decoded::Gif(anim)) => (Some(anim), None),
+        Some(Decoded::Still(image)) => (None, Some(image)),
+        None => (None, None),
+    };
+    let mut anim = if in_ext.eq_ignore_ascii_case("gif") {
+        Some(match ahead_gif {
+            Some(anim) => anim,
+            None => opts
+                .retry
+                .run(fname.display(), || Animation::load_gif(fname))?,
+        })
+        .filter(|a| a.len() > 1)
+    } else {
+        None
+    };
+    // Cut down before anything else, so --still picks from what's left
+    match &mut anim {
+        Some(a) if opts.trim.is_set() => {
+            let total = a.len();
+            *a = a.trim(&opts.trim)?;
+            let seconds = a.delays.iter().map(|&d| d as f64).sum::<f64>() / 100.0;
+            println!("{}", tr!(Trimmed, a.len(), total, seconds));
+            if a.len() < 2 {
+                still = Some(DynamicImage::ImageRgba8(a.frames[0].clone()));
+            }
+        }
+        None if opts.trim.is_set() => println!("{}", tr!(TrimIgnored)),
+        _ => (),
+    }
+    match &mut anim {
+        Some(a) if opts.transform.is_set() => {
+            *a = a.transform(&opts.transform);
+            let seconds = a.delays.iter().map(|&d| d as f64).sum::<f64>() / 100.0;
+            println!("{}", tr!(Transformed, a.len(), seconds));
+        }
+        None if opts.transform.is_set() => println!("{}", tr!(TransformIgnored)),
+        _ => (),
+    }
+    match &mut anim {
+        Some(a) if opts.stabilize => match stabilize::stabilize(a) {
+            stabilize::Outcome::Steadied(steady) => {
+                let (w, h) = steady.frames[0].dimensions();
+                println!("{}", tr!(Stabilized, w, h));
+                *a = steady;
+            }
+            stabilize::Outcome::Steady => println!("{}", tr!(AlreadySteady)),
+            stabilize::Outcome::TooMuchMotion => println!("{}", tr!(TooMuchMotion)),
+        },
+        None if opts.stabilize => println!("{}", tr!(StabilizeIgnored)),
+        _ => (),
+    }
+    // Offered whenever there's a good deal to crop, done with the flag
+    match &mut anim {
+        Some(a) => match a.changing_region() {
+            Some(r) => {
+                let (w, h) = a.frames[0].dimensions();
+                let share = (r.w * r.h) as f64 / (w * h) as f64;
+                if opts.crop_to_changes && (r.w, r.h) != (w, h) {
+                    *a = a.crop(r);
+                    println!("{}", tr!(CroppedToChanges, r.w, r.h));
+                } else if !opts.crop_to_changes && share < STATIC_SHARE {
+                    println!("{}", tr!(StaticSurroundings, r.w, r.h, share * 100.0));
+                }
+            }
+            None if opts.crop_to_changes => println!("{}", tr!(NothingChanges)),
+            None => (),
+        },
+        None if opts.crop_to_changes => println!("{}", tr!(CropToChangesIgnored)),
+        None => (),
+    }
+    if let (Some(a), Some(tolerance)) = (&mut anim, opts.coalesce) {
+        let merged = a.coalesce(tolerance);
+        if merged.len() < a.len() {
+            println!("{}", tr!(Coalesced, merged.len(), a.len()));
+            if merged.len() < 2 {
+                still = Some(DynamicImage::ImageRgba8(merged.frames[0].clone()));
+            }
+            *a = merged;
+        }
+    }
+    match (&anim, opts.still) {
+        (Some(a), Some(pick)) => {
+            let index = a.pick_still(pick)?;
+            println!("{}", tr!(UsingFrame, index, a.len()));
+            still = Some(DynamicImage::ImageRgba8(a.frames[index].clone()));
+        }
+        (Some(a), None) if !ext.eq_ignore_ascii_case("gif") => {
+            still = Some(DynamicImage::ImageRgba8(a.frames[0].clone()));
+        }
+        (None, Some(_)) => println!("{}", tr!(StillIgnored)),
+        _ => (),
+    }
+    if still.is_some() {
+        anim = None;
+    }
+    let lower = ext.to_ascii_lowercase();
+    if opts.encoder.interlaced && lower != "png" && lower != "gif" {
+        println!("{}", tr!(InterlaceIgnored));
+    }
+    if opts.adaptive && anim.is_none() {
+        println!("{}", tr!(AdaptiveIgnored));
+    }
+    if opts.min_ssim.is_some() && (anim.is_some() || lower == "ico") {
+        println!("{}", tr!(MinSsimIgnored));
+    }
+    if opts.max_color_shift.is_some() && lower == "ico" {
+        println!("{}", tr!(ColorShiftIgnored));
+    }
+    if opts.encoder.depth != depth::Depth::Full && anim.is_some() {
+        println!("{}", tr!(DepthIgnored));
+    }
+    if opts.encoder.progressive && !encode::is_jpeg(&ext) {
+        println!("{}", tr!(ProgressiveIgnored));
+    }
+    let (mut base_scale, mut edited) = (1.0, false);
+    let mut image = match (still, &anim) {
+        (Some(still), _) => still,
+        (None, Some(anim)) => DynamicImage::ImageRgba8(anim.frames[0].clone()),
+        (None, None) if opts.low_memory => {
+            match opts.retry.run(fname.display(), || lowmem::open(fname))? {
+                Some((reduced, scale)) => {
+                    println!(
+                        "{}",
+                        tr!(LowMemoryCopy, reduced.width(), reduced.height(), scale)
+                    );
+                    base_scale = scale;
+                    reduced
+                }
+                None => opts.retry.run(fname.display(), || encode::open(fname))?,
+            }
+        }
+        (None, None) => match ahead_still {
+            Some(image) => image,
+            None => opts.retry.run(fname.display(), || encode::open(fname))?,
+        },
+    };
+    if opts.srgb && anim.is_none() {
+        if let Some(icc) = color::read_icc(fname) {
+            if let Some(converted) = color::to_srgb(&image, &icc)? {
+                println!("{}", tr!(ConvertedToSrgb));
+                image = converted;
+                edited = true;
+            }
+        }
+    }
+    if let Some(aspect) = opts.crop {
+        let faces = find_faces(&image, opts)?;
+        if let Some(r) = crop::region(&image, aspect, &faces) {
+            image = image.crop_imm(r.x, r.y, r.w, r.h);
+            edited = true;
+            // Animations are cropped where their first frame says
+            if let Some(anim) = &mut anim {
+                for frame in &mut anim.frames {
+                    *frame = imageops::crop_imm(frame, r.x, r.y, r.w, r.h).to_image();
+                }
+            }
+            match faces.len() {
+                0 => println!("{}", tr!(CroppedToDetail, r.w, r.h)),
+                n => println!("{}", tr!(CroppedToFaces, r.w, r.h, n)),
+            }
+        }
+    } else if opts.face_model.is_some() {
+        println!("{}", tr!(FaceModelIgnored));
+    }
+    #[cfg(feature = "background")]
+    if let Some(model) = &opts.remove_background {
+        if anim.is_some() {
+            println!("{}", tr!(BackgroundRemovalIgnored));
+        } else {
+            image = matte::remove_background(&image, model)?;
+            edited = true;
+            println!("{}", tr!(BackgroundRemoved));
+            if encode::is_jpeg(&ext) {
+                println!("{}", tr!(BackgroundFlattened));
+            }
+        }
+    }
+    // JPEG has no alpha channel, so composite onto the background colour
+    // rather than letting the hidden colour under transparent pixels show
+    if encode::is_jpeg(&ext) && image.color().has_alpha() {
+        image = color::flatten(&image, opts.background);
+    }
+    Ok(Input {
+        path: fname.to_path_buf(),
+        name,
+        in_ext,
+        ext,
+        anim,
+        image,
+        base_scale,
+        edited,
+    })
+}
+
+// How fitting one input went
+struct Fit {
+    scale: f64,
+    output: PathBuf,
+    // Candidates encoded, when the planner ran
+    encodes: Option<u32>,
+    // What each of the planner's steps saved, see plan::Plan::attribution
+    savings: Vec<String>,
+    // The placeholder written with --preview
+    preview: Option<PathBuf>,
+    // The frames written with --preview-strip
+    strip: Option<PathBuf>,
+}
+
+// Fits a loaded input into the target and writes the result. Per-image
+// adjustments (text legibility, chroma subsampling) are made on a copy of
+// the options
+fn fit_input(input: &Input, opts: &Options) -> Result<Fit, Box<dyn Error>> {
+    let mut opts = opts.clone();
+    if opts.margin > 0.0 {
+        let aim = opts.aim(opts.target);
+        if !opts.quiet {
+            println!(
+                "{}",
+                tr!(
+                    AimingUnder,
+                    units::format_size(aim),
+                    opts.margin * 100.0,
+                    units::format_size(opts.target)
+                )
+            );
+        }
+        opts.target = aim;
+    }
+    let mut ext = input.ext.clone();
+    if let Some(profile) = profile::choose(&opts, &input.image) {
+        ext = profile.apply(
+            &mut opts,
+            &input.in_ext,
+            &ext,
+            &input.image,
+            input.anim.is_some(),
+        );
+        if ext != input.ext && !opts.quiet {
+            println!("{}", tr!(ProfileFormat, profile.name(), ext));
+        }
+    }
+    let (fname, ext, image) = (&input.path, &ext, &input.image);
+    // The searches scale a reduced working copy, see Input::working, and
+    // their results are converted back
+    let base = input.base_scale;
+    // Text needs a minimum resolution to stay readable
+    let legible = match opts.text_check {
+        TextCheck::Off => None,
+        _ => legibility::min_legible_scale(&image.to_rgba8()).map(|s| s * base),
+    };
+    if let (TextCheck::Block, Some(legible)) = (&opts.text_check, legible) {
+        if legible > opts.max_scale {
+            return Err(tr!(TextUnreadable).into());
+        }
+        if legible > opts.min_scale {
+            println!("{}", tr!(TextKeepingScale, legible));
+            opts.min_scale = legible;
+        }
+    }
+    if encode::is_jpeg(ext) && opts.encoder.subsampling == Subsampling::Auto {
+        let subsampling = encode::resolve_subsampling(image, &opts.encoder)?;
+        println!("{}", tr!(UsingSubsampling, subsampling.name()));
+        opts.encoder.subsampling = subsampling;
+    }
+    if encode::is_jpeg(ext) && opts.noise_cap && input.anim.is_none() {
+        let cap = noise::quality_cap(image).filter(|&(_, cap)| cap < opts.encoder.jpeg_quality);
+        if let Some((sigma, cap)) = cap {
+            println!("{}", tr!(NoisyInput, sigma, cap));
+            opts.encoder.jpeg_quality = cap;
+        }
+    }
+    if opts.preview_strip.is_some() && input.anim.is_none() && !opts.quiet {
+        println!("{}", tr!(StripNotAnimated));
+    }
+    let mut strip = None;
+    let ((scale, mut output), encodes, savings) = if let Some(anim) = &input.anim {
+        let original = fs::metadata(fname).ok().map(|m| m.len());
+        let plan = review::until_accepted("gif", &opts, |opts| {
+            plan::fit_animation(anim, original, opts)
+        })?;
+        let output = output_name(&input.name, &plan.bytes, "gif", &opts);
+        backup::write_output(&output, &plan.bytes, &opts)?;
+        if let Some(count) = opts.preview_strip {
+            let (path, frames) = strip::write(&plan.bytes, &input.name, count, &opts)?;
+            println!("{}", tr!(StripWritten, frames, path.display()));
+            strip = Some(path);
+        }
+        ((plan.scale, output), Some(plan.encodes), plan.attribution())
+    } else if ext.eq_ignore_ascii_case("ico") {
+        let (scale, output) = ico::fit(image, &input.name, &input.working(&opts))?;
+        ((scale * base, output), None, Vec::new())
+    } else if let Some(done) = recompress_to_jxl(fname, &input.in_ext, ext, &input.name, &opts)? {
+        (done, None, Vec::new())
+    } else {
+        // A reduced working copy or an edited image isn't the original's
+        // pixels, so its compressed data can't stand in for them
+        let original = match base == 1.0 && !input.edited {
+            true => Some(opts.retry.run(fname.display(), || fs::read(fname))?),
+            false => None,
+        };
+        let (plan, output) = find_largest_within(
+            image,
+            ext,
+            original.as_deref(),
+            &input.name,
+            &input.working(&opts),
+        )?;
+        (
+            (plan.scale * base, output),
+            Some(plan.encodes),
+            plan.attribution(),
+        )
+    };
+    if let Some(legible) = legible {
+        if scale < legible {
+            println!("{}", tr!(TextWarning, scale, legible));
+        }
+    }
+    if opts.in_place {
+        backup::replace_in_place(fname, &output, &opts)?;
+        if !opts.quiet {
+            println!("{}", tr!(ReplacedInPlace, fname.display()));
+        }
+        output = fname.clone();
+    } else if opts.preserve_attrs {
+        if let Err(e) = attrs::copy_all(fname, &output) {
+            println!("{}", tr!(CantCopyAttrs, fname.display(), e));
+        }
+    }
+    // From the same decoded image, so it's one pass over the input
+    let preview = match opts.preview {
+        Some(target) => {
+            let preview = preview::write(image, &input.name, target, &opts)?;
+            let size = fs::metadata(&preview).map(|m| m.len()).unwrap_or(0);
+            println!(
+                "{}",
+                tr!(PreviewWritten, units::format_size(size), preview.display())
+            );
+            Some(preview)
+        }
+        None => None,
+    };
+    if opts.blurhash {
+        // Of what was written, falling back on the input for formats that
+        // can't be read back like JPEG XL
+        let written = encode::open(&output);
+        let hash = blurhash::encode(written.as_ref().unwrap_or(image), opts.background);
+        println!("{}", tr!(BlurHash, hash));
+        progress::emit(&opts, "blurhash", &[("blurhash", hash.into())]);
+    }
+    Ok(Fit {
+        scale,
+        output,
+        encodes,
+        savings,
+        preview,
+        strip,
+    })
+}
+
+// Lists what each of the planner's steps saved, if it took any
+fn print_savings(savings: &[String]) {
+    if savings.is_empty() {
+        return;
+    }
+    println!("{}", tr!(StepSavings));
+    for line in savings {
+        println!("  {}", line);
+    }
+}
+
+// Input and output are what the run was given and where the result ended
+// up, which for object storage are URLs rather than the local copies
+#[allow(clippy::too_many_arguments)]
+fn record_history(
+    input: &str,
+    output: &str,
+    size: u64,
+    opts: &Options,
+    scale: f64,
+    encodes: Option<u32>,
+    millis: u128,
+    usage: Option<usage::Usage>,
+    args: &[String],
+) {
+    let entry = history::Entry {
+        time: history::Entry::now(),
+        input: input.to_string(),
+        output: output.to_string(),
+        target: opts.target,
+        size,
+        scale,
+        millis,
+        flags: args.join(" ").replace('\t', " "),
+        encodes,
+        iters: Some(opts.iters),
+        byte_halt: Some(opts.byte_halt),
+        cpu_millis: usage.map(|u| u.cpu.as_millis()),
+        peak: usage.and_then(|u| u.peak),
+    };
+    if let Err(e) = entry.append() {
+        println!("{}", tr!(CantRecordHistory, e));
+    }
+}
+
+fn run_one(fname: &Path, opts: &mut Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    // A local --output naming a folder is the same as --output-dir. Anything
+    // else is written to a staging folder first and handed to its sink after
+    let (mut staging, mut sink) = (None, None);
+    if let Some(output) = &opts.output {
+        if opts.in_place {
+            return Err(tr!(OutputInPlace).into());
+        }
+        let local = Path::new(output);
+        match sink::parse(output)? {
+            to if to.is_local() && (output.ends_with(['/', '\\']) || local.is_dir()) => {
+                opts.output_dir = Some(local.to_path_buf())
+            }
+            to => {
+                let dir = remote::staging_dir()?;
+                opts.output_dir = Some(dir.clone());
+                staging = Some(dir);
+                sink = Some(to);
+            }
+        }
+    }
+    let input = load_input(fname, opts, None)?;
+    prompt_settings(opts, input.anim.is_some())?;
+    println!("{}", tr!(OneMoment));
+    let now = Instant::now();
+    let meter = usage::Meter::start();
+    progress::emit(
+        opts,
+        "file",
+        &[("input", fname.display().to_string().into())],
+    );
+    let result = fit_input(&input, opts);
+    let millis = now.elapsed().as_millis();
+    let usage = meter.and_then(|m| m.usage());
+    let delivered = result.and_then(|fit| {
+        let output = &fit.output;
+        let size = opts
+            .retry
+            .run(output.display(), || fs::metadata(output))?
+            .len();
+        let deliver = |output: &Path, sink: Option<&dyn OutputSink>| match sink {
+            Some(sink) => {
+                let name = output.file_name().unwrap_or_default().to_string_lossy();
+                let delivered = opts
+                    .retry
+                    .run(output.display(), || fs::read(output))
+                    .map_err(Box::from)
+                    .and_then(|bytes| sink.deliver(&bytes, &name, opts));
+                remote::clean_up(output);
+                delivered.inspect(|saved| println!("{}", tr!(SavedTo, saved)))
+            }
+            None => Ok(output.display().to_string()),
+        };
+        let saved = deliver(output, sink.as_deref())?;
+        // Files written beside the output follow it, where the sink has
+        // somewhere for them
+        let companion = |path: &Path, nowhere: String| -> Result<_, Box<dyn Error>> {
+            let Some(sink) = &sink else {
+                return Ok(Some(deliver(path, None)?));
+            };
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match sink.companion(&name) {
+                Some(beside) => Ok(Some(deliver(path, Some(beside.as_ref()))?)),
+                None => {
+                    remote::clean_up(path);
+                    println!("{}", nowhere);
+                    Ok(None)
+                }
+            }
+        };
+        let preview = match &fit.preview {
+            Some(preview) => companion(preview, tr!(PreviewNowhere))?,
+            None => None,
+        };
+        let strip = match &fit.strip {
+            Some(strip) => companion(strip, tr!(StripNowhere))?,
+            None => None,
+        };
+        Ok((fit, saved, preview, strip, size))
+    });
+    if let Some(dir) = staging {
+        let _ = fs::remove_dir(dir);
+    }
+    let (fit, saved, preview, strip, size) = match delivered {
+        Ok(delivered) => delivered,
+        Err(e) => {
+            progress::emit(opts, "failed", &[("error", e.to_string().into())]);
+            return Err(e);
+        }
+    };
+    progress::emit(
+        opts,
+        "written",
+        &[
+            ("output", saved.as_str().into()),
+            ("bytes", size.into()),
+            ("scale", fit.scale.into()),
+        ],
+    );
+    println!("{}", tr!(FinishedIn, millis));
+    if let Some(usage) = usage {
+        println!("{}", usage.describe());
+    }
+    print_savings(&fit.savings);
+    let local = sink.as_ref().is_none_or(|sink| sink.is_local());
+    if opts.checksums {
+        // Results sent elsewhere have nothing local to sit next to
+        match local {
+            false => println!("{}", tr!(ChecksumsRemote)),
+            true => match checksums::write(
+                &[Some(&saved), preview.as_ref(), strip.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::from)
+                    .collect::<Vec<_>>(),
+            ) {
+                Ok(manifest) => println!("{}", tr!(ChecksumsWritten, manifest.display())),
+                Err(e) => println!("{}", tr!(ChecksumsFailed, e)),
+            },
+        }
+    }
+    // Only local files, there's nothing to open an upload with
+    if opts.open && local {
+        if let Err(e) = tools::open(Path::new(&saved)) {
+            println!("{}", tr!(CantOpenViewer, e));
+        }
+    }
+    let shown = opts.input.clone().unwrap_or(fname.display().to_string());
+    record_history(
+        &shown,
+        &saved,
+        size,
+        opts,
+        fit.scale,
+        fit.encodes,
+        millis,
+        usage,
+        args,
+    );
+    Ok(())
+}
+
+// The command-line interface, run with the program's arguments
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        Some("batch") => return batch::run(&args[1..]),
+        Some("check") => return check::run(&args[1..]),
+        Some("combined") => return combined::run(&args[1..]),
+        Some("daemon") => return daemon::run(&args[1..]),
+        Some("diff") => return compare::run(&args[1..]),
+        #[cfg(feature = "gui")]
+        Some("gui") => return gui::run(&args[1..]),
+        #[cfg(not(feature = "gui"))]
+        Some("gui") => return Err("The window needs autosize built with `--features gui`".into()),
+        Some("formats") => return formats::run(&args[1..]),
+        Some("history") => return history::run(&args[1..]),
+        Some("inspect") => return inspect::run(&args[1..]),
+        Some("pre-commit") => return precommit::run(&args[1..]),
+        Some("install-shell-integration") => return shell::run(&args[1..]),
+        Some("restore") => return backup::run(&args[1..]),
+        Some("resume") => return resume::run(&args[1..]),
+        Some("sheet") => return sheet::run(&args[1..]),
+        Some("stats") => return stats::run(&args[1..]),
+        #[cfg(feature = "tray")]
+        Some("tray") => return tray::run(&args[1..]),
+        #[cfg(not(feature = "tray"))]
+        Some("tray") => {
+            return Err("The tray agent needs autosize built with `--features tray`".into())
+        }
+        _ => (),
+    }
+    let opts = Options::parse(&args)?;
+    if let Some(input) = opts.inputs.first() {
+        return Err(tr!(UnknownArgument, input.display()).into());
+    }
+    run_input(opts, &args)
+}
+
+// Fits the one input of a run from the command line, --input or else one
+// picked from the current folder
+fn run_input(mut opts: Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let source = match opts.input.as_deref() {
+        Some(input) => source::parse(input),
+        None => {
+            let fname = input_prompt("input", FindType::File, &tr!(SelectImage), &opts)?;
+            Box::new(source::File(fname))
+        }
+    };
+    // Fetched next to nothing else, and removed again afterwards
+    if !source.is_local() {
+        if opts.in_place {
+            return Err(tr!(InPlaceRemote, source.describe()).into());
+        }
+        println!("{}", tr!(Downloading, source.describe()));
+    }
+    let fetched = source.fetch()?;
+    // Long GIF searches save checkpoints, see resume
+    if opts.session.is_none() {
+        opts.session = Some(resume::Session {
+            args: args.to_vec(),
+            input: opts
+                .input
+                .clone()
+                .unwrap_or(fetched.path.display().to_string()),
+            resumed: None,
+        });
+    }
+    let result = run_one(&fetched.path, &mut opts, args);
+    // Finished or failed, a crash is the only reason left to resume
+    resume::clear();
+    fetched.clean_up();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_names_add_the_stamp_and_hash_to_the_stem() {
+        let bytes = vec![0; 1500];
+        let mut opts = Options::default();
+        let name = |opts: &Options| output_name(Path::new("out/photo"), &bytes, "jpg", opts);
+        assert_eq!(name(&opts), Path::new("out/photo_1KB.jpg"));
+        opts.size_stamp = SizeStamp::Exact;
+        opts.hash = true;
+        let hash = format!("{:08x}", crc32fast::hash(&bytes));
+        assert_eq!(
+            name(&opts),
+            PathBuf::from(format!("out/photo_1500B.{}.jpg", hash))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_names_keep_names_that_are_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let input = Path::new(OsStr::from_bytes(b"caf\xe9.png"));
+        let opts = Options {
+            size_stamp: SizeStamp::None,
+            ..Options::default()
+        };
+        let output = output_name(&output_stem(input, &opts), &[], "webp", &opts);
+        assert_eq!(output.as_os_str().as_bytes(), b"caf\xe9.webp");
+    }
+
+    #[test]
+    fn cropped_jpegs_are_not_passed_through_whole() {
+        let dir = tools::temp_path("crop", "d");
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("photo.jpg");
+        let img =
+            image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 90]));
+        DynamicImage::ImageRgb8(img).save(&input).unwrap();
+        // Far over the file, so the stripped original would fit as it is
+        let opts = Options {
+            target: 1_000_000,
+            crop: Some((1, 1)),
+            max_scale: 1.0,
+            output_dir: Some(dir.clone()),
+            quiet: true,
+            ..Options::default()
+        };
+        let loaded = load_input(&input, &opts, None).unwrap();
+        let fit = fit_input(&loaded, &opts).unwrap();
+        let written = image::open(&fit.output).unwrap();
+        assert_eq!((written.width(), written.height()), (48, 48));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use image::{codecs::jpeg::JpegDecoder, DynamicImage, ImageDecoder, ImageFormat, RgbaImage};
+use png::{ColorType, Transformations};
+
+// Most pixels --low-memory keeps in its working copy, 64 MB as RGBA
+pub const MAX_PIXELS: u64 = 16_000_000;
+
+// Decodes a reduced working copy of a large JPEG or PNG without ever holding
+// the full resolution in memory. Returns the copy and its scale relative to
+// the original, or None when the input is small enough already or can't be
+// decoded this way
+pub fn open(path: &Path) -> Result<Option<(DynamicImage, f64)>, Box<dyn Error>> {
+    match ImageFormat::from_path(path) {
+        Ok(ImageFormat::Jpeg) => open_jpeg(BufReader::new(File::open(path)?), MAX_PIXELS),
+        Ok(ImageFormat::Png) => open_png(BufReader::new(File::open(path)?), MAX_PIXELS),
+        _ => Ok(None),
+    }
+}
+
+// JPEG's DCT can be decoded straight at 1/2, 1/4 or 1/8 of the size, which
+// skips most of the work along with the memory. Asking for half the budget's
+// width gets the smallest of those at or above it, so the copy ends up
+// between a quarter of the budget and all of it
+fn open_jpeg(
+    reader: impl Read,
+    max_pixels: u64,
+) -> Result<Option<(DynamicImage, f64)>, Box<dyn Error>> {
+    let mut decoder = JpegDecoder::new(reader)?;
+    let (w, h) = decoder.dimensions();
+    let pixels = w as u64 * h as u64;
+    if pixels <= max_pixels {
+        return Ok(None);
+    }
+    let fraction = (max_pixels as f64 / pixels as f64).sqrt() / 2.0;
+    let (sw, sh) = decoder.scale(
+        ((w as f64 * fraction) as u16).max(1),
+        ((h as f64 * fraction) as u16).max(1),
+    )?;
+    if (sw as u32, sh as u32) == (w, h) {
+        return Ok(None);
+    }
+    let img = DynamicImage::from_decoder(decoder)?;
+    Ok(Some((img, sw as f64 / w as f64)))
+}
+
+// PNG rows are decoded one at a time and averaged into blocks of
+// factor x factor pixels as they arrive, so only one row of the input and
+// one row of sums are held at once. Interlaced PNGs deliver their rows in
+// passes instead, so they're left to the normal decoder
+fn open_png(
+    reader: impl Read,
+    max_pixels: u64,
+) -> Result<Option<(DynamicImage, f64)>, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let (w, h) = (reader.info().width, reader.info().height);
+    let pixels = w as u64 * h as u64;
+    if pixels <= max_pixels || reader.info().interlaced {
+        return Ok(None);
+    }
+    let factor = (pixels as f64 / max_pixels as f64).sqrt().ceil() as u32;
+    let channels = match reader.output_color_type().0 {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb => 3,
+        ColorType::Rgba => 4,
+        ColorType::Indexed => return Ok(None),
+    };
+    let (ow, oh) = (w.div_ceil(factor), h.div_ceil(factor));
+    let mut out = RgbaImage::new(ow, oh);
+    // Colour weighted by alpha, then alpha, so transparent pixels don't
+    // bleed their hidden colour into the average
+    let mut sums = vec![0u64; ow as usize * 4];
+    let mut y = 0;
+    while let Some(row) = reader.next_row()? {
+        for (x, p) in row.data().chunks_exact(channels).enumerate() {
+            let [r, g, b, a] = match *p {
+                [l] => [l, l, l, 255],
+                [l, a] => [l, l, l, a],
+                [r, g, b] => [r, g, b, 255],
+                [r, g, b, a] => [r, g, b, a],
+                _ => unreachable!(),
+            };
+            let a = a as u64;
+            let sum = &mut sums[x / factor as usize * 4..][..4];
+            sum[0] += r as u64 * a;
+            sum[1] += g as u64 * a;
+            sum[2] += b as u64 * a;
+            sum[3] += a;
+        }
+        y += 1;
+        if y % factor == 0 || y == h {
+            let rows = (y - 1) % factor + 1;
+            for (ox, sum) in sums.chunks_exact_mut(4).enumerate() {
+                let cols = factor.min(w - ox as u32 * factor);
+                let alpha = sum[3];
+                let colour = |c: u64| c.checked_div(alpha).unwrap_or(0) as u8;
+                let pixel = [
+                    colour(sum[0]),
+                    colour(sum[1]),
+                    colour(sum[2]),
+                    (alpha / (rows * cols) as u64) as u8,
+                ];
+                out.put_pixel(ox as u32, (y - 1) / factor, image::Rgba(pixel));
+                sum.fill(0);
+            }
+        }
+    }
+    Ok(Some((DynamicImage::ImageRgba8(out), ow as f64 / w as f64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{codecs::jpeg::JpegEncoder, GrayAlphaImage, LumaA, Rgb, RgbImage};
+
+    use super::*;
+
+    fn png(img: &DynamicImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn png_blocks_are_averaged_by_alpha() {
+        // 5x3 into 2x2 blocks, leaving a column and a row of partial blocks
+        let img = GrayAlphaImage::from_fn(5, 3, |x, y| match (x, y) {
+            // Hidden under no alpha, so it mustn't show in the average
+            (0, 0) => LumaA([255, 0]),
+            (4, _) => LumaA([200, 255]),
+            (_, 2) => LumaA([100, 128]),
+            _ => LumaA([40, 255]),
+        });
+        let (copy, scale) = open_png(Cursor::new(png(&img.into())), 4).unwrap().unwrap();
+        assert_eq!((copy.width(), copy.height()), (3, 2));
+        assert_eq!(scale, 3.0 / 5.0);
+        let copy = copy.to_rgba8();
+        assert_eq!(copy.get_pixel(0, 0).0, [40, 40, 40, 191]);
+        assert_eq!(copy.get_pixel(1, 0).0, [40, 40, 40, 255]);
+        assert_eq!(copy.get_pixel(2, 0).0, [200, 200, 200, 255]);
+        assert_eq!(copy.get_pixel(0, 1).0, [100, 100, 100, 128]);
+        assert_eq!(copy.get_pixel(2, 1).0, [200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn small_pngs_are_left_to_the_normal_decoder() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        assert!(open_png(Cursor::new(png(&img)), 16).unwrap().is_none());
+    }
+
+    #[test]
+    fn jpegs_are_decoded_at_a_dct_scale() {
+        let img = RgbImage::from_pixel(64, 64, Rgb([120, 60, 200]));
+        let mut bytes = Vec::new();
+        JpegEncoder::new_with_quality(&mut bytes, 95)
+            .encode_image(&img)
+            .unwrap();
+        // Half of a 16 pixel wide budget is 8, exactly 1/8 of the width
+        let (copy, scale) = open_jpeg(Cursor::new(&bytes), 256).unwrap().unwrap();
+        assert_eq!((copy.width(), copy.height()), (8, 8));
+        assert_eq!(scale, 1.0 / 8.0);
+        for p in copy.to_rgb8().pixels() {
+            for (got, want) in p.0.iter().zip([120, 60, 200]) {
+                assert!(got.abs_diff(want) <= 4, "{:?}", p);
+            }
+        }
+        assert!(open_jpeg(Cursor::new(&bytes), 64 * 64).unwrap().is_none());
+    }
+}
@@ -0,0 +1,59 @@
+use std::{error::Error, path::Path};
+
+use image::{imageops, DynamicImage, GrayImage, RgbaImage};
+
+use crate::onnx::Model;
+
+// Side of the square the model looks at when it doesn't say, what U²-Net
+// and the models trained like it (u2netp, silueta) expect
+const DEFAULT_SIZE: u32 = 320;
+// The ImageNet statistics those models were trained with
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+// The model's first output is how likely each pixel is to be the subject.
+// It's stretched to the full 0-255 range, since the raw scores rarely reach
+// either end, and scaled back up to the image's size
+fn mask(scores: &[f32], w: u32, h: u32, size: (u32, u32)) -> GrayImage {
+    let lo = scores.iter().copied().fold(f32::MAX, f32::min);
+    let hi = scores.iter().copied().fold(f32::MIN, f32::max);
+    let range = (hi - lo).max(f32::EPSILON);
+    let small = GrayImage::from_fn(w, h, |x, y| {
+        let score = scores[(y * w + x) as usize];
+        image::Luma([((score - lo) / range * 255.0).round() as u8])
+    });
+    imageops::resize(&small, size.0, size.1, imageops::FilterType::Triangle)
+}
+
+// Makes everything but the subject transparent with a salient object
+// segmentation model in ONNX format, e.g. U²-Net from rembg. For stickers
+// and product shots, where the background has to go anyway and all the
+// detail it took up comes off the file size. Edges are soft, the mask's
+// values become the alpha
+pub fn remove_background(img: &DynamicImage, model: &Path) -> Result<DynamicImage, Box<dyn Error>> {
+    let mut onnx = Model::load(model, (DEFAULT_SIZE, DEFAULT_SIZE))?;
+    let (w, h) = (onnx.w, onnx.h);
+    let input = onnx.input(img, |c, v| (v as f32 / 255.0 - MEAN[c]) / STD[c])?;
+    let outputs = onnx.session.run(ort::inputs![input])?;
+    let (_, scores) = outputs[0].try_extract_tensor::<f32>()?;
+    if scores.len() < (w * h) as usize {
+        return Err(format!(
+            "The model {} doesn't give a {}x{} mask",
+            model.display(),
+            w,
+            h
+        )
+        .into());
+    }
+    let mask = mask(
+        &scores[..(w * h) as usize],
+        w,
+        h,
+        (img.width(), img.height()),
+    );
+    let mut cut: RgbaImage = img.to_rgba8();
+    for (pixel, alpha) in cut.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = ((pixel[3] as u16 * alpha[0] as u16) / 255) as u8;
+    }
+    Ok(DynamicImage::ImageRgba8(cut))
+}
@@ -0,0 +1,475 @@
+use std::{env, fmt::Display, sync::OnceLock};
+
+use crate::settings;
+
+// Languages the interactive text can be shown in
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Lang {
+    En,
+    Es,
+}
+
+impl Lang {
+    // Accepts codes like `es`, `es_MX.UTF-8` or `ES`
+    fn from_code(code: &str) -> Option<Lang> {
+        let code = code
+            .split(['_', '.', '-', '@'])
+            .next()?
+            .to_ascii_lowercase();
+        match code.as_str() {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "es" => Some(Lang::Es),
+            _ => None,
+        }
+    }
+}
+
+// AUTOSIZE_LANG, then `lang = ...` in the config file, then the usual locale
+// variables. Anything unknown falls back to English
+pub fn lang() -> Lang {
+    static LANG: OnceLock<Lang> = OnceLock::new();
+    *LANG.get_or_init(|| {
+        let config = settings::read_config()
+            .into_iter()
+            .rev()
+            .find(|(k, _)| k == "lang")
+            .map(|(_, v)| v);
+        env::var("AUTOSIZE_LANG")
+            .ok()
+            .into_iter()
+            .chain(config)
+            .chain(
+                ["LC_ALL", "LC_MESSAGES", "LANG"]
+                    .iter()
+                    .filter_map(|v| env::var(v).ok()),
+            )
+            .filter(|code| !code.is_empty())
+            .find_map(|code| Lang::from_code(&code))
+            .unwrap_or(Lang::En)
+    })
+}
+
+// Everything the interactive run says. Each has a text per language in
+// `text`, so adding a message without translating it doesn't compile
+#[derive(Clone, Copy)]
+pub enum Msg {
+    SelectImage,
+    InRange,
+    InRangeDefault,
+    OutOfInput,
+    EnterSize,
+    EnterThreshold,
+    EnterIterations,
+    EnterGifSpeed,
+    CantRememberSettings,
+    OneMoment,
+    FinishedIn,
+    UsageCpu,
+    UsageWithPeak,
+    Starting,
+    StoppedAt,
+    Presearch,
+    PresearchMissed,
+    CandidateTimedOut,
+    AllCandidatesTimedOut,
+    FloorJpegQuality,
+    FloorGifPalette,
+    FloorUnreachable,
+    NothingFits,
+    SsimTooLow,
+    SsimAgainstInput,
+    SsimCantDecode,
+    ColorShiftTooHigh,
+    ColorShiftAgainstInput,
+    ColorShiftCantDecode,
+    #[cfg_attr(not(feature = "jxl"), allow(dead_code))]
+    JxlRecompressed,
+    NoExtension,
+    InPlaceFormat,
+    OutputIsInput,
+    UsingFrame,
+    StillIgnored,
+    Trimmed,
+    TrimIgnored,
+    Transformed,
+    TransformIgnored,
+    Stabilized,
+    AlreadySteady,
+    TooMuchMotion,
+    StabilizeIgnored,
+    Coalesced,
+    AdaptiveDetail,
+    AdaptiveIgnored,
+    CroppedToChanges,
+    StaticSurroundings,
+    NothingChanges,
+    CropToChangesIgnored,
+    CroppedToDetail,
+    CroppedToFaces,
+    FaceModelIgnored,
+    #[cfg_attr(not(feature = "background"), allow(dead_code))]
+    BackgroundRemoved,
+    #[cfg_attr(not(feature = "background"), allow(dead_code))]
+    BackgroundFlattened,
+    #[cfg_attr(not(feature = "background"), allow(dead_code))]
+    BackgroundRemovalIgnored,
+    InterlaceIgnored,
+    MinSsimIgnored,
+    ColorShiftIgnored,
+    DepthIgnored,
+    ProgressiveIgnored,
+    ConvertedToSrgb,
+    LowMemoryCopy,
+    Retrying,
+    TextUnreadable,
+    TextKeepingScale,
+    UsingSubsampling,
+    ProfileFormat,
+    ProfileDetected,
+    NoisyInput,
+    TextWarning,
+    ReplacedInPlace,
+    CantCopyAttrs,
+    PreviewWritten,
+    PreviewNowhere,
+    AimingUnder,
+    StripWritten,
+    StripNowhere,
+    StripNotAnimated,
+    BlurHash,
+    IconDropped,
+    IconTooLarge,
+    IconSizes,
+    BackedUpExisting,
+    BackedUp,
+    CantRecordHistory,
+    UnknownArgument,
+    Downloading,
+    SavedTo,
+    OutputInPlace,
+    InPlaceRemote,
+    StepApplied,
+    StepStripped,
+    StepReencoded,
+    StepQuality,
+    StepColors,
+    PaletteShiftsColors,
+    StepFrames,
+    StepScale,
+    StepSavings,
+    NeedsDownscale,
+    ReviewResult,
+    ReviewChoice,
+    ReviewStrategies,
+    ReviewCancelled,
+    RetryFailed,
+    CantOpenViewer,
+    ChecksumsWritten,
+    ChecksumsFailed,
+    ChecksumsRemote,
+    CantSaveCheckpoint,
+    NothingToResume,
+    Resuming,
+    ResumedAt,
+    CheckpointMismatch,
+}
+
+fn text(msg: Msg, lang: Lang) -> &'static str {
+    use Lang::*;
+    use Msg::*;
+    match (msg, lang) {
+        (SelectImage, En) => "Please select an image: ",
+        (SelectImage, Es) => "Elige una imagen: ",
+        (InRange, En) => "{} in the range [{}:{}]",
+        (InRange, Es) => "{} en el rango [{}:{}]",
+        (InRangeDefault, En) => "{} in the range [{}:{}] (default: {})",
+        (InRangeDefault, Es) => "{} en el rango [{}:{}] (por defecto: {})",
+        (OutOfInput, En) => "Ran out of input before getting a valid value",
+        (OutOfInput, Es) => "La entrada terminó antes de recibir un valor válido",
+        (EnterSize, En) => "\nEnter desired filesize (e.g. 2MB, 500k or 128000)\nChoose a value",
+        (EnterSize, Es) => "\nIntroduce el tamaño deseado (p. ej. 2MB, 500k o 128000)\nElige un valor",
+        (EnterThreshold, En) => "\nEnter the byte threshold (stop when the diff is equal or less than this)\n(It may not be possible to exactly reach the filesize)\nChoose a value",
+        (EnterThreshold, Es) => "\nIntroduce el margen en bytes (se para cuando la diferencia es igual o menor)\n(Puede que no se alcance exactamente el tamaño)\nElige un valor",
+        (EnterIterations, En) => "\nEnter number of iterations to run (more = closer filesize to target)\nChoose a value",
+        (EnterIterations, Es) => "\nIntroduce el número de iteraciones (más = tamaño más cercano al objetivo)\nElige un valor",
+        (EnterGifSpeed, En) => "\nEnter the GIF quantizer speed (1 = best quality, 30 = fastest)\nChoose a value",
+        (EnterGifSpeed, Es) => "\nIntroduce la velocidad del cuantizador GIF (1 = mejor calidad, 30 = más rápido)\nElige un valor",
+        (CantRememberSettings, En) => "Couldn't remember these settings: {}",
+        (CantRememberSettings, Es) => "No se pudieron guardar estos ajustes: {}",
+        (OneMoment, En) => "\nOk! One moment...",
+        (OneMoment, Es) => "\n¡Vale! Un momento...",
+        (FinishedIn, En) => "\nFinished in: {}ms!",
+        (FinishedIn, Es) => "\n¡Terminado en {}ms!",
+        (UsageCpu, En) => "CPU time: {:.2}s",
+        (UsageCpu, Es) => "Tiempo de CPU: {:.2}s",
+        (UsageWithPeak, En) => "CPU time: {:.2}s, peak memory: {}",
+        (UsageWithPeak, Es) => "Tiempo de CPU: {:.2}s, memoria máxima: {}",
+        (Starting, En) => "Starting!",
+        (Starting, Es) => "¡Empezando!",
+        (StoppedAt, En) => "\rStopped at ||{}({:.2}%) ||",
+        (StoppedAt, Es) => "\rDetenido en ||{}({:.2}%) ||",
+        (Presearch, En) => "Estimating the scale on a quarter-size copy first",
+        (Presearch, Es) => "Estimando primero la escala con una copia a un cuarto del tamaño",
+        (PresearchMissed, En) => "The estimate didn't fit, searching at full size",
+        (PresearchMissed, Es) => "La estimación no cabe, buscando a tamaño completo",
+        (CandidateTimedOut, En) => "\nGave up on the candidate at scale {:.2} after {:.2}s (--encode-timeout), trying smaller ones",
+        (CandidateTimedOut, Es) => "\nSe abandonó el candidato a escala {:.2} tras {:.2}s (--encode-timeout), probando otros más pequeños",
+        (AllCandidatesTimedOut, En) => "Nothing that fits could be encoded within --encode-timeout",
+        (AllCandidatesTimedOut, Es) => "No se pudo codificar nada que quepa dentro de --encode-timeout",
+        (FloorJpegQuality, En) => "Reached the scale floor of {:.2}, fitted with JPEG quality {} instead",
+        (FloorJpegQuality, Es) => "Se alcanzó la escala mínima de {:.2}, se ajustó con calidad JPEG {} en su lugar",
+        (FloorGifPalette, En) => "Reached the scale floor of {:.2}, reduced the palette to {} colors instead",
+        (FloorGifPalette, Es) => "Se alcanzó la escala mínima de {:.2}, se redujo la paleta a {} colores en su lugar",
+        (FloorUnreachable, En) => "Couldn't reach the target without going below the scale floor of {:.2}",
+        (FloorUnreachable, Es) => "No se pudo alcanzar el objetivo sin bajar de la escala mínima de {:.2}",
+        (NothingFits, En) => "Nothing fits in {}, not even the smallest scale tried",
+        (NothingFits, Es) => "Nada cabe en {}, ni siquiera la escala más pequeña probada",
+        (SsimTooLow, En) => "The best fit (scale {:.2}) only reaches an SSIM of {:.4}, below --min-ssim {}. The target can't be met at acceptable quality",
+        (SsimTooLow, Es) => "El mejor ajuste (escala {:.2}) solo alcanza un SSIM de {:.4}, por debajo de --min-ssim {}. No se puede llegar al objetivo con una calidad aceptable",
+        (SsimAgainstInput, En) => "SSIM against the input: {:.4}",
+        (SsimAgainstInput, Es) => "SSIM respecto a la entrada: {:.4}",
+        (SsimCantDecode, En) => "Can't decode {} to check --min-ssim, skipping it",
+        (SsimCantDecode, Es) => "No se puede decodificar {} para comprobar --min-ssim, se omite",
+        (ColorShiftTooHigh, En) => "The best fit (scale {:.2}) moves {:.1}% of the colours, over --max-color-shift {}%. The target can't be met without shifting them",
+        (ColorShiftTooHigh, Es) => "El mejor ajuste (escala {:.2}) mueve el {:.1}% de los colores, por encima de --max-color-shift {}%. No se puede llegar al objetivo sin desplazarlos",
+        (ColorShiftAgainstInput, En) => "Colours moved from the input: {:.1}%",
+        (ColorShiftAgainstInput, Es) => "Colores desplazados respecto a la entrada: {:.1}%",
+        (ColorShiftCantDecode, En) => "Can't decode {} to check --max-color-shift, skipping it",
+        (ColorShiftCantDecode, Es) => "No se puede decodificar {} para comprobar --max-color-shift, se omite",
+        (JxlRecompressed, En) => "Lossless JPEG XL recompression already fits the target",
+        (JxlRecompressed, Es) => "La recompresión sin pérdida a JPEG XL ya cabe en el objetivo",
+        (NoExtension, En) => "{} has no file extension",
+        (NoExtension, Es) => "{} no tiene extensión",
+        (InPlaceFormat, En) => "--in-place can't be combined with a different --format",
+        (InPlaceFormat, Es) => "--in-place no se puede combinar con otro --format",
+        (OutputIsInput, En) => {
+            "The output would overwrite {} itself, use --output-dir, --in-place or a size stamp"
+        }
+        (OutputIsInput, Es) => {
+            "La salida sobrescribiría el propio {}, usa --output-dir, --in-place o un sello de tamaño"
+        }
+        (UsingFrame, En) => "Using frame {} of {} as a still",
+        (UsingFrame, Es) => "Usando el fotograma {} de {} como imagen fija",
+        (StillIgnored, En) => "--still only applies to animated input, ignoring it",
+        (StillIgnored, Es) => "--still solo se aplica a entradas animadas, se ignora",
+        (Trimmed, En) => "Trimmed to {} of {} frames, {:.2}s long",
+        (Trimmed, Es) => "Recortada a {} de {} fotogramas, {:.2}s de duración",
+        (TrimIgnored, En) => "--trim-frames and --trim-duration only apply to animated input, ignoring them",
+        (Transformed, En) => "Now {} frames, {:.2}s long",
+        (Transformed, Es) => "Ahora son {} fotogramas, {:.2}s de duración",
+        (TransformIgnored, En) => "--reverse, --boomerang and --speed only apply to animated input, ignoring them",
+        (TransformIgnored, Es) => "--reverse, --boomerang y --speed solo se aplican a entradas animadas, se ignoran",
+        (Stabilized, En) => "Stabilized, cropped to {}x{}",
+        (Stabilized, Es) => "Estabilizada, recortada a {}x{}",
+        (AlreadySteady, En) => "No shake found, nothing to stabilize",
+        (AlreadySteady, Es) => "No hay temblor, nada que estabilizar",
+        (TooMuchMotion, En) => "Too much motion to be shake, leaving it as it is",
+        (TooMuchMotion, Es) => "Demasiado movimiento para ser temblor, se deja como está",
+        (StabilizeIgnored, En) => "--stabilize only applies to animated input, ignoring it",
+        (StabilizeIgnored, Es) => "--stabilize solo se aplica a entradas animadas, se ignora",
+        (Coalesced, En) => "Merged repeated frames, {} of {} left",
+        (Coalesced, Es) => "Fotogramas repetidos fusionados, quedan {} de {}",
+        (AdaptiveDetail, En) => "Adaptive detail: {} segment(s), down to {:.1} of the scale where it moves fastest",
+        (AdaptiveDetail, Es) => "Detalle adaptativo: {} segmento(s), hasta {:.1} de la escala donde más se mueve",
+        (AdaptiveIgnored, En) => "--adaptive only applies to animated output, ignoring it",
+        (AdaptiveIgnored, Es) => "--adaptive solo se aplica a salidas animadas, se ignora",
+        (CroppedToChanges, En) => "Cropped to the {}x{} part that changes",
+        (CroppedToChanges, Es) => "Recortado a la parte de {}x{} que cambia",
+        (StaticSurroundings, En) => "Only a {}x{} part of the animation changes ({:.0}% of it), --crop-to-changes would crop away the rest",
+        (StaticSurroundings, Es) => "Solo cambia una parte de {}x{} de la animación ({:.0}%), --crop-to-changes recortaría el resto",
+        (NothingChanges, En) => "Nothing changes from one frame to the next, so there's nothing to crop to",
+        (NothingChanges, Es) => "Nada cambia de un fotograma a otro, así que no hay a qué recortar",
+        (CropToChangesIgnored, En) => "--crop-to-changes only applies to animated input, ignoring it",
+        (CropToChangesIgnored, Es) => "--crop-to-changes solo se aplica a entradas animadas, se ignora",
+        (CroppedToDetail, En) => "Cropped to {}x{}, keeping the part with the most detail",
+        (CroppedToDetail, Es) => "Recortada a {}x{}, conservando la parte con más detalle",
+        (CroppedToFaces, En) => "Cropped to {}x{} around the faces found ({})",
+        (CroppedToFaces, Es) => "Recortada a {}x{} alrededor de las caras encontradas ({})",
+        (FaceModelIgnored, En) => "--face-model only places --crop, ignoring it",
+        (FaceModelIgnored, Es) => "--face-model solo sirve para colocar --crop, se ignora",
+        (BackgroundRemoved, En) => "Removed the background",
+        (BackgroundRemoved, Es) => "Fondo eliminado",
+        (BackgroundFlattened, En) => "JPEG can't be transparent, the subject goes on the --background colour",
+        (BackgroundFlattened, Es) => "JPEG no admite transparencia, el sujeto va sobre el color de --background",
+        (BackgroundRemovalIgnored, En) => "--remove-background only applies to stills, ignoring it",
+        (BackgroundRemovalIgnored, Es) => "--remove-background solo se aplica a imágenes fijas, se ignora",
+        (TrimIgnored, Es) => "--trim-frames y --trim-duration solo se aplican a entradas animadas, se ignoran",
+        (InterlaceIgnored, En) => "--interlace only applies to PNG and GIF output, ignoring it",
+        (InterlaceIgnored, Es) => "--interlace solo se aplica a salidas PNG y GIF, se ignora",
+        (MinSsimIgnored, En) => "--min-ssim only applies to still images, ignoring it",
+        (MinSsimIgnored, Es) => "--min-ssim solo se aplica a imágenes fijas, se ignora",
+        (ColorShiftIgnored, En) => "--max-color-shift doesn't apply to icons, ignoring it",
+        (ColorShiftIgnored, Es) => "--max-color-shift no se aplica a iconos, se ignora",
+        (DepthIgnored, En) => "--depth doesn't apply to animations, ignoring it",
+        (DepthIgnored, Es) => "--depth no se aplica a animaciones, se ignora",
+        (ProgressiveIgnored, En) => "--progressive only applies to JPEG output, ignoring it",
+        (ProgressiveIgnored, Es) => "--progressive solo se aplica a salidas JPEG, se ignora",
+        (ConvertedToSrgb, En) => "Converted the embedded colour profile to sRGB",
+        (ConvertedToSrgb, Es) => "Se convirtió el perfil de color incrustado a sRGB",
+        (LowMemoryCopy, En) => "Working from a {}x{} copy ({:.2} of the original) to save memory",
+        (LowMemoryCopy, Es) => "Trabajando con una copia de {}x{} ({:.2} del original) para ahorrar memoria",
+        (Retrying, En) => "Couldn't access {} ({}), trying again in {}ms ({} of {})",
+        (Retrying, Es) => "No se pudo acceder a {} ({}), se reintenta en {}ms ({} de {})",
+        (TextUnreadable, En) => "Text would be unreadable at any scale allowed by --max-scale",
+        (TextUnreadable, Es) => "El texto sería ilegible en cualquier escala que permite --max-scale",
+        (TextKeepingScale, En) => "Text detected, keeping the scale at or above {:.2} so it stays readable",
+        (TextKeepingScale, Es) => "Se detectó texto, la escala se mantiene en {:.2} o más para que siga siendo legible",
+        (UsingSubsampling, En) => "Using {} chroma subsampling",
+        (UsingSubsampling, Es) => "Usando submuestreo de croma {}",
+        (ProfileFormat, En) => "The {} profile writes {}",
+        (ProfileFormat, Es) => "El perfil {} escribe {}",
+        (ProfileDetected, En) => "Picked the {} profile for what's in the image (--profile none to turn this off)",
+        (ProfileDetected, Es) => "Se eligió el perfil {} por el contenido de la imagen (--profile none para desactivarlo)",
+        (NoisyInput, En) => "The input is noisy (about {:.1} levels), capping the JPEG quality at {} since more would only keep the grain",
+        (NoisyInput, Es) => "La entrada tiene ruido (unos {:.1} niveles), se limita la calidad JPEG a {} porque más solo conservaría el grano",
+        (TextWarning, En) => "\nWarning: text will likely be unreadable at a scale of {:.2} (needs {:.2}).\nTry a lossier format or lower quality instead, or rerun with --text-check block",
+        (TextWarning, Es) => "\nAviso: el texto probablemente será ilegible a una escala de {:.2} (necesita {:.2}).\nPrueba un formato con más pérdida o menos calidad, o vuelve a ejecutar con --text-check block",
+        (ReplacedInPlace, En) => "Replaced {} with the result",
+        (ReplacedInPlace, Es) => "Se reemplazó {} con el resultado",
+        (CantCopyAttrs, En) => "Couldn't copy the file attributes of {}: {}",
+        (CantCopyAttrs, Es) => "No se pudieron copiar los atributos de {}: {}",
+        (PreviewWritten, En) => "Wrote a {} preview to {}",
+        (PreviewWritten, Es) => "Escrita una vista previa de {} en {}",
+        (PreviewNowhere, En) => "That output only takes the one file, so the preview was left out",
+        (PreviewNowhere, Es) => "Esa salida solo admite un archivo, así que la vista previa se omitió",
+        (AimingUnder, En) => "Aiming for {} to stay {:.1}% under {}",
+        (AimingUnder, Es) => "Apuntando a {} para quedar un {:.1}% por debajo de {}",
+        (StripWritten, En) => "Wrote a strip of {} frames to {}",
+        (StripWritten, Es) => "Escrita una tira de {} fotogramas en {}",
+        (StripNowhere, En) => "That output only takes the one file, so the frame strip was left out",
+        (StripNowhere, Es) => "Esa salida solo admite un archivo, así que la tira de fotogramas se omitió",
+        (StripNotAnimated, En) => "Not an animation, so there's no frame strip to write",
+        (StripNotAnimated, Es) => "No es una animación, así que no hay tira de fotogramas que escribir",
+        (BlurHash, En) => "BlurHash: {}",
+        (BlurHash, Es) => "BlurHash: {}",
+        (IconDropped, En) => "Dropped the {}x{} icon to fit the target",
+        (IconDropped, Es) => "Se quitó el icono de {}x{} para caber en el objetivo",
+        (IconTooLarge, En) => "Not even the smallest icon, {}x{}, fits in {}",
+        (IconTooLarge, Es) => "Ni siquiera el icono más pequeño, de {}x{}, cabe en {}",
+        (IconSizes, En) => "Embedded sizes: {}",
+        (IconSizes, Es) => "Tamaños incluidos: {}",
+        (BackedUpExisting, En) => "Backed up the existing {} to {}",
+        (BackedUpExisting, Es) => "Se guardó una copia del {} existente en {}",
+        (BackedUp, En) => "Backed up {} to {}",
+        (BackedUp, Es) => "Se guardó una copia de {} en {}",
+        (CantRecordHistory, En) => "Couldn't record this run in the history: {}",
+        (CantRecordHistory, Es) => "No se pudo guardar esta ejecución en el historial: {}",
+        (UnknownArgument, En) => "Unknown argument '{}'",
+        (UnknownArgument, Es) => "Argumento desconocido '{}'",
+        (Downloading, En) => "Downloading {}",
+        (Downloading, Es) => "Descargando {}",
+        (SavedTo, En) => "Saved to {}",
+        (SavedTo, Es) => "Guardado en {}",
+        (OutputInPlace, En) => "--output and --in-place can't be used together",
+        (OutputInPlace, Es) => "--output y --in-place no se pueden usar juntos",
+        (InPlaceRemote, En) => "--in-place can't replace {}, give --output instead",
+        (InPlaceRemote, Es) => "--in-place no puede reemplazar {}, usa --output en su lugar",
+        (StepApplied, En) => "{}, now {}",
+        (StepApplied, Es) => "{}, ahora {}",
+        (StepStripped, En) => "Metadata stripped",
+        (StepStripped, Es) => "Metadatos eliminados",
+        (StepReencoded, En) => "Re-encoded at full size",
+        (StepReencoded, Es) => "Recodificado a tamaño completo",
+        (StepQuality, En) => "JPEG quality {} -> {}",
+        (StepQuality, Es) => "Calidad JPEG {} -> {}",
+        (StepColors, En) => "Palette of {} colours",
+        (StepColors, Es) => "Paleta de {} colores",
+        (PaletteShiftsColors, En) => "A palette of {} colours moves {:.1}% of the colours, over --max-color-shift, skipping it",
+        (PaletteShiftsColors, Es) => "Una paleta de {} colores mueve el {:.1}% de los colores, por encima de --max-color-shift, se omite",
+        (StepFrames, En) => "Kept {} of {} frames",
+        (StepFrames, Es) => "Se conservaron {} de {} fotogramas",
+        (StepScale, En) => "Scaled to {:.2}",
+        (StepScale, Es) => "Escalado a {:.2}",
+        (StepSavings, En) => "What each step saved:",
+        (StepSavings, Es) => "Lo que ahorró cada paso:",
+        (NeedsDownscale, En) => "Doesn't fit without downscaling, which --strategies leaves out",
+        (NeedsDownscale, Es) => "No cabe sin reducir la escala, que --strategies deja fuera",
+        (ReviewResult, En) => "Result: {} of {}, at a scale of {:.2}",
+        (ReviewResult, Es) => "Resultado: {} de {}, a una escala de {:.2}",
+        (ReviewChoice, En) => "[a]ccept, [v]iew it, [r]etry with other strategies or [q]uit? (a)",
+        (ReviewChoice, Es) => "¿[a]ceptar, [v]erlo, [r]eintentar con otras estrategias o salir ([q])? (a)",
+        (ReviewStrategies, En) => "Strategies to retry with, from strip,recompress,quantize,drop-frames,downscale (last time: {})",
+        (ReviewStrategies, Es) => "Estrategias para reintentar, de strip,recompress,quantize,drop-frames,downscale (la última vez: {})",
+        (ReviewCancelled, En) => "Cancelled, nothing was written",
+        (ReviewCancelled, Es) => "Cancelado, no se escribió nada",
+        (RetryFailed, En) => "The retry didn't work out ({}), here's the last result again",
+        (RetryFailed, Es) => "El reintento no salió bien ({}), aquí está otra vez el último resultado",
+        (CantOpenViewer, En) => "Couldn't open it in a viewer: {}",
+        (CantOpenViewer, Es) => "No se pudo abrir en un visor: {}",
+        (ChecksumsWritten, En) => "Checksums written to {}",
+        (ChecksumsWritten, Es) => "Sumas de verificación escritas en {}",
+        (ChecksumsFailed, En) => "Couldn't write the checksums: {}",
+        (ChecksumsFailed, Es) => "No se pudieron escribir las sumas de verificación: {}",
+        (ChecksumsRemote, En) => "Checksums are only written for local results",
+        (ChecksumsRemote, Es) => "Las sumas de verificación solo se escriben para resultados locales",
+        (CantSaveCheckpoint, En) => "Couldn't save a checkpoint of the search: {}",
+        (CantSaveCheckpoint, Es) => "No se pudo guardar un punto de control de la búsqueda: {}",
+        (NothingToResume, En) => "There's no interrupted search to resume",
+        (NothingToResume, Es) => "No hay ninguna búsqueda interrumpida que reanudar",
+        (Resuming, En) => "Resuming the search on {} from iteration {} of {}",
+        (Resuming, Es) => "Reanudando la búsqueda en {} desde la iteración {} de {}",
+        (ResumedAt, En) => "Picked up the search at iteration {}, best scale so far {:.2}",
+        (ResumedAt, Es) => "Búsqueda retomada en la iteración {}, mejor escala hasta ahora {:.2}",
+        (CheckpointMismatch, En) => "The checkpoint is for a different search, starting over",
+        (CheckpointMismatch, Es) => "El punto de control es de otra búsqueda, empezando de nuevo",
+    }
+}
+
+// Fills each `{}` or `{:.N}` in a template with the next argument
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let spec = &rest[start + 1..end];
+        match (
+            args.next(),
+            spec.strip_prefix(":.").map(str::parse::<usize>),
+        ) {
+            (Some(arg), Some(Ok(precision))) => out.push_str(&format!("{:.*}", precision, arg)),
+            (Some(arg), _) => out.push_str(&arg.to_string()),
+            (None, _) => out.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// The message in the chosen language, with its arguments filled in
+pub fn translate(msg: Msg, args: &[&dyn Display]) -> String {
+    fill(text(msg, lang()), args)
+}
+
+// `tr!(Msg, args...)`, so call sites read like format!
+macro_rules! tr {
+    ($msg:ident $(, $arg:expr)* $(,)?) => {
+        $crate::messages::translate($crate::messages::Msg::$msg, &[$(&$arg),*])
+    };
+}
+pub(crate) use tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_arguments_in_order() {
+        let args: [&dyn Display; 3] = [&"a.png", &0.123456, &7];
+        assert_eq!(fill("{} at {:.2}, {}ms", &args), "a.png at 0.12, 7ms");
+        assert_eq!(fill("no arguments", &[]), "no arguments");
+        assert_eq!(fill("missing {}", &[]), "missing {}");
+    }
+
+    #[test]
+    fn reads_locale_codes() {
+        assert_eq!(Lang::from_code("es_MX.UTF-8"), Some(Lang::Es));
+        assert_eq!(Lang::from_code("C.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::from_code("fr_FR"), None);
+    }
+}
@@ -0,0 +1,53 @@
+use image::{DynamicImage, GrayImage};
+
+// Noise levels (standard deviation in 8-bit steps) and the JPEG quality
+// above which a source that noisy gains nothing: the extra bytes go into
+// keeping the grain, not the picture. Noisiest first
+const CAPS: [(f64, u8); 2] = [(8.0, 80), (3.0, 85)];
+
+// Blocks the estimate is taken over, and the share of them, flattest first,
+// that it's taken from. Edges and texture look like noise to the filter, so
+// only the flattest blocks say how noisy the source is
+const BLOCK: u32 = 16;
+const FLAT_SHARE: f64 = 0.25;
+
+// Immerkaer's fast noise estimate: the Laplacian difference below cancels
+// out smooth gradients, so what's left in flat areas is the noise
+pub fn estimate(img: &GrayImage) -> f64 {
+    let (w, h) = img.dimensions();
+    if w < 3 || h < 3 {
+        return 0.0;
+    }
+    let p = |x: u32, y: u32| img.get_pixel(x, y)[0] as f64;
+    let (bw, bh) = ((w - 2).div_ceil(BLOCK), (h - 2).div_ceil(BLOCK));
+    let mut blocks = vec![(0.0, 0u32); (bw * bh) as usize];
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let corners = p(x - 1, y - 1) + p(x + 1, y - 1) + p(x - 1, y + 1) + p(x + 1, y + 1);
+            let sides = p(x, y - 1) + p(x - 1, y) + p(x + 1, y) + p(x, y + 1);
+            let d = (corners - 2.0 * sides + 4.0 * p(x, y)).abs();
+            let block = &mut blocks[(((y - 1) / BLOCK) * bw + (x - 1) / BLOCK) as usize];
+            block.0 += d;
+            block.1 += 1;
+        }
+    }
+    let mut means = blocks
+        .iter()
+        .map(|&(sum, n)| sum / n as f64)
+        .collect::<Vec<_>>();
+    means.sort_by(|a, b| a.total_cmp(b));
+    let flat = &means[..((means.len() as f64 * FLAT_SHARE).ceil() as usize).max(1)];
+    let mean = flat.iter().sum::<f64>() / flat.len() as f64;
+    // The filter's response to unit noise has a mean absolute value of
+    // 6 sqrt(2 / pi)
+    mean * (std::f64::consts::PI / 2.0).sqrt() / 6.0
+}
+
+// The noise level, and the highest JPEG quality worth spending bytes on for
+// it, when the source is noisy enough for there to be one
+pub fn quality_cap(img: &DynamicImage) -> Option<(f64, u8)> {
+    let sigma = estimate(&img.to_luma8());
+    CAPS.iter()
+        .find(|&&(level, _)| sigma >= level)
+        .map(|&(_, cap)| (sigma, cap))
+}
@@ -0,0 +1,54 @@
+use std::{error::Error, path::Path};
+
+use image::{imageops, DynamicImage};
+use ort::{session::Session, value::Tensor};
+
+// An ONNX model ready to run, with the width and height of the image its
+// first input takes. Models with a fixed 1x3xHxW input say so, ones that
+// take any size get default
+pub struct Model {
+    pub session: Session,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Model {
+    pub fn load(path: &Path, default: (u32, u32)) -> Result<Model, Box<dyn Error>> {
+        let session = Session::builder()?
+            .commit_from_file(path)
+            .map_err(|e| format!("Couldn't load the model {}: {}", path.display(), e))?;
+        let (w, h) = match session
+            .inputs
+            .first()
+            .and_then(|i| i.input_type.tensor_shape())
+        {
+            Some(shape) if shape.len() == 4 && shape[2] > 0 && shape[3] > 0 => {
+                (shape[3] as u32, shape[2] as u32)
+            }
+            _ => default,
+        };
+        Ok(Model { session, w, h })
+    }
+
+    // img resized to what the model takes, as a 1x3xHxW tensor of each
+    // channel's values through normalize(channel, value)
+    pub fn input(
+        &self,
+        img: &DynamicImage,
+        normalize: impl Fn(usize, u8) -> f32,
+    ) -> Result<Tensor<f32>, Box<dyn Error>> {
+        let (w, h) = (self.w, self.h);
+        let small = imageops::resize(&img.to_rgb8(), w, h, imageops::FilterType::Triangle);
+        let plane = w as usize * h as usize;
+        let mut data = vec![0.0; plane * 3];
+        for (i, pixel) in small.pixels().enumerate() {
+            for c in 0..3 {
+                data[c * plane + i] = normalize(c, pixel[c]);
+            }
+        }
+        Ok(Tensor::from_array((
+            [1usize, 3, h as usize, w as usize],
+            data,
+        ))?)
+    }
+}
@@ -0,0 +1,658 @@
+use std::{path::PathBuf, time::Duration};
+
+use image::imageops::FilterType;
+
+use crate::{
+    animation::{Still, Transform, Trim},
+    backup, color, crop,
+    depth::Depth,
+    dither::Dither,
+    encode::{EncoderSettings, Subsampling},
+    formats, ico,
+    legibility::TextCheck,
+    plan::Strategy,
+    profile::Profile,
+    resample,
+    resume::Session,
+    retry::{self, Retry},
+    settings,
+    tools::{self, ExternalTool},
+    units, SizeStamp,
+};
+
+// Everything a run needs, gathered from command-line flags and the prompts
+#[derive(Clone)]
+pub struct Options {
+    pub target: u64,
+    // Set with --target, which skips the prompts and keeps the last run's
+    // other settings
+    pub given_target: Option<u64>,
+    // Share of the target to stay under, for services that measure a file
+    // after re-muxing it themselves, see Options::aim
+    pub margin: f64,
+    pub byte_halt: u64,
+    pub iters: i32,
+    // Never resize below/above these fractions of the original resolution
+    pub min_scale: f64,
+    pub max_scale: f64,
+    pub text_check: TextCheck,
+    // Fail instead of writing a result whose SSIM against the input is lower
+    pub min_ssim: Option<f64>,
+    // Content the settings are tuned for, see profile. Without one, the
+    // profile is picked per image when classify is on
+    pub profile: Option<Profile>,
+    pub classify: bool,
+    // Flags given on the command line, which a profile leaves alone
+    pub given: Vec<String>,
+    // What candidates are resized with, and whether only by whole factors
+    pub filter: FilterType,
+    pub whole_factors: bool,
+    // Reject palettes, and fail on results, that move more than this share
+    // of the input's colour histogram, see compare::histogram_shift
+    pub max_color_shift: Option<f64>,
+    pub encoder: EncoderSettings,
+    // External optimizers from the config file
+    pub tools: Vec<ExternalTool>,
+    // Where outputs are written, the current directory when None
+    pub output_dir: Option<PathBuf>,
+    // Anything an output would replace is copied here first
+    pub backup_dir: PathBuf,
+    // Retries for reads and writes that fail for a moment, see retry
+    pub retry: Retry,
+    // Replace the input with the result instead of writing a new file
+    pub in_place: bool,
+    // Copy the input's timestamps, permissions and xattrs to the output
+    pub preserve_attrs: bool,
+    // Convert inputs with an embedded ICC profile to sRGB
+    pub srgb: bool,
+    // Work from a reduced copy of huge JPEG/PNG inputs, see lowmem
+    pub low_memory: bool,
+    // Predict the scale on a small proxy of large images first, see
+    // oracle::presearch
+    pub presearch: bool,
+    // Give up on a search candidate whose encode takes longer than this, see
+    // pipeline::with_timeout
+    pub encode_timeout: Option<Duration>,
+    // Lower the JPEG quality of noisy inputs, see noise::quality_cap
+    pub noise_cap: bool,
+    // Same input and settings, same output bytes: a fixed seed for the
+    // search and no external tools
+    pub reproducible: bool,
+    // Leave out the search's progress and notes, for callers of the library
+    // that have their own way of reporting
+    pub quiet: bool,
+    // Also report progress as JSON lines on stderr, see progress
+    pub progress_json: bool,
+    // Set for single runs from the command line, whose GIF searches save
+    // checkpoints to resume from, see resume
+    pub session: Option<Session>,
+    // What the planner may do to get under the target, see plan
+    pub strategies: Vec<Strategy>,
+    // Show the planner's result and ask before writing it, see review
+    pub review: bool,
+    // Show the result in the system's viewer once it's written
+    pub open: bool,
+    // Output format by extension, the input's format when None
+    pub format: Option<String>,
+    // What output names say about their size, and whether they carry a hash
+    // of the content
+    pub size_stamp: SizeStamp,
+    pub hash: bool,
+    // What the picker and directory inputs list: symlinks are followed
+    // unless --no-symlinks, dot files are included unless --skip-hidden
+    pub follow_symlinks: bool,
+    pub skip_hidden: bool,
+    // What transparent pixels are composited onto for JPEG output
+    pub background: [u8; 3],
+    // Fit one frame of an animation as a still image
+    pub still: Option<Still>,
+    // Part of an animation to keep before fitting it
+    pub trim: Trim,
+    // Reversing, boomeranging and speed changes, after the trim
+    pub transform: Transform,
+    // Take camera shake out of animations, see stabilize
+    pub stabilize: bool,
+    // Merge consecutive frames within this much of each other, see
+    // Animation::coalesce. None, the default, or with `--coalesce off`
+    pub coalesce: Option<u8>,
+    // Give frames in fast motion less detail than still ones, see
+    // Animation::detail_by_motion
+    pub adaptive: bool,
+    // Crop animations to the part that changes, see
+    // Animation::changing_region
+    pub crop_to_changes: bool,
+    // Size of a blurred placeholder to write next to the output, see preview
+    pub preview: Option<u64>,
+    // Frames of a fitted animation to lay side by side in a PNG, see strip
+    pub preview_strip: Option<usize>,
+    // Print the output's BlurHash
+    pub blurhash: bool,
+    // ONNX model to cut stills out of their background with, see matte
+    pub remove_background: Option<PathBuf>,
+    // Aspect ratio to crop to before fitting, placed by crop::region, and
+    // the ONNX face detector that helps place it
+    pub crop: Option<(u32, u32)>,
+    pub face_model: Option<PathBuf>,
+    // Sort batch and tray outputs into YYYY/MM folders, see batch::date_folder
+    pub by_date: bool,
+    // Write a SHA256SUMS manifest of the outputs, see checksums
+    pub checksums: bool,
+    // Sizes embedded in ICO output
+    pub ico_sizes: Vec<u32>,
+    // --input and --output of a single run, a local path or an s3:// or gs://
+    // URL, see remote
+    pub input: Option<String>,
+    pub output: Option<String>,
+    // Arguments that aren't flags, for the subcommands that take files
+    pub inputs: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            target: 1000,
+            given_target: None,
+            margin: 0.0,
+            byte_halt: 128,
+            iters: 256,
+            min_scale: 0.0,
+            max_scale: f64::INFINITY,
+            text_check: TextCheck::Off,
+            min_ssim: None,
+            profile: None,
+            classify: true,
+            given: Vec::new(),
+            filter: FilterType::Lanczos3,
+            whole_factors: false,
+            max_color_shift: None,
+            encoder: EncoderSettings::default(),
+            tools: Vec::new(),
+            output_dir: None,
+            backup_dir: settings::data_dir().join("backups"),
+            retry: Retry::default(),
+            in_place: false,
+            preserve_attrs: false,
+            srgb: false,
+            low_memory: false,
+            presearch: true,
+            encode_timeout: None,
+            noise_cap: true,
+            reproducible: false,
+            quiet: false,
+            progress_json: false,
+            session: None,
+            strategies: Strategy::ALL.to_vec(),
+            review: false,
+            open: false,
+            format: None,
+            size_stamp: SizeStamp::Rounded,
+            hash: false,
+            follow_symlinks: true,
+            skip_hidden: false,
+            background: [255, 255, 255],
+            still: None,
+            trim: Trim::default(),
+            transform: Transform::default(),
+            stabilize: false,
+            coalesce: None,
+            adaptive: false,
+            crop_to_changes: false,
+            preview: None,
+            preview_strip: None,
+            blurhash: false,
+            remove_background: None,
+            crop: None,
+            face_model: None,
+            by_date: false,
+            checksums: false,
+            ico_sizes: ico::DEFAULT_SIZES.to_vec(),
+            input: None,
+            output: None,
+            inputs: Vec::new(),
+        }
+    }
+}
+
+// Accepts both `0.25` and `25%`
+fn parse_scale(flag: &str, value: &str) -> Result<f64, String> {
+    let scale = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => value.trim().parse::<f64>(),
+    }
+    .map_err(|_| format!("{} expects a scale like 0.25 or 25%, got '{}'", flag, value))?;
+    if !scale.is_finite() || scale <= 0.0 {
+        return Err(format!("{} must be greater than zero", flag));
+    }
+    Ok(scale)
+}
+
+impl Options {
+    // The scale a candidate at scale is actually resized to
+    pub fn resize_scale(&self, scale: f64) -> f64 {
+        match self.whole_factors {
+            true => resample::whole_factor(scale),
+            false => scale,
+        }
+    }
+
+    // The size the search treats as the target: target less the --margin
+    pub fn aim(&self, target: u64) -> u64 {
+        ((target as f64 * (1.0 - self.margin)).floor() as u64).max(1)
+    }
+
+    // Whether the flag was on the command line
+    pub fn given(&self, flag: &str) -> bool {
+        self.given.iter().any(|f| f == flag)
+    }
+
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            tools: tools::load(),
+            backup_dir: backup::default_dir(),
+            retry: retry::default_policy(),
+            ..Options::default()
+        };
+        let mut args = args.iter().cloned();
+        while let Some(arg) = args.next() {
+            // Allow both `--flag value` and `--flag=value`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) => (f.to_string(), Some(v.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or(format!("{} expects a value", flag))
+            };
+            if flag.starts_with("--") {
+                options.given.push(flag.clone());
+            }
+            match flag.as_str() {
+                "--target" => {
+                    let value = value()?;
+                    options.given_target = Some(
+                        units::parse_size(&value)
+                            .filter(|&size| size >= 128)
+                            .ok_or(format!(
+                            "--target expects a size of at least 128 B like 500k or 8MB, got '{}'",
+                            value
+                        ))?,
+                    )
+                }
+                "--min-scale" => options.min_scale = parse_scale(&flag, &value()?)?,
+                "--max-scale" => options.max_scale = parse_scale(&flag, &value()?)?,
+                "--margin" => {
+                    let value = value()?;
+                    let margin = match value.strip_suffix('%') {
+                        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+                        None => value.trim().parse::<f64>(),
+                    };
+                    options.margin =
+                        margin
+                            .ok()
+                            .filter(|m| (0.0..0.5).contains(m))
+                            .ok_or(format!(
+                                "--margin expects a share under half like 3% or 0.03, got '{}'",
+                                value
+                            ))?;
+                }
+                "--text-check" => {
+                    options.text_check = match value()?.as_str() {
+                        "off" => TextCheck::Off,
+                        "warn" => TextCheck::Warn,
+                        "block" => TextCheck::Block,
+                        other => {
+                            return Err(format!(
+                                "--text-check expects off, warn or block, got '{}'",
+                                other
+                            ))
+                        }
+                    }
+                }
+                "--min-ssim" => {
+                    let value = value()?;
+                    options.min_ssim = Some(
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|s| (0.0..=1.0).contains(s))
+                            .ok_or(format!(
+                                "--min-ssim expects a value from 0 to 1 like 0.9, got '{}'",
+                                value
+                            ))?,
+                    )
+                }
+                "--max-color-shift" => {
+                    let value = value()?;
+                    let shift = parse_scale("--max-color-shift", &value)?;
+                    if shift > 1.0 {
+                        return Err(format!(
+                            "--max-color-shift expects a share like 0.05 or 5%, got '{}'",
+                            value
+                        ));
+                    }
+                    options.max_color_shift = Some(shift);
+                }
+                "--profile" => {
+                    let value = value()?;
+                    options.classify = value != "none";
+                    options.profile = match value.as_str() {
+                        "auto" | "none" => None,
+                        _ => Some(Profile::parse(&value).ok_or(format!(
+                            "--profile expects screenshot, photo, pixel-art, auto or none, got '{}'",
+                            value
+                        ))?),
+                    }
+                }
+                "--input" => options.input = Some(value()?),
+                "--output" => options.output = Some(value()?),
+                "--output-dir" => options.output_dir = Some(PathBuf::from(value()?)),
+                "--backup-dir" => options.backup_dir = PathBuf::from(value()?),
+                "--retries" => {
+                    let value = value()?;
+                    options.retry.attempts = value.parse().map_err(|_| {
+                        format!("--retries expects a number of attempts, got '{}'", value)
+                    })?
+                }
+                "--in-place" => options.in_place = true,
+                "--preserve-attrs" => options.preserve_attrs = true,
+                "--srgb" => options.srgb = true,
+                "--low-memory" => options.low_memory = true,
+                "--no-presearch" => options.presearch = false,
+                "--encode-timeout" => {
+                    let value = value()?;
+                    options.encode_timeout = Some(Duration::from_millis(
+                        units::parse_duration(&value)
+                            .filter(|&ms| ms > 0)
+                            .ok_or(format!(
+                            "--encode-timeout expects a length of time like 30s or 2m, got '{}'",
+                            value
+                        ))?,
+                    ))
+                }
+                "--no-noise-cap" => options.noise_cap = false,
+                "--size-stamp" => {
+                    let value = value()?;
+                    options.size_stamp = SizeStamp::parse(&value).ok_or(format!(
+                        "--size-stamp expects rounded, exact or none, got '{}'",
+                        value
+                    ))?
+                }
+                "--hash" => options.hash = true,
+                "--no-symlinks" => options.follow_symlinks = false,
+                "--skip-hidden" => options.skip_hidden = true,
+                "--reproducible" => options.reproducible = true,
+                "--review" => options.review = true,
+                "--open" => options.open = true,
+                "--progress-json" => options.progress_json = true,
+                "--strategies" => {
+                    let value = value()?;
+                    options.strategies = Strategy::parse_list(&value).ok_or(format!(
+                            "--strategies expects a list of strip, recompress, quantize, drop-frames and downscale, got '{}'",
+                            value
+                        ))?;
+                }
+                "--format" => {
+                    let value = value()?.trim_start_matches('.').to_ascii_lowercase();
+                    formats::check_write(&value).map_err(|e| e.to_string())?;
+                    options.format = Some(value);
+                }
+                "--still" => {
+                    let value = value()?;
+                    options.still = Some(match value.as_str() {
+                        "auto" => Still::Auto,
+                        index => Still::Frame(index.parse().map_err(|_| {
+                            format!("--still expects auto or a frame index, got '{}'", value)
+                        })?),
+                    })
+                }
+                "--trim-frames" => {
+                    let value = value()?;
+                    let range = value.split_once("..").and_then(|(start, end)| {
+                        let start = match start.trim() {
+                            "" => 0,
+                            start => start.parse().ok()?,
+                        };
+                        let end = match end.trim() {
+                            "" => None,
+                            end => Some(end.parse().ok()?),
+                        };
+                        Some((start, end))
+                    });
+                    options.trim.frames = Some(range.ok_or(format!(
+                        "--trim-frames expects a range of frames like 10..40, 10.. or ..40, got '{}'",
+                        value
+                    ))?);
+                }
+                "--trim-duration" => {
+                    let value = value()?;
+                    options.trim.duration = Some(units::parse_duration(&value).ok_or(format!(
+                        "--trim-duration expects a length like 5s or 1500ms, got '{}'",
+                        value
+                    ))?);
+                }
+                "--by-date" => options.by_date = true,
+                "--checksums" => options.checksums = true,
+                "--stabilize" => options.stabilize = true,
+                "--adaptive" => options.adaptive = true,
+                "--crop-to-changes" => options.crop_to_changes = true,
+                "--blurhash" => options.blurhash = true,
+                "--preview" => {
+                    let value = value()?;
+                    options.preview = Some(
+                        units::parse_size(&value)
+                            .filter(|&size| size >= 128)
+                            .ok_or(format!(
+                                "--preview expects a size of at least 128 B like 2k, got '{}'",
+                                value
+                            ))?,
+                    )
+                }
+                "--preview-strip" => {
+                    let value = value()?;
+                    options.preview_strip = Some(
+                        value
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or(format!(
+                                "--preview-strip expects a number of frames, got '{}'",
+                                value
+                            ))?,
+                    )
+                }
+                "--coalesce" => {
+                    let value = value()?;
+                    options.coalesce = match value.as_str() {
+                        "off" => None,
+                        _ => Some(value.parse().map_err(|_| {
+                            format!(
+                                "--coalesce expects a tolerance from 0 to 255 or off, got '{}'",
+                                value
+                            )
+                        })?),
+                    }
+                }
+                "--reverse" => options.transform.reverse = true,
+                "--boomerang" => options.transform.boomerang = true,
+                "--speed" => {
+                    let value = value()?;
+                    options.transform.speed = Some(
+                        value
+                            .trim()
+                            .trim_end_matches(['x', 'X'])
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|s| s.is_finite() && *s > 0.0)
+                            .ok_or(format!(
+                                "--speed expects a factor like 2 or 0.5x, got '{}'",
+                                value
+                            ))?,
+                    );
+                }
+                "--ico-sizes" => {
+                    let value = value()?;
+                    options.ico_sizes = value
+                        .split(',')
+                        .map(|size| {
+                            size.trim()
+                                .parse::<u32>()
+                                .ok()
+                                .filter(|s| (1..=256).contains(s))
+                        })
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(format!(
+                            "--ico-sizes expects sizes from 1 to 256 like 16,32,48, got '{}'",
+                            value
+                        ))?;
+                }
+                "--background" => {
+                    let value = value()?;
+                    options.background = color::parse_hex(&value).ok_or(format!(
+                        "--background expects a colour like #RRGGBB, got '{}'",
+                        value
+                    ))?
+                }
+                "--no-tools" => options.tools.clear(),
+                "--progressive" => options.encoder.progressive = true,
+                "--zopfli" => options.encoder.zopfli = true,
+                "--gif-opt" => {
+                    options.encoder.gif_optimize = match value()?.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        other => {
+                            return Err(format!("--gif-opt expects on or off, got '{}'", other))
+                        }
+                    }
+                }
+                "--dither" => {
+                    let value = value()?;
+                    options.encoder.dither = Dither::parse(&value).ok_or(format!(
+                        "--dither expects none, ordered, floyd-steinberg or blue-noise, got '{}'",
+                        value
+                    ))?
+                }
+                "--gif-palette" => {
+                    options.encoder.gif_global_palette = match value()?.as_str() {
+                        "global" => true,
+                        "local" => false,
+                        other => {
+                            return Err(format!(
+                                "--gif-palette expects global or local, got '{}'",
+                                other
+                            ))
+                        }
+                    }
+                }
+                "--png-opt" => {
+                    let value = value()?;
+                    options.encoder.png_level = match value.as_str() {
+                        "off" => None,
+                        level => match level.parse::<u8>() {
+                            Ok(level) if level <= 6 => Some(level),
+                            _ => {
+                                return Err(format!(
+                                    "--png-opt expects a level from 0 to 6 or off, got '{}'",
+                                    value
+                                ))
+                            }
+                        },
+                    }
+                }
+                "--crop" => {
+                    let value = value()?;
+                    options.crop = Some(crop::parse_aspect(&value).ok_or(format!(
+                        "--crop expects an aspect ratio like 1:1 or 16:9, got '{}'",
+                        value
+                    ))?)
+                }
+                "--face-model" if cfg!(feature = "faces") => {
+                    options.face_model = Some(PathBuf::from(value()?))
+                }
+                "--face-model" => {
+                    return Err("--face-model needs autosize built with `--features faces`".into())
+                }
+                "--remove-background" if cfg!(feature = "background") => {
+                    options.remove_background = Some(PathBuf::from(value()?))
+                }
+                "--remove-background" => {
+                    return Err(
+                        "--remove-background needs autosize built with `--features background`"
+                            .into(),
+                    )
+                }
+                "--mozjpeg" if cfg!(feature = "mozjpeg") => options.encoder.mozjpeg = true,
+                "--mozjpeg" => {
+                    return Err("--mozjpeg needs autosize built with `--features mozjpeg`".into())
+                }
+                "--depth" => {
+                    let value = value()?;
+                    options.encoder.depth = Depth::parse(&value).ok_or(format!(
+                        "--depth expects full, rgb565 or gray4, got '{}'",
+                        value
+                    ))?
+                }
+                "--jxl-distance" | "--jxl-effort" if !cfg!(feature = "jxl") => {
+                    return Err(format!(
+                        "{} needs autosize built with `--features jxl`",
+                        flag
+                    ))
+                }
+                "--jxl-distance" => {
+                    let value = value()?;
+                    options.encoder.jxl_distance = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|d| (0.0..=25.0).contains(d))
+                        .ok_or(format!(
+                            "--jxl-distance expects a distance from 0 to 25, got '{}'",
+                            value
+                        ))?
+                }
+                "--jxl-effort" => {
+                    let value = value()?;
+                    options.encoder.jxl_effort = value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|e| (1..=9).contains(e))
+                        .ok_or(format!(
+                            "--jxl-effort expects an effort from 1 to 9, got '{}'",
+                            value
+                        ))?
+                }
+                "--quality" => {
+                    let value = value()?;
+                    options.encoder.jpeg_quality = value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|q| (1..=100).contains(q))
+                        .ok_or(format!(
+                            "--quality expects a JPEG quality from 1 to 100, got '{}'",
+                            value
+                        ))?
+                }
+                "--interlace" => options.encoder.interlaced = true,
+                "--subsampling" => {
+                    let value = value()?;
+                    options.encoder.subsampling = Subsampling::parse(&value).ok_or(format!(
+                        "--subsampling expects 444, 422, 420 or auto, got '{}'",
+                        value
+                    ))?
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown argument '{}'", arg)),
+                _ => options.inputs.push(PathBuf::from(arg)),
+            }
+        }
+        if options.min_scale > options.max_scale {
+            return Err("--min-scale can't be larger than --max-scale".to_string());
+        }
+        // Whatever the tools do is out of our hands, and which candidates
+        // finish in time depends on how busy the machine is
+        if options.reproducible {
+            options.tools.clear();
+            options.encode_timeout = None;
+        }
+        Ok(options)
+    }
+}
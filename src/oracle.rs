@@ -0,0 +1,414 @@
+use std::{
+    error::Error,
+    io::{self, Write},
+};
+
+use image::DynamicImage;
+use rand::Rng;
+
+use crate::{encode_candidate, messages::tr, options::Options, pipeline, progress, resized};
+
+// Tells the search how big a candidate at some scale comes out. Real runs
+// encode, tests can answer from a model without encoding anything
+pub trait SizeOracle {
+    // Whatever a measurement leaves behind, kept for the best candidate so
+    // the winner never has to be produced twice
+    type Candidate;
+
+    fn measure(&mut self, scale: f64) -> Result<(u64, Self::Candidate), Box<dyn Error>>;
+}
+
+// The size a candidate is measured at when its encode ran past
+// --encode-timeout. It never fits, so the search moves below it
+pub const TIMED_OUT: u64 = u64::MAX;
+
+// Resizes the image and encodes it with the run's settings and tools
+pub struct Encoder<'a> {
+    pub img: &'a DynamicImage,
+    pub ext: &'a str,
+    pub opts: &'a Options,
+    // How many candidates have been encoded so far
+    pub encodes: u32,
+    // The smallest scale whose encode ran past --encode-timeout. Bigger
+    // ones would take at least as long, so they're not tried
+    pub too_slow: Option<f64>,
+}
+
+impl SizeOracle for Encoder<'_> {
+    type Candidate = Vec<u8>;
+
+    fn measure(&mut self, scale: f64) -> Result<(u64, Vec<u8>), Box<dyn Error>> {
+        if self.too_slow.is_some_and(|slow| scale >= slow) {
+            return Ok((TIMED_OUT, Vec::new()));
+        }
+        self.encodes += 1;
+        let img = resized(self.img, scale, self.opts);
+        let Some(timeout) = self.opts.encode_timeout else {
+            let bytes = encode_candidate(&img, self.ext, self.opts)?;
+            return Ok((bytes.len() as u64, bytes));
+        };
+        let (img, ext, opts) = (img.into_owned(), self.ext.to_string(), self.opts.clone());
+        let encoded = pipeline::with_timeout(timeout, move || {
+            encode_candidate(&img, &ext, &opts).map_err(|e| e.to_string())
+        })?;
+        match encoded {
+            Some(bytes) => Ok((bytes.len() as u64, bytes)),
+            None => {
+                if !self.opts.quiet {
+                    println!("{}", tr!(CandidateTimedOut, scale, timeout.as_secs_f64()));
+                }
+                self.too_slow = Some(scale);
+                Ok((TIMED_OUT, Vec::new()))
+            }
+        }
+    }
+}
+
+// A scale that fit, with what measuring it produced
+pub type Best<C> = (f64, C);
+
+// Narrows a range of scales down to the largest whose size fits
+// opts.target, trying random scales inside it. Returns the best scale and
+// its candidate, or None if nothing between the scale bounds fit
+pub fn bracket<O: SizeOracle>(
+    oracle: &mut O,
+    opts: &Options,
+    ratio: f64,
+    rng: &mut impl Rng,
+) -> Result<Option<Best<O::Candidate>>, Box<dyn Error>> {
+    let (target, m, byte_diff) = (opts.target, opts.iters, opts.byte_halt);
+    let (osize, original) = oracle.measure(1.0)?;
+    let osize = osize as f64;
+    let mut psize = osize;
+    if psize < target as f64 {
+        psize = target as f64;
+    }
+    let mut scale = 1.0f64.clamp(opts.min_scale, opts.max_scale);
+    let (mut size, mut candidate) = if scale == 1.0 {
+        (osize as u64, original)
+    } else {
+        oracle.measure(scale)?
+    };
+    let mut best = None;
+    // println!("Scale: {}, v: {}, OFF: {}", scale, v, (1.50001 * (1.0 - v)) + v);
+    let mut i = 0;
+    let mut diff_ratio = 0f64;
+    let mut diff = 0f64;
+    let mut imgsize;
+    let mut best_diff = f64::MAX;
+    let (mut a, mut b) = (0.0f64, 1.0f64);
+    if target > osize as u64 {
+        a = b;
+        b = (target as f64 / osize) * 1.05;
+    }
+    a = a.max(opts.min_scale);
+    b = b.min(opts.max_scale);
+
+    let quiet = opts.quiet;
+    if !quiet {
+        println!("{}", tr!(Starting));
+    }
+    progress::emit(
+        opts,
+        "search",
+        &[("target", target.into()), ("iterations", (m as u64).into())],
+    );
+    while (diff.abs() > byte_diff as f64 || diff_ratio != 1.0 || diff_ratio > 1.0)
+        || i == 0
+        || size > target
+    {
+        imgsize = size as f64;
+        diff = imgsize - target as f64;
+        diff_ratio = (imgsize * ratio) / target as f64;
+
+        if diff.abs() < best_diff.abs() && diff <= 0.0 {
+            best_diff = diff;
+            best = Some((scale, candidate));
+            if !quiet {
+                println!("\r\u{8}||{:^wa$}({:6.2}%) || BEST_DIFF: {:>width$}, BEST_SCALE: {:5.2} || SCALE: {:.2} || RANGE: ({:>5.2}:{:<5.2}) ||", i, (i as f32 / m as f32) * 100.0, best_diff, scale, scale, a, b, wa=(m.to_string().len()+2), width=(psize.to_string().len()));
+            }
+        }
+        let best_scale = best.as_ref().map_or(f64::NAN, |b: &Best<_>| b.0);
+        progress::iteration(opts, i, scale, size, best_scale, (a, b));
+
+        // Close enough only counts from below, a candidate just over the
+        // target doesn't fit
+        let close = diff <= 0.0 && diff.abs() < byte_diff as f64;
+        if i > m || (1.0 - (a.min(b) / a.max(b))).abs() < 0.05 || close {
+            break;
+        } else if !quiet {
+            print!("\r\u{8}");
+            print!(
+                "||{:^wa$}({:3.2}%) ||",
+                i,
+                (i as f32 / m as f32) * 100.0,
+                wa = (m.to_string().len() + 2)
+            );
+            io::stdout().flush().unwrap();
+        }
+
+        let lscale = scale;
+        if imgsize < target as f64 {
+            a = scale - (1.0 / (i + 2) as f64);
+        } else {
+            b = scale + (1.0 / (i + 2) as f64);
+        }
+        a = a.max(opts.min_scale);
+        b = b.min(opts.max_scale);
+        if a >= b {
+            break;
+        }
+        scale = rng.gen_range(a..b);
+        if scale < 0.0 {
+            scale = lscale;
+        }
+        (size, candidate) = oracle.measure(scale)?;
+
+        i += 1;
+    }
+    let at = format!("{:^wa$}", i, wa = (m.to_string().len() + 2));
+    if !quiet {
+        println!("{}", tr!(StoppedAt, at, (i as f32 / m as f32) * 100.0));
+    }
+    let best_scale = best.as_ref().map_or(f64::NAN, |b: &Best<_>| b.0);
+    progress::emit(
+        opts,
+        "search_done",
+        &[
+            ("iterations", (i as u64).into()),
+            ("scale", best_scale.into()),
+        ],
+    );
+    Ok(best)
+}
+
+// Checks a predicted scale with up to `tries` measurements. The size grows
+// with the pixel count, so each miss is corrected by the square root of how
+// far off it was, aiming a little under the target so the correction lands
+// inside it. Returns the largest scale that fit, if any did
+pub fn refine<O: SizeOracle>(
+    oracle: &mut O,
+    opts: &Options,
+    predicted: f64,
+    tries: u32,
+) -> Result<Option<Best<O::Candidate>>, Box<dyn Error>> {
+    let target = opts.target as f64;
+    let goal = target - (opts.byte_halt as f64 / 2.0).max(target * 0.015);
+    let mut scale = predicted.clamp(opts.min_scale, opts.max_scale);
+    let mut best: Option<Best<O::Candidate>> = None;
+    for _ in 0..tries {
+        let (size, candidate) = oracle.measure(scale)?;
+        let timed_out = size == TIMED_OUT;
+        let size = size as f64;
+        if size <= target {
+            if best.as_ref().is_none_or(|(s, _)| scale > *s) {
+                best = Some((scale, candidate));
+            }
+            if size >= goal || target - size <= opts.byte_halt as f64 {
+                break;
+            }
+        }
+        // A candidate that ran out of time says nothing about its size,
+        // only that smaller ones are worth a try
+        let next = match timed_out {
+            true => scale / 2.0,
+            false => scale * (goal / size).sqrt(),
+        };
+        let next = next.clamp(opts.min_scale, opts.max_scale);
+        if next == scale {
+            break;
+        }
+        scale = next;
+    }
+    Ok(best)
+}
+
+// Images with fewer pixels than this are searched directly, a proxy
+// wouldn't save enough to make up for its own encodes
+pub const PRESEARCH_PIXELS: u64 = 2_000_000;
+// Width and height of the proxy relative to the image
+const PROXY: f64 = 0.25;
+
+// On large images the full-size encodes are most of the work. Runs the
+// search on a quarter-size proxy to predict the scale, then checks it with a
+// few full-size encodes. None when none of those fit, leaving it to the
+// full search
+pub fn presearch(
+    full: &mut Encoder,
+    ratio: f64,
+    rng: &mut impl Rng,
+) -> Result<Option<Best<Vec<u8>>>, Box<dyn Error>> {
+    let opts = full.opts;
+    // The proxy can't show anything about scales it has no pixels for
+    if opts.min_scale >= PROXY * 0.95 {
+        return Ok(None);
+    }
+    if !opts.quiet {
+        println!("{}", tr!(Presearch));
+    }
+    let proxy = resized(full.img, PROXY, opts);
+    let proxy_opts = Options {
+        min_scale: opts.min_scale / PROXY,
+        max_scale: (opts.max_scale / PROXY).min(1.0),
+        ..opts.clone()
+    };
+    let mut small = Encoder {
+        img: &proxy,
+        ext: full.ext,
+        opts: &proxy_opts,
+        encodes: 0,
+        too_slow: None,
+    };
+    let predicted = match bracket(&mut small, &proxy_opts, ratio, rng)? {
+        Some((scale, _)) if scale < 0.95 => scale * PROXY,
+        // The whole proxy fits, so the answer is past it. Extrapolate from
+        // its size instead
+        _ => PROXY * (opts.target as f64 / small.measure(1.0)?.0 as f64).sqrt(),
+    };
+    full.encodes += small.encodes;
+    let best = refine(full, opts, predicted, 3)?;
+    if best.is_none() && !opts.quiet {
+        println!("{}", tr!(PresearchMissed));
+    }
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    // Size proportional to the pixel count, like a flat-ish image
+    struct Pixels {
+        full: u64,
+        calls: usize,
+    }
+
+    impl SizeOracle for Pixels {
+        type Candidate = ();
+
+        fn measure(&mut self, scale: f64) -> Result<(u64, ()), Box<dyn Error>> {
+            self.calls += 1;
+            Ok(((self.full as f64 * scale * scale) as u64, ()))
+        }
+    }
+
+    // Pixels, but anything from slow up takes too long to encode
+    struct Slow {
+        pixels: Pixels,
+        slow: f64,
+    }
+
+    impl SizeOracle for Slow {
+        type Candidate = ();
+
+        fn measure(&mut self, scale: f64) -> Result<(u64, ()), Box<dyn Error>> {
+            match scale >= self.slow {
+                true => Ok((TIMED_OUT, ())),
+                false => self.pixels.measure(scale),
+            }
+        }
+    }
+
+    fn options(target: u64) -> Options {
+        Options {
+            target,
+            byte_halt: target / 100,
+            iters: 64,
+            ..Options::default()
+        }
+    }
+
+    // Runs the search with a handful of fixed seeds
+    fn scales(full: u64, opts: &Options) -> Vec<Option<f64>> {
+        (0..32)
+            .map(|seed| {
+                let mut oracle = Pixels { full, calls: 0 };
+                let mut rng = StdRng::seed_from_u64(seed);
+                let best = bracket(&mut oracle, opts, 0.5, &mut rng).unwrap();
+                assert!(oracle.calls as i32 <= opts.iters + 3);
+                best.map(|(scale, ())| scale)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_a_scale_that_fits_close_to_the_target() {
+        let scales = scales(1_000_000, &options(250_000))
+            .into_iter()
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        for &scale in &scales {
+            assert!((1_000_000.0 * scale * scale) as u64 <= 250_000);
+            assert!(scale > 0.2, "{} is far below the ideal 0.5", scale);
+        }
+        let mean = scales.iter().sum::<f64>() / scales.len() as f64;
+        assert!(
+            mean > 0.4,
+            "{} is well below the ideal 0.5 on average",
+            mean
+        );
+    }
+
+    #[test]
+    fn grows_past_full_size_when_the_target_allows() {
+        for scale in scales(100_000, &options(400_000)) {
+            let scale = scale.unwrap();
+            assert!(scale >= 1.0);
+            assert!((100_000.0 * scale * scale) as u64 <= 400_000);
+        }
+    }
+
+    #[test]
+    fn stays_within_the_scale_bounds() {
+        let opts = Options {
+            max_scale: 0.3,
+            ..options(500_000)
+        };
+        for scale in scales(1_000_000, &opts) {
+            assert!(scale.unwrap() <= 0.3);
+        }
+    }
+
+    #[test]
+    fn reports_nothing_when_the_floor_is_too_big() {
+        let opts = Options {
+            min_scale: 0.8,
+            ..options(100_000)
+        };
+        assert!(scales(1_000_000, &opts).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn refine_corrects_a_rough_prediction() {
+        let opts = options(250_000);
+        for predicted in [0.3, 0.45, 0.6, 0.9] {
+            let mut oracle = Pixels {
+                full: 1_000_000,
+                calls: 0,
+            };
+            let (scale, ()) = refine(&mut oracle, &opts, predicted, 3).unwrap().unwrap();
+            assert!(oracle.calls <= 3);
+            let size = (1_000_000.0 * scale * scale) as u64;
+            assert!(size <= 250_000);
+            assert!(size >= 240_000, "{} from {}", size, predicted);
+        }
+    }
+
+    #[test]
+    fn refine_backs_off_from_candidates_that_time_out() {
+        let mut oracle = Slow {
+            pixels: Pixels {
+                full: 1_000_000,
+                calls: 0,
+            },
+            slow: 0.7,
+        };
+        let (scale, ()) = refine(&mut oracle, &options(250_000), 0.9, 3)
+            .unwrap()
+            .unwrap();
+        assert!(scale < 0.7);
+        assert!((1_000_000.0 * scale * scale) as u64 <= 250_000);
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    error::Error,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// One stage of a pipeline: a thread working through items in order and
+// handing each result over as the next stage asks for it. The channel has
+// no room, so the thread gets at most one item ahead of what's been taken,
+// which bounds the memory to one result in waiting. Dropping the stage stops
+// the thread after the item it's on and waits for it, so it never outlives
+// whoever started it
+pub struct Stage<T> {
+    results: Option<Receiver<T>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Stage<T> {
+    pub fn spawn<I, F>(items: I, mut work: F) -> Stage<T>
+    where
+        I: IntoIterator + Send + 'static,
+        F: FnMut(I::Item) -> T + Send + 'static,
+    {
+        let (sender, results) = mpsc::sync_channel(0);
+        let worker = thread::spawn(move || {
+            for item in items {
+                // Nobody left to take it
+                if sender.send(work(item)).is_err() {
+                    break;
+                }
+            }
+        });
+        Stage {
+            results: Some(results),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl<T> Iterator for Stage<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.results.as_ref()?.recv().ok()
+    }
+}
+
+impl<T> Drop for Stage<T> {
+    fn drop(&mut self) {
+        // Closing the channel first is what lets a thread waiting to send
+        // give up
+        self.results.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Runs work on a thread of its own and waits at most timeout for it, None
+// when it took longer. The encoders can't be interrupted, so work given up
+// on finishes in the background and what it gives is dropped. Errors don't
+// cross threads, their messages do
+pub fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    work: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let (sender, result) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(work());
+    });
+    match result.recv_timeout(timeout) {
+        Ok(done) => Ok(Some(done?)),
+        Err(RecvTimeoutError::Timeout) => Ok(None),
+        Err(RecvTimeoutError::Disconnected) => Err("The encoder stopped unexpectedly".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[test]
+    fn stages_hand_over_results_in_order() {
+        let doubled = Stage::spawn(0..5, |i| i * 2).collect::<Vec<_>>();
+        assert_eq!(doubled, vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn stages_stay_one_item_ahead_and_stop_when_dropped() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let mut stage = Stage::spawn(0..100, {
+            let started = started.clone();
+            move |i| {
+                started.fetch_add(1, Ordering::SeqCst);
+                i
+            }
+        });
+        assert_eq!(stage.next(), Some(0));
+        thread::sleep(Duration::from_millis(50));
+        // The one taken, and the next waiting to be
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        drop(stage);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn timeouts_give_up_on_slow_work() {
+        let quick = with_timeout(Duration::from_secs(5), || Ok(7)).unwrap();
+        assert_eq!(quick, Some(7));
+        let slow = with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_millis(500));
+            Ok(7)
+        })
+        .unwrap();
+        assert_eq!(slow, None);
+        let failed = with_timeout::<()>(Duration::from_secs(5), || Err("broken".into()));
+        assert_eq!(failed.unwrap_err().to_string(), "broken");
+        let panicked = with_timeout::<()>(Duration::from_secs(5), || panic!("boom"));
+        assert!(panicked.is_err());
+    }
+}
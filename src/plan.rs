@@ -0,0 +1,567 @@
+use std::error::Error;
+
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::{
+    animation::{self, Animation},
+    compare,
+    depth::Depth,
+    encode, encode_candidate, jpeg,
+    messages::tr,
+    options::Options,
+    progress, search, units, Found,
+};
+
+// Ways of getting an image under the target, in the order the planner tries
+// them. The order is the estimate of how much quality each costs for the
+// bytes it saves: dropping metadata costs nothing, a lower JPEG quality or a
+// palette is rarely noticed, fewer frames is, and fewer pixels always is
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Strategy {
+    Strip,
+    Recompress,
+    Quantize,
+    DropFrames,
+    Downscale,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::Strip,
+        Strategy::Recompress,
+        Strategy::Quantize,
+        Strategy::DropFrames,
+        Strategy::Downscale,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Strip => "strip",
+            Strategy::Recompress => "recompress",
+            Strategy::Quantize => "quantize",
+            Strategy::DropFrames => "drop-frames",
+            Strategy::Downscale => "downscale",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Strategy> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "strip" | "metadata" => Some(Strategy::Strip),
+            "recompress" | "quality" => Some(Strategy::Recompress),
+            "quantize" | "palette" => Some(Strategy::Quantize),
+            "drop-frames" | "frames" => Some(Strategy::DropFrames),
+            "downscale" | "scale" => Some(Strategy::Downscale),
+            _ => None,
+        }
+    }
+
+    // A comma-separated list like `strip,downscale`
+    pub fn parse_list(list: &str) -> Option<Vec<Strategy>> {
+        list.split(',').map(Strategy::parse).collect()
+    }
+}
+
+// The lowest JPEG quality the planner goes down to before it starts
+// scaling. Below this the blocking shows more than a smaller size would
+const QUALITY_FLOOR: u8 = 60;
+
+// Palettes tried for the quantize step, the first one staying on for the
+// downscale when none of them fit at full size
+const PNG_COLORS: [u16; 2] = [256, 128];
+const GIF_COLORS: [u16; 1] = [128];
+
+// A strategy the planner applied and the size it brought the image to
+pub struct Step {
+    pub strategy: Strategy,
+    pub detail: String,
+    pub size: u64,
+}
+
+// What the planner settled on
+pub struct Plan {
+    pub scale: f64,
+    pub bytes: Vec<u8>,
+    // Candidates encoded along the way, the scale search's included
+    pub encodes: u32,
+    // The input file's size, when there is a file
+    pub original: Option<u64>,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    // What each step saved over the one before it, starting from the
+    // original, e.g. `recompress (JPEG quality 75 -> 60): -1.1 MB`. Named
+    // the way --strategies takes them, so any of them can be left out next
+    // time. Without an original the first step has nothing to compare to
+    pub fn attribution(&self) -> Vec<String> {
+        let mut before = self.original;
+        let mut lines = Vec::new();
+        for step in &self.steps {
+            if let Some(before) = before {
+                lines.push(format!(
+                    "{} ({}): -{}",
+                    step.strategy.name(),
+                    step.detail,
+                    units::format_size(before.saturating_sub(step.size))
+                ));
+            }
+            before = Some(step.size);
+        }
+        lines
+    }
+}
+
+// Applies strategies one after another on a copy of the options, each
+// staying applied for the ones after it
+struct Planner {
+    opts: Options,
+    encodes: u32,
+    original: Option<u64>,
+    steps: Vec<Step>,
+    // The smallest size so far, which a step has to beat to count
+    size: Option<u64>,
+    // The smallest full-size candidate, for when downscaling is left out
+    best: Option<Vec<u8>>,
+    // The input's colour histogram, when --max-color-shift is given
+    histogram: Option<Vec<f64>>,
+}
+
+impl Planner {
+    fn new(opts: &Options, original: Option<u64>, histogram: Option<Vec<f64>>) -> Planner {
+        Planner {
+            opts: opts.clone(),
+            encodes: 0,
+            original,
+            steps: Vec::new(),
+            size: original,
+            best: None,
+            histogram,
+        }
+    }
+
+    fn allows(&self, strategy: Strategy) -> bool {
+        self.opts.strategies.contains(&strategy)
+    }
+
+    // The full-size strategies only apply when the scale bounds allow 1.0
+    fn full_size(&self) -> bool {
+        self.opts.min_scale <= 1.0 && 1.0 <= self.opts.max_scale
+    }
+
+    fn fits(&self, bytes: &[u8]) -> bool {
+        bytes.len() as u64 <= self.opts.target
+    }
+
+    // Keeps a step when it made the image smaller than anything before it
+    fn step(&mut self, strategy: Strategy, detail: String, bytes: &[u8]) -> bool {
+        let size = bytes.len() as u64;
+        if self.size.is_some_and(|smallest| size >= smallest) {
+            return false;
+        }
+        if !self.opts.quiet {
+            println!("{}", tr!(StepApplied, detail, units::format_size(size)));
+        }
+        progress::emit(
+            &self.opts,
+            "step",
+            &[
+                ("strategy", strategy.name().into()),
+                ("detail", detail.as_str().into()),
+                ("bytes", size.into()),
+            ],
+        );
+        self.size = Some(size);
+        self.best = Some(bytes.to_vec());
+        self.steps.push(Step {
+            strategy,
+            detail,
+            size,
+        });
+        true
+    }
+
+    fn done(self, scale: f64, bytes: Vec<u8>, encodes: u32) -> Plan {
+        Plan {
+            scale,
+            bytes,
+            encodes: self.encodes + encodes,
+            original: self.original,
+            steps: self.steps,
+        }
+    }
+
+    // May the scale search go above 1.0 to get closer to the target
+    fn grows(&self) -> bool {
+        self.opts.max_scale > 1.0 && self.allows(Strategy::Downscale)
+    }
+
+    // Encodes at full size as the settings stand. False when that fits
+    // already, or when the scale bounds leave full size out and the
+    // scale search is all there is
+    fn needs_reducing(
+        &mut self,
+        encode: impl Fn(&Options) -> Result<Vec<u8>, Box<dyn Error>>,
+    ) -> Result<bool, Box<dyn Error>> {
+        if !self.full_size() {
+            return Ok(false);
+        }
+        let bytes = encode(&self.opts)?;
+        self.encodes += 1;
+        let fits = self.fits(&bytes);
+        if fits && self.grows() {
+            return Ok(false);
+        }
+        if self.allows(Strategy::Recompress) || fits {
+            self.step(Strategy::Recompress, tr!(StepReencoded), &bytes);
+        }
+        // Counted even when no bigger than the original, it's the size
+        // the reductions after it start from
+        if self.best.is_none() {
+            self.best = Some(bytes);
+        }
+        Ok(!fits)
+    }
+
+    // Tries each palette size at full size, returning the first that fits.
+    // When none does the largest stays on for what follows, as long as it
+    // made the image smaller. The sizes don't depend on each other, so
+    // they're all encoded and measured at once and then judged in order
+    fn quantize(
+        &mut self,
+        levels: &[u16],
+        encode: impl Fn(&Options) -> Result<Vec<u8>, Box<dyn Error>> + Sync,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if !self.allows(Strategy::Quantize)
+            || self.opts.encoder.depth != Depth::Full
+            || self.opts.encoder.palette.is_some()
+        {
+            return Ok(None);
+        }
+        let probes = levels
+            .par_iter()
+            .map(|&colors| {
+                let mut probe = self.opts.clone();
+                probe.encoder.palette = Some(colors);
+                // Errors don't cross threads, their messages do
+                let bytes = encode(&probe).map_err(|e| e.to_string())?;
+                let shift = self.shift_over_limit(&bytes);
+                Ok((colors, bytes, shift))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        self.encodes += probes.len() as u32;
+        let mut mildest = None;
+        for (colors, bytes, shift) in probes {
+            if let Some(shift) = shift {
+                if !self.opts.quiet {
+                    println!("{}", tr!(PaletteShiftsColors, colors, shift * 100.0));
+                }
+                continue;
+            }
+            if self.fits(&bytes) {
+                self.step(Strategy::Quantize, tr!(StepColors, colors), &bytes);
+                return Ok(Some(bytes));
+            }
+            mildest.get_or_insert((colors, bytes));
+        }
+        if let Some((colors, bytes)) = mildest {
+            if self.step(Strategy::Quantize, tr!(StepColors, colors), &bytes) {
+                self.opts.encoder.palette = Some(colors);
+            }
+        }
+        Ok(None)
+    }
+
+    // How far a candidate moves the input's colours, when that's over
+    // --max-color-shift
+    fn shift_over_limit(&self, bytes: &[u8]) -> Option<f64> {
+        let (histogram, limit) = (self.histogram.as_ref()?, self.opts.max_color_shift?);
+        compare::encoded_shift(histogram, bytes).filter(|&shift| shift > limit)
+    }
+
+    // Without the downscale there's nothing left to try. The smallest
+    // full-size candidate is the result only if it fits, a run never
+    // writes over the target
+    fn give_up(self) -> Result<Plan, Box<dyn Error>> {
+        match self.best.clone().filter(|bytes| self.fits(bytes)) {
+            Some(bytes) => Ok(self.done(1.0, bytes, 0)),
+            None => Err(tr!(NeedsDownscale).into()),
+        }
+    }
+}
+
+// A still at full size, with the lossless pass the search gives its result
+fn encode_full(img: &DynamicImage, ext: &str, opts: &Options) -> Result<Vec<u8>, Box<dyn Error>> {
+    let bytes = encode_candidate(img, ext, opts)?;
+    if ext.eq_ignore_ascii_case("png") {
+        return encode::optimize_png(bytes, &opts.encoder);
+    }
+    Ok(bytes)
+}
+
+fn at_full_size(bytes: Vec<u8>) -> Found {
+    Found {
+        scale: 1.0,
+        bytes,
+        encodes: 0,
+    }
+}
+
+// Better to fail loudly than to quietly hand back a mangled thumbnail
+fn check_ssim(img: &DynamicImage, ext: &str, plan: &Plan, opts: &Options) -> Result<(), String> {
+    let Some(min_ssim) = opts.min_ssim else {
+        return Ok(());
+    };
+    match compare::encoded_ssim(img, &plan.bytes) {
+        Some(ssim) if ssim < min_ssim => Err(tr!(SsimTooLow, plan.scale, ssim, min_ssim)),
+        _ if opts.quiet => Ok(()),
+        Some(ssim) => {
+            println!("{}", tr!(SsimAgainstInput, ssim));
+            Ok(())
+        }
+        None => {
+            println!("{}", tr!(SsimCantDecode, ext));
+            Ok(())
+        }
+    }
+}
+
+// Same for colours, for brand colours that mustn't drift. The palettes the
+// planner tries are held to it as well, this catches the ones it can't
+// leave out, like a GIF's
+fn check_colors(
+    histogram: Option<&[f64]>,
+    plan: &Plan,
+    ext: &str,
+    opts: &Options,
+) -> Result<(), String> {
+    let (Some(histogram), Some(limit)) = (histogram, opts.max_color_shift) else {
+        return Ok(());
+    };
+    match compare::encoded_shift(histogram, &plan.bytes) {
+        Some(shift) if shift > limit => Err(tr!(
+            ColorShiftTooHigh,
+            plan.scale,
+            shift * 100.0,
+            limit * 100.0
+        )),
+        _ if opts.quiet => Ok(()),
+        Some(shift) => {
+            println!("{}", tr!(ColorShiftAgainstInput, shift * 100.0));
+            Ok(())
+        }
+        None => {
+            println!("{}", tr!(ColorShiftCantDecode, ext));
+            Ok(())
+        }
+    }
+}
+
+// Fits a still image, trying the strategies in order until one gets it
+// under the target. original is the input file, if there is one: the
+// attribution starts from its size, and a JPEG's compressed data can be
+// kept as it is once its metadata is dropped
+pub fn fit_still(
+    img: &DynamicImage,
+    ext: &str,
+    original: Option<&[u8]>,
+    opts: &Options,
+) -> Result<Plan, Box<dyn Error>> {
+    // The input's colours, when --max-color-shift holds the result to them
+    let histogram = opts
+        .max_color_shift
+        .map(|_| compare::histogram(&img.to_rgba8()));
+    let mut planner = Planner::new(opts, original.map(|o| o.len() as u64), histogram.clone());
+    let plan = plan_still(&mut planner, img, ext, original)?;
+    let plan = match plan {
+        Some(found) => planner.done(found.scale, found.bytes, found.encodes),
+        None => planner.give_up()?,
+    };
+    check_ssim(img, ext, &plan, opts)?;
+    check_colors(histogram.as_deref(), &plan, ext, opts)?;
+    Ok(plan)
+}
+
+fn plan_still(
+    planner: &mut Planner,
+    img: &DynamicImage,
+    ext: &str,
+    original: Option<&[u8]>,
+) -> Result<Option<Found>, Box<dyn Error>> {
+    let opts = planner.opts.clone();
+    // Settings that change the pixels need a re-encode, so the original
+    // compressed data is no use to them
+    let keeps_pixels = !opts.encoder.progressive && !opts.srgb && opts.encoder.depth == Depth::Full;
+    if let (true, Some(original)) = (
+        planner.full_size()
+            && planner.allows(Strategy::Strip)
+            && encode::is_jpeg(ext)
+            && keeps_pixels,
+        original,
+    ) {
+        if let Some(stripped) = jpeg::strip_markers(original) {
+            // Kept whenever it fits, even with no metadata to drop, since
+            // the original data beats another generation of loss
+            planner.step(Strategy::Strip, tr!(StepStripped), &stripped);
+            if planner.fits(&stripped) {
+                return Ok(Some(at_full_size(stripped)));
+            }
+        }
+    }
+    if planner.needs_reducing(|opts| encode_full(img, ext, opts))? {
+        if let Some(found) = recompress(planner, img, ext)? {
+            return Ok(Some(found));
+        }
+        let levels: &[u16] = match ext.to_ascii_lowercase().as_str() {
+            "png" if !opts.encoder.interlaced => &PNG_COLORS,
+            "gif" => &GIF_COLORS,
+            _ => &[],
+        };
+        let levels = opts.profile.and_then(|p| p.palettes(ext)).unwrap_or(levels);
+        if let Some(bytes) = planner.quantize(levels, |opts| encode_full(img, ext, opts))? {
+            return Ok(Some(at_full_size(bytes)));
+        }
+    } else if !planner.grows() {
+        return Ok(planner.best.take().map(at_full_size));
+    }
+    if !planner.allows(Strategy::Downscale) {
+        return Ok(None);
+    }
+    let found = search(img, ext, &planner.opts)?;
+    planner.step(
+        Strategy::Downscale,
+        tr!(StepScale, found.scale),
+        &found.bytes,
+    );
+    Ok(Some(found))
+}
+
+// Lowers the JPEG quality down to the floor, returning the highest quality
+// that fits at full size. When none does the floor stays for what follows
+fn recompress(
+    planner: &mut Planner,
+    img: &DynamicImage,
+    ext: &str,
+) -> Result<Option<Found>, Box<dyn Error>> {
+    let quality = planner.opts.encoder.jpeg_quality;
+    if !planner.allows(Strategy::Recompress) || !encode::is_jpeg(ext) || quality <= QUALITY_FLOOR {
+        return Ok(None);
+    }
+    let (mut lo, mut hi) = (QUALITY_FLOOR, quality - 1);
+    let (mut best, mut floor) = (None, None);
+    while lo <= hi {
+        let mid = lo + (hi - lo) / 2;
+        let mut probe = planner.opts.clone();
+        probe.encoder.jpeg_quality = mid;
+        let bytes = encode_full(img, ext, &probe)?;
+        planner.encodes += 1;
+        if mid == QUALITY_FLOOR {
+            floor = Some(bytes.clone());
+        }
+        if planner.fits(&bytes) {
+            best = Some((mid, bytes));
+            lo = mid + 1;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    if let Some((to, bytes)) = best {
+        planner.step(Strategy::Recompress, tr!(StepQuality, quality, to), &bytes);
+        return Ok(Some(at_full_size(bytes)));
+    }
+    planner.opts.encoder.jpeg_quality = QUALITY_FLOOR;
+    if let Some(bytes) = floor {
+        planner.step(
+            Strategy::Recompress,
+            tr!(StepQuality, quality, QUALITY_FLOOR),
+            &bytes,
+        );
+    }
+    Ok(None)
+}
+
+// Fits an animation the same way, with dropping frames before scaling.
+// original is the input file's size
+pub fn fit_animation(
+    anim: &Animation,
+    original: Option<u64>,
+    opts: &Options,
+) -> Result<Plan, Box<dyn Error>> {
+    // Of the first frame, which is what decoding a GIF gives back to
+    // compare with
+    let histogram = opts
+        .max_color_shift
+        .map(|_| compare::histogram(&anim.frames[0]));
+    let planner = Planner::new(opts, original, histogram.clone());
+    let plan = plan_animation(planner, anim)?;
+    check_colors(histogram.as_deref(), &plan, "gif", opts)?;
+    Ok(plan)
+}
+
+fn plan_animation(mut planner: Planner, anim: &Animation) -> Result<Plan, Box<dyn Error>> {
+    let mut reduced = None;
+    let levels = planner
+        .opts
+        .profile
+        .and_then(|p| p.palettes("gif"))
+        .unwrap_or(&GIF_COLORS);
+    if planner.needs_reducing(|opts| animation::encode_final(anim, 1.0, opts))? {
+        if let Some(bytes) =
+            planner.quantize(levels, |opts| animation::encode_final(anim, 1.0, opts))?
+        {
+            return Ok(planner.done(1.0, bytes, 0));
+        }
+        // Keeping at least two frames, or it's not much of an animation
+        if planner.allows(Strategy::DropFrames) && anim.len() >= 4 {
+            let halved = anim.drop_every_other();
+            let bytes = animation::encode_final(&halved, 1.0, &planner.opts)?;
+            planner.encodes += 1;
+            let detail = tr!(StepFrames, halved.len(), anim.len());
+            if planner.step(Strategy::DropFrames, detail, &bytes) {
+                if planner.fits(&bytes) {
+                    return Ok(planner.done(1.0, bytes, 0));
+                }
+                reduced = Some(halved);
+            }
+        }
+    } else if !planner.grows() {
+        if let Some(bytes) = planner.best.take() {
+            return Ok(planner.done(1.0, bytes, 0));
+        }
+    }
+    if !planner.allows(Strategy::Downscale) {
+        return planner.give_up();
+    }
+    let anim = reduced.as_ref().unwrap_or(anim);
+    let Found {
+        scale,
+        bytes,
+        encodes,
+    } = animation::search_gif(anim, &planner.opts)?;
+    planner.step(Strategy::Downscale, tr!(StepScale, scale), &bytes);
+    Ok(planner.done(scale, bytes, encodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_the_downscale_only_a_fit_is_kept() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * y) as u8, (x ^ y) as u8 * 4, y as u8 * 4])
+        }));
+        let opts = |target| Options {
+            target,
+            quiet: true,
+            strategies: vec![Strategy::Recompress, Strategy::Quantize],
+            ..Options::default()
+        };
+        // Nothing at full size gets this small, and there's no smallest try
+        // handed back over the target
+        assert!(fit_still(&img, "png", None, &opts(200)).is_err());
+        let plan = fit_still(&img, "png", None, &opts(1_000_000)).unwrap();
+        assert_eq!(plan.scale, 1.0);
+        assert!(plan.bytes.len() <= 1_000_000);
+    }
+}
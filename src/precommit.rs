@@ -0,0 +1,105 @@
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{batch::fit_file, budget, formats, options::Options, units};
+
+// `autosize pre-commit [--manifest <budgets>] [--target <size>] [options]`:
+// for a git pre-commit hook. Reads the staged paths from stdin, one per line
+// or NUL-separated as `git diff --cached --name-only -z` gives them, fits
+// the images over their budget in place, and prints the paths it modified
+// (and only those) on stdout so the hook can `git add` them again. Notes go
+// to stderr. A file's budget is its manifest rule, or --target for files no
+// rule covers (or without a manifest). Fails if any image still doesn't fit,
+// which stops the commit
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut manifest = None;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.split_once('=') {
+            Some(("--manifest", value)) => manifest = Some(PathBuf::from(value)),
+            _ if arg == "--manifest" => {
+                let value = iter.next().ok_or("--manifest expects a value")?;
+                manifest = Some(PathBuf::from(value));
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let mut opts = Options::parse(&rest)?;
+    if manifest.is_none() && opts.given_target.is_none() {
+        return Err(
+            "Usage: git diff --cached --name-only -z | autosize pre-commit [--manifest <budgets>] [--target <size>]"
+                .into(),
+        );
+    }
+    let rules = match &manifest {
+        Some(manifest) => budget::load(manifest)?,
+        None => Vec::new(),
+    };
+    // Paths given as arguments instead of on stdin, to try the hook out
+    let paths = match opts.inputs.is_empty() {
+        true => io::read_to_string(io::stdin())?
+            .split(['\0', '\n'])
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect(),
+        false => opts.inputs.clone(),
+    };
+    opts.quiet = true;
+    let (mut modified, mut left) = (0, 0);
+    for path in &paths {
+        // Deleted files are staged too
+        if !path.is_file() || !formats::readable(path) {
+            continue;
+        }
+        let rule = budget::rule_for(&rules, path, Path::new(""));
+        let Some(target) = rule.map(|r| rules[r].target).or(opts.given_target) else {
+            continue;
+        };
+        let size = fs::metadata(path)?.len();
+        if size <= target {
+            continue;
+        }
+        eprintln!(
+            "{}: {}, over its {} budget, fitting it",
+            path.display(),
+            units::format_size(size),
+            units::format_size(target)
+        );
+        let opts = Options {
+            target,
+            given_target: Some(target),
+            in_place: true,
+            ..opts.clone()
+        };
+        match fit_file(path, &opts, args) {
+            Ok((_, size)) => {
+                println!("{}", path.display());
+                modified += 1;
+                if size > target {
+                    eprintln!(
+                        "{}: still over, {}",
+                        path.display(),
+                        units::format_size(size)
+                    );
+                    left += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: couldn't fit it: {}", path.display(), e);
+                left += 1;
+            }
+        }
+    }
+    if modified > 0 {
+        eprintln!("Fitted {} image(s), stage them again", modified);
+    }
+    match left {
+        0 => Ok(()),
+        n => Err(format!("{} image(s) still over their budget", n).into()),
+    }
+}
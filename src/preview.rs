@@ -0,0 +1,54 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use image::DynamicImage;
+
+use crate::{backup, options::Options, output_name, search};
+
+// Longest side a preview is made from. It's shown stretched and blurred, so
+// detail past this only costs bytes
+const SIDE: u32 = 256;
+
+// Blur at SIDE, enough that the blocky upscale a browser does looks like a
+// soft version of the image rather than a pixelated one
+const SIGMA: f32 = 3.0;
+
+// With --preview, a low-quality placeholder (LQIP) to show while the full
+// output loads: the image shrunk, blurred and fitted into the preview size,
+// written next to the output as `<name>_preview_<size>`. JPEG, or PNG when
+// the image has see-through parts, since every browser shows both
+pub fn write(
+    image: &DynamicImage,
+    name: &Path,
+    target: u64,
+    opts: &Options,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let small = match image.width().max(image.height()) > SIDE {
+        true => image.thumbnail(SIDE, SIDE),
+        false => image.clone(),
+    };
+    let transparent = small.color().has_alpha() && small.to_rgba8().pixels().any(|p| p.0[3] < 255);
+    let (small, ext) = match transparent {
+        true => (
+            DynamicImage::ImageRgba8(small.blur(SIGMA).to_rgba8()),
+            "png",
+        ),
+        false => (DynamicImage::ImageRgb8(small.blur(SIGMA).to_rgb8()), "jpg"),
+    };
+    let opts = Options {
+        target,
+        min_scale: 0.0,
+        max_scale: 1.0,
+        quiet: true,
+        progress_json: false,
+        ..opts.clone()
+    };
+    let found = search(&small, ext, &opts)?;
+    let mut stem = name.as_os_str().to_os_string();
+    stem.push("_preview");
+    let output = output_name(Path::new(&stem), &found.bytes, ext, &opts);
+    backup::write_output(&output, &found.bytes, &opts)?;
+    Ok(output)
+}
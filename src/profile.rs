@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+
+use crate::{
+    encode::{self, Subsampling},
+    messages::tr,
+    options::Options,
+    resample,
+};
+
+// Lowest SSIM against the input the photo profile accepts, when no
+// --min-ssim is given. Below it a photo reads as a blurry thumbnail
+const PHOTO_MIN_SSIM: f64 = 0.7;
+
+// What detect goes by. Colours are only counted up to COLOR_LIMIT
+const COLOR_LIMIT: usize = 4096;
+// Photos: almost no pixel exactly repeats its neighbour, and colours run
+// into the thousands
+const PHOTO_FLAT: f64 = 0.2;
+// Screenshots: most pixels repeat their neighbour, in flat backgrounds
+// and panels
+const SCREENSHOT_FLAT: f64 = 0.5;
+// Pixel art: a small palette, and either blown up into uniform blocks of
+// up to MAX_BLOCK pixels, so that nearly every change of colour falls on a
+// block's edge, or small enough to be at its native size
+const PIXEL_ART_COLORS: usize = 32;
+const MAX_BLOCK: u32 = 8;
+const BLOCK_MATCH: f64 = 0.99;
+const MIN_EDGES: usize = 64;
+const NATIVE_SIDE: u32 = 64;
+
+// Kinds of image the settings can be tuned for with --profile. Each only
+// changes what the flags left alone, so `--profile screenshot --subsampling
+// 420` still gets 4:2:0
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Profile {
+    // UI captures: flat colour and sharp edges, which lossless formats and
+    // small palettes keep for far fewer bytes than JPEG does
+    Screenshot,
+    // Camera pictures: smooth gradients and noise, which JPEG handles best
+    // and palettes posterize
+    Photo,
+    // Sprites and other art drawn pixel by pixel, which any smoothing
+    // filter or palette change ruins
+    PixelArt,
+}
+
+impl Profile {
+    pub fn parse(name: &str) -> Option<Profile> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "screenshot" | "ui" => Some(Profile::Screenshot),
+            "photo" | "photograph" => Some(Profile::Photo),
+            "pixel-art" | "pixelart" | "pixel" => Some(Profile::PixelArt),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Screenshot => "screenshot",
+            Profile::Photo => "photo",
+            Profile::PixelArt => "pixel-art",
+        }
+    }
+
+    // Palette sizes the planner tries on ext, None for its usual ones (see
+    // plan::PNG_COLORS)
+    pub fn palettes(self, ext: &str) -> Option<&'static [u16]> {
+        match self {
+            Profile::Screenshot if ext.eq_ignore_ascii_case("png") => Some(&[256, 128, 64, 32, 16]),
+            Profile::Screenshot => None,
+            Profile::Photo | Profile::PixelArt => Some(&[]),
+        }
+    }
+
+    // The format to write when none was asked for, None to keep the input's.
+    // Animations stay GIFs
+    fn format(self, in_ext: &str, image: &DynamicImage, anim: bool) -> Option<&'static str> {
+        let is = |ext: &str| in_ext.eq_ignore_ascii_case(ext);
+        match self {
+            _ if anim => None,
+            // Lossless already, and usually smaller than PNG
+            Profile::Screenshot if is("webp") => None,
+            Profile::Screenshot => Some("png"),
+            // Made for photos already. Transparency would be lost in a JPEG,
+            // so a cut-out keeps its format
+            Profile::Photo if encode::is_jpeg(in_ext) || is("avif") || is("jxl") => None,
+            Profile::Photo if resample::see_through(image) => None,
+            Profile::Photo => Some("jpg"),
+            Profile::PixelArt if is("gif") => None,
+            Profile::PixelArt => Some("png"),
+        }
+    }
+
+    // Tunes the options for an input with extension in_ext going to ext,
+    // returning the extension to write instead
+    pub fn apply(
+        self,
+        opts: &mut Options,
+        in_ext: &str,
+        ext: &str,
+        image: &DynamicImage,
+        anim: bool,
+    ) -> String {
+        let mut ext = ext.to_string();
+        // One picked by detect rather than asked for only tunes how the
+        // image is resized and encoded. A wrong guess mustn't change the
+        // format or make a fit fail that would have worked without it
+        let detected = opts.profile.is_none();
+        // An in-place run can't change the format
+        if !opts.given("--format") && !opts.in_place && !detected {
+            if let Some(format) = self.format(in_ext, image, anim) {
+                ext = format.to_string();
+            }
+        }
+        match self {
+            Profile::Screenshot => {
+                if !opts.given("--subsampling") {
+                    opts.encoder.subsampling = Subsampling::S444;
+                }
+                // Lanczos rings around sharp edges like text
+                opts.filter = FilterType::CatmullRom;
+            }
+            Profile::Photo => {
+                if !opts.given("--subsampling") {
+                    opts.encoder.subsampling = Subsampling::S420;
+                }
+                if !opts.given("--min-ssim") && !detected {
+                    opts.min_ssim = Some(PHOTO_MIN_SSIM);
+                }
+            }
+            Profile::PixelArt => {
+                // Every pixel becomes a whole block of them, or none
+                opts.filter = FilterType::Nearest;
+                opts.whole_factors = !detected;
+                // Art with at most 256 colours keeps exactly those. GIF
+                // frames with that few are written as they are, a global
+                // palette would be learned and shift them
+                if !opts.given("--gif-palette") {
+                    opts.encoder.gif_global_palette = false;
+                }
+            }
+        }
+        ext
+    }
+}
+
+// Share of pixels the same as the one to their left
+fn flat_share(img: &RgbaImage) -> f64 {
+    let (w, h) = img.dimensions();
+    if w < 2 {
+        return 0.0;
+    }
+    let flat = (0..h)
+        .map(|y| {
+            (1..w)
+                .filter(|&x| img.get_pixel(x, y) == img.get_pixel(x - 1, y))
+                .count()
+        })
+        .sum::<usize>();
+    flat as f64 / ((w - 1) * h) as f64
+}
+
+// Distinct colours, up to COLOR_LIMIT + 1
+fn colors(img: &RgbaImage) -> usize {
+    let mut seen = HashSet::new();
+    for p in img.pixels() {
+        seen.insert(p.0);
+        if seen.len() > COLOR_LIMIT {
+            break;
+        }
+    }
+    seen.len()
+}
+
+// Whether the image is made of uniform size x size blocks, as pixel art
+// blown up with nearest-neighbour is. Only where the colour changes tells,
+// a flat image with a few shapes would pass a pixel by pixel comparison
+fn blocky(img: &RgbaImage, size: u32) -> bool {
+    let (mut edges, mut aligned) = (0, 0);
+    for (x, y, p) in img.enumerate_pixels() {
+        if x > 0 && p != img.get_pixel(x - 1, y) {
+            edges += 1;
+            aligned += usize::from(x % size == 0);
+        }
+        if y > 0 && p != img.get_pixel(x, y - 1) {
+            edges += 1;
+            aligned += usize::from(y % size == 0);
+        }
+    }
+    edges >= MIN_EDGES && aligned as f64 / edges as f64 >= BLOCK_MATCH
+}
+
+// A lightweight guess at which profile suits an image, from how flat it is
+// and how many colours it has. None when it's nothing clear-cut, which keeps
+// the general settings
+pub fn detect(img: &DynamicImage) -> Option<Profile> {
+    let img = img.to_rgba8();
+    let (colors, flat) = (colors(&img), flat_share(&img));
+    let (w, h) = img.dimensions();
+    if colors <= PIXEL_ART_COLORS
+        && (w.max(h) <= NATIVE_SIDE || (2..=MAX_BLOCK).any(|size| blocky(&img, size)))
+    {
+        return Some(Profile::PixelArt);
+    }
+    match flat {
+        f if f >= SCREENSHOT_FLAT => Some(Profile::Screenshot),
+        f if f < PHOTO_FLAT && colors > COLOR_LIMIT => Some(Profile::Photo),
+        _ => None,
+    }
+}
+
+// The profile for an image: the one given, or with classify the one it
+// looks like, which is reported
+pub fn choose(opts: &Options, img: &DynamicImage) -> Option<Profile> {
+    if opts.profile.is_some() || !opts.classify {
+        return opts.profile;
+    }
+    let profile = detect(img)?;
+    if !opts.quiet {
+        println!("{}", tr!(ProfileDetected, profile.name()));
+    }
+    Some(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn image(w: u32, h: u32, mut pixel: impl FnMut(u32, u32) -> [u8; 4]) -> RgbaImage {
+        RgbaImage::from_fn(w, h, |x, y| Rgba(pixel(x, y)))
+    }
+
+    // Two colours in size x size squares, like a chessboard
+    fn checkers(side: u32, size: u32) -> RgbaImage {
+        image(side, side, |x, y| match (x / size + y / size) % 2 {
+            0 => [0, 0, 0, 255],
+            _ => [255, 255, 255, 255],
+        })
+    }
+
+    fn noise(side: u32, colors: u32) -> RgbaImage {
+        let mut rng = StdRng::seed_from_u64(1);
+        image(side, side, |_, _| {
+            let c = rng.gen_range(0..colors);
+            [(c >> 16) as u8, (c >> 8) as u8, c as u8, 255]
+        })
+    }
+
+    #[test]
+    fn flat_share_counts_pixels_repeating_their_left_neighbour() {
+        assert_eq!(flat_share(&image(8, 4, |_, _| [9, 9, 9, 255])), 1.0);
+        assert_eq!(flat_share(&checkers(8, 1)), 0.0);
+        // A change every fourth pixel
+        assert_eq!(flat_share(&checkers(16, 4)), 12.0 / 15.0);
+        assert_eq!(flat_share(&image(1, 4, |_, _| [0, 0, 0, 255])), 0.0);
+    }
+
+    #[test]
+    fn blocky_needs_every_edge_on_the_block_grid() {
+        let blocks = checkers(32, 4);
+        assert!(blocky(&blocks, 4));
+        // Edges every 4 pixels fall on a 2 pixel grid as well
+        assert!(blocky(&blocks, 2));
+        assert!(!blocky(&blocks, 3));
+        assert!(!blocky(&checkers(32, 1), 4));
+        // Too few edges to tell
+        assert!(!blocky(&checkers(8, 4), 4));
+    }
+
+    #[test]
+    fn detects_each_kind_of_image() {
+        let detect = |img: RgbaImage| detect(&DynamicImage::ImageRgba8(img));
+        // Small and few colours, or blown up into blocks
+        assert_eq!(detect(checkers(16, 1)), Some(Profile::PixelArt));
+        assert_eq!(detect(checkers(256, 8)), Some(Profile::PixelArt));
+        // Flat runs of many colours
+        let bands = image(200, 100, |x, y| [(x / 4) as u8, (y / 4) as u8, 0, 255]);
+        assert_eq!(detect(bands), Some(Profile::Screenshot));
+        assert_eq!(detect(noise(100, 1 << 24)), Some(Profile::Photo));
+        // Noisy but with few colours: none of them
+        assert_eq!(detect(noise(100, 16)), None);
+    }
+}
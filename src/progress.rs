@@ -0,0 +1,103 @@
+use std::io::{self, Write};
+
+use crate::options::Options;
+
+// A value in a progress event
+pub enum Value {
+    Num(f64),
+    Int(u64),
+    Str(String),
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Num(n)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Value {
+        Value::Int(n)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Value {
+        Value::Int(n as u64)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::Str(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json(value: &Value) -> String {
+    match value {
+        // JSON has no NaN or infinity
+        Value::Num(n) if !n.is_finite() => "null".to_string(),
+        Value::Num(n) => format!("{}", (n * 10000.0).round() / 10000.0),
+        Value::Int(n) => n.to_string(),
+        Value::Str(s) => quote(s),
+    }
+}
+
+// With --progress-json, writes one line of JSON to stderr for each step of
+// the run, e.g.
+//   {"event":"iteration","i":3,"scale":0.4812,"bytes":8123456,...}
+// so wrappers and CI logs can follow a long search without picking apart
+// the progress text on stdout. Every line has an "event", the other fields
+// depend on it
+pub fn emit(opts: &Options, event: &str, fields: &[(&str, Value)]) {
+    if !opts.progress_json {
+        return;
+    }
+    let mut line = format!("{{\"event\":{}", quote(event));
+    for (key, value) in fields {
+        line.push_str(&format!(",{}:{}", quote(key), json(value)));
+    }
+    line.push_str("}\n");
+    // Lines are written whole so they never interleave with other threads'
+    let _ = io::stderr().lock().write_all(line.as_bytes());
+}
+
+// One candidate measured by a scale search, with the best scale that fit
+// so far (NaN before anything has) and the range still being searched
+pub fn iteration(opts: &Options, i: i32, scale: f64, bytes: u64, best: f64, range: (f64, f64)) {
+    emit(
+        opts,
+        "iteration",
+        &[
+            ("i", (i.max(0) as u64).into()),
+            ("scale", scale.into()),
+            ("bytes", bytes.into()),
+            ("best_scale", best.into()),
+            ("low", range.0.into()),
+            ("high", range.1.into()),
+        ],
+    );
+}
@@ -0,0 +1,131 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::tools::temp_path;
+
+// Objects in S3 or Google Cloud Storage go through the providers' own
+// command-line tools, `aws` and `gcloud`, which need to be on the PATH. They
+// already know about credentials, profiles, regions and retries, which the
+// SDKs would have to be taught one by one
+#[derive(Clone, Copy)]
+enum Store {
+    S3,
+    Gcs,
+}
+
+// An `s3://bucket/key` or `gs://bucket/key` URL. One ending in `/` is a
+// prefix that outputs are put under with their own name
+pub struct Object {
+    store: Store,
+    url: String,
+}
+
+impl Object {
+    pub fn parse(url: &str) -> Option<Object> {
+        let store = if url.starts_with("s3://") {
+            Store::S3
+        } else if url.starts_with("gs://") {
+            Store::Gcs
+        } else {
+            return None;
+        };
+        Some(Object {
+            store,
+            url: url.to_string(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // The last part of the key, which local copies are named after
+    pub fn name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or_default()
+    }
+
+    // Where a file called name goes: under the prefix, or the key itself
+    pub fn for_file(&self, name: &str) -> Object {
+        let url = if self.url.ends_with('/') {
+            format!("{}{}", self.url, name)
+        } else {
+            self.url.clone()
+        };
+        Object {
+            store: self.store,
+            url,
+        }
+    }
+
+    // A file called name in the same folder as this one
+    pub fn beside(&self, name: &str) -> Object {
+        let folder = self.url.rfind('/').map_or(0, |i| i + 1);
+        Object {
+            store: self.store,
+            url: format!("{}{}", &self.url[..folder], name),
+        }
+    }
+
+    fn copy(&self, from: &str, to: &str) -> Result<(), Box<dyn Error>> {
+        let (tool, args) = match self.store {
+            Store::S3 => ("aws", ["s3", "cp", "--only-show-errors", from, to]),
+            Store::Gcs => ("gcloud", ["storage", "cp", "--quiet", from, to]),
+        };
+        let output = Command::new(tool)
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| format!("Couldn't run {} (is it installed?): {}", tool, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Copying {} to {} failed: {}",
+                from,
+                to,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // Downloads the object into a folder of its own in the temp folder,
+    // keeping its name so outputs are named after it as usual
+    pub fn download(&self) -> Result<PathBuf, Box<dyn Error>> {
+        if self.name().is_empty() {
+            return Err(format!("{} is a prefix, not an object", self.url).into());
+        }
+        let dir = temp_path("remote", "d");
+        fs::create_dir_all(&dir)?;
+        let file = dir.join(self.name());
+        if let Err(e) = self.copy(&self.url, &file.to_string_lossy()) {
+            clean_up(&file);
+            return Err(e);
+        }
+        Ok(file)
+    }
+
+    pub fn upload(&self, file: &Path) -> Result<(), Box<dyn Error>> {
+        self.copy(&file.to_string_lossy(), &self.url)
+    }
+}
+
+// Removes a download or an output staged for upload, and the folder it was
+// made in
+pub fn clean_up(file: &Path) {
+    let _ = fs::remove_file(file);
+    if let Some(dir) = file.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+// A folder in the temp folder for outputs on their way to the store
+pub fn staging_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = temp_path("upload", "d");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
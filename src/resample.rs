@@ -0,0 +1,98 @@
+use image::{imageops, DynamicImage, ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+
+fn to_linear(v: f32) -> f32 {
+    match v <= 0.04045 {
+        true => v / 12.92,
+        false => ((v + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn to_srgb(v: f32) -> f32 {
+    match v <= 0.0031308 {
+        true => v * 12.92,
+        false => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+    }
+}
+
+// Resizes with the colours in linear light and multiplied by their alpha,
+// then divided out again. Straight sRGB values let whatever colour hides
+// under the transparent pixels (usually black) bleed into the edges as a
+// dark fringe, and averaging in sRGB darkens them further
+fn premultiplied(img: &Rgba32FImage, w: u32, h: u32, filter: imageops::FilterType) -> Rgba32FImage {
+    let mut linear = img.clone();
+    for p in linear.pixels_mut() {
+        let a = p.0[3];
+        for c in &mut p.0[..3] {
+            *c = to_linear(*c) * a;
+        }
+    }
+    let mut resized = imageops::resize(&linear, w, h, filter);
+    for p in resized.pixels_mut() {
+        let a = p.0[3].clamp(0.0, 1.0);
+        for c in &mut p.0[..3] {
+            *c = match a > 0.0 {
+                true => to_srgb((*c / a).clamp(0.0, 1.0)),
+                false => 0.0,
+            };
+        }
+        p.0[3] = a;
+    }
+    resized
+}
+
+pub fn see_through(img: &DynamicImage) -> bool {
+    match img {
+        DynamicImage::ImageLumaA8(i) => i.pixels().any(|p| p.0[1] < u8::MAX),
+        DynamicImage::ImageLumaA16(i) => i.pixels().any(|p| p.0[1] < u16::MAX),
+        DynamicImage::ImageRgba8(i) => i.pixels().any(|p| p.0[3] < u8::MAX),
+        DynamicImage::ImageRgba16(i) => i.pixels().any(|p| p.0[3] < u16::MAX),
+        DynamicImage::ImageRgba32F(i) => i.pixels().any(|p| p.0[3] < 1.0),
+        _ => false,
+    }
+}
+
+// The nearest whole factor at or below scale: 1/2, 1/3 ... shrinking and
+// 2, 3 ... growing, so every source pixel becomes the same number of
+// output pixels
+pub fn whole_factor(scale: f64) -> f64 {
+    match scale >= 1.0 {
+        true => scale.floor(),
+        // Less a hair, so 0.5 stays 1/2 rather than rounding to 1/3
+        false => 1.0 / (1.0 / scale - 1e-9).ceil(),
+    }
+}
+
+// Resizes to fit inside w x h keeping the aspect ratio, like
+// DynamicImage::resize. Anything with see-through pixels goes through
+// premultiplied, in the colour type it came in; opaque images are resized
+// as they always were, which is faster and has nothing to fringe
+pub fn image(img: &DynamicImage, w: u32, h: u32, filter: imageops::FilterType) -> DynamicImage {
+    // Nearest only copies pixels, so there's no mixing to fringe
+    if !see_through(img) || filter == imageops::FilterType::Nearest {
+        return img.resize(w, h, filter);
+    }
+    let ratio = (w as f64 / img.width() as f64).min(h as f64 / img.height() as f64);
+    let (w, h) = (
+        ((img.width() as f64 * ratio).round() as u32).max(1),
+        ((img.height() as f64 * ratio).round() as u32).max(1),
+    );
+    let resized = DynamicImage::ImageRgba32F(premultiplied(&img.to_rgba32f(), w, h, filter));
+    match img {
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLumaA8(resized.to_luma_alpha8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA16(resized.to_luma_alpha16()),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba16(resized.to_rgba16()),
+        DynamicImage::ImageRgba32F(_) => resized,
+        _ => DynamicImage::ImageRgba8(resized.to_rgba8()),
+    }
+}
+
+// The same for animation frames
+pub fn rgba(frame: &RgbaImage, w: u32, h: u32, filter: imageops::FilterType) -> RgbaImage {
+    if filter == imageops::FilterType::Nearest || frame.pixels().all(|p| p.0[3] == u8::MAX) {
+        return imageops::resize(frame, w, h, filter);
+    }
+    let float: Rgba32FImage = ImageBuffer::from_fn(frame.width(), frame.height(), |x, y| {
+        Rgba(frame.get_pixel(x, y).0.map(|c| c as f32 / 255.0))
+    });
+    DynamicImage::ImageRgba32F(premultiplied(&float, w, h, filter)).to_rgba8()
+}
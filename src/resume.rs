@@ -0,0 +1,174 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::{animation::Animation, messages::tr, options::Options, run_input, settings};
+
+// How often a GIF search saves where it's got to. Often enough that a crash
+// costs little, rarely enough that the writes don't
+const EVERY: Duration = Duration::from_secs(30);
+
+// Where a GIF search stands: everything the loop in search_gif carries from
+// one iteration to the next, apart from the random numbers
+#[derive(Clone, Copy)]
+pub struct State {
+    // What the search was run on, see fingerprint
+    pub fingerprint: u32,
+    pub target: u64,
+    pub byte_halt: u64,
+    pub iters: i32,
+    pub gif_speed: i32,
+    pub i: i32,
+    pub scale: f64,
+    pub size: f64,
+    // Size at full scale
+    pub full_size: f64,
+    pub best_scale: f64,
+    pub best_diff: f64,
+    pub best_size: f64,
+    pub low: f64,
+    pub high: f64,
+    pub encodes: u32,
+}
+
+// A single run from the command line, which is all that's ever resumed.
+// Batches and the daemon don't get one
+#[derive(Clone)]
+pub struct Session {
+    pub args: Vec<String>,
+    pub input: String,
+    // The checkpoint being picked up from, with `autosize resume`
+    pub resumed: Option<State>,
+}
+
+fn path() -> PathBuf {
+    settings::data_dir().join("checkpoint")
+}
+
+// Identifies the frames, delays and scale bounds a search ran on, so a
+// checkpoint is only picked up by the same search
+pub fn fingerprint(anim: &Animation, opts: &Options) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for (frame, delay) in anim.frames.iter().zip(&anim.delays) {
+        hasher.update(&frame.width().to_le_bytes());
+        hasher.update(&frame.height().to_le_bytes());
+        hasher.update(&delay.to_le_bytes());
+        hasher.update(frame.as_raw());
+    }
+    hasher.update(&opts.min_scale.to_le_bytes());
+    hasher.update(&opts.max_scale.to_le_bytes());
+    hasher.update(&[opts.adaptive as u8]);
+    hasher.finalize()
+}
+
+// Saves the state every EVERY, going by when it was last saved. Written
+// next to the checkpoint and renamed over it, so a crash halfway through
+// writing leaves the previous one
+pub fn save(opts: &Options, state: &State, saved_at: &mut Instant) {
+    let Some(session) = &opts.session else {
+        return;
+    };
+    if saved_at.elapsed() < EVERY {
+        return;
+    }
+    *saved_at = Instant::now();
+    let write = || -> io::Result<()> {
+        fs::create_dir_all(settings::data_dir())?;
+        let partial = path().with_extension("partial");
+        let mut file = fs::File::create(&partial)?;
+        writeln!(file, "input = {}", session.input)?;
+        for (key, value) in [
+            ("fingerprint", state.fingerprint as f64),
+            ("target", state.target as f64),
+            ("byte_halt", state.byte_halt as f64),
+            ("iters", state.iters as f64),
+            ("gif_speed", state.gif_speed as f64),
+            ("i", state.i as f64),
+            ("scale", state.scale),
+            ("size", state.size),
+            ("full_size", state.full_size),
+            ("best_scale", state.best_scale),
+            ("best_diff", state.best_diff),
+            ("best_size", state.best_size),
+            ("low", state.low),
+            ("high", state.high),
+            ("encodes", state.encodes as f64),
+        ] {
+            writeln!(file, "{} = {:?}", key, value)?;
+        }
+        for arg in &session.args {
+            writeln!(file, "arg = {}", arg)?;
+        }
+        file.sync_all()?;
+        fs::rename(partial, path())
+    };
+    if let Err(e) = write() {
+        println!("{}", tr!(CantSaveCheckpoint, e));
+    }
+}
+
+fn load() -> Option<Session> {
+    let contents = fs::read_to_string(path()).ok()?;
+    let (mut input, mut args) = (None, Vec::new());
+    let mut numbers = std::collections::HashMap::new();
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        match key {
+            "input" => input = Some(value.to_string()),
+            "arg" => args.push(value.to_string()),
+            _ => {
+                numbers.insert(key, value.parse::<f64>().ok()?);
+            }
+        }
+    }
+    let get = |key: &str| numbers.get(key).copied();
+    Some(Session {
+        args,
+        input: input?,
+        resumed: Some(State {
+            fingerprint: get("fingerprint")? as u32,
+            target: get("target")? as u64,
+            byte_halt: get("byte_halt")? as u64,
+            iters: get("iters")? as i32,
+            gif_speed: get("gif_speed")? as i32,
+            i: get("i")? as i32,
+            scale: get("scale")?,
+            size: get("size")?,
+            full_size: get("full_size")?,
+            best_scale: get("best_scale")?,
+            best_diff: get("best_diff")?,
+            best_size: get("best_size")?,
+            low: get("low")?,
+            high: get("high")?,
+            encodes: get("encodes")? as u32,
+        }),
+    })
+}
+
+// The run is over one way or another, so there's nothing to resume
+pub fn clear() {
+    let _ = fs::remove_file(path());
+}
+
+// `autosize resume` carries on with the GIF search a crash or reboot
+// interrupted, from its last checkpoint, with the same input and settings.
+// Everything before the search runs again, it's the search that takes long
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(arg) = args.first() {
+        return Err(tr!(UnknownArgument, arg).into());
+    }
+    let session = load().ok_or(tr!(NothingToResume))?;
+    let state = session.resumed.expect("a loaded session is always resumed");
+    println!("{}", tr!(Resuming, session.input, state.i, state.iters));
+    let mut opts = Options::parse(&session.args)?;
+    opts.input = Some(session.input.clone());
+    let args = session.args.clone();
+    opts.session = Some(session);
+    run_input(opts, &args)
+}
@@ -0,0 +1,201 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, ErrorKind},
+    thread,
+    time::Duration,
+};
+
+use image::ImageError;
+
+use crate::{messages::tr, settings};
+
+// Wait before the first retry, doubled for each one after
+const FIRST_DELAY: Duration = Duration::from_millis(200);
+
+// Windows errors from a network share dropping out or a file held open by
+// another program, which come through without a kind of their own:
+// ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION, ERROR_BAD_NETPATH,
+// ERROR_NETWORK_BUSY, ERROR_UNEXP_NET_ERR and ERROR_NETNAME_DELETED
+#[cfg(windows)]
+const NETWORK_ERRORS: &[i32] = &[32, 33, 53, 54, 59, 64];
+#[cfg(not(windows))]
+const NETWORK_ERRORS: &[i32] = &[];
+
+// How many times a failed read or write is tried again. Network drives and
+// cloud-synced folders fail now and then for a moment, which shouldn't throw
+// away a long search. Set with `io_retries = <n>` in the config file or
+// --retries, 0 turns it off
+#[derive(Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    // Wait before the first retry, FIRST_DELAY but for the tests
+    pub delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            delay: FIRST_DELAY,
+        }
+    }
+}
+
+pub fn default_policy() -> Retry {
+    settings::read_config()
+        .into_iter()
+        .rev()
+        .find(|(key, _)| key == "io_retries")
+        .and_then(|(_, n)| n.parse().ok())
+        .map(|attempts| Retry {
+            attempts,
+            ..Retry::default()
+        })
+        .unwrap_or_default()
+}
+
+// Errors worth another try. A missing file or a corrupt image won't be any
+// different a moment later, a dropped connection or a locked file might.
+// Only the kinds known to pass are retried, anything else, like a full disk
+// or a read-only filesystem, fails straight away
+pub trait Transient {
+    fn transient(&self) -> bool;
+}
+
+impl Transient for io::Error {
+    fn transient(&self) -> bool {
+        match self.kind() {
+            ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::StaleNetworkFileHandle => true,
+            // Another error wrapped up, which counts as it would on its own
+            ErrorKind::Other => match self.get_ref() {
+                Some(inner) => inner
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.transient()),
+                None => false,
+            },
+            _ => self
+                .raw_os_error()
+                .is_some_and(|code| NETWORK_ERRORS.contains(&code)),
+        }
+    }
+}
+
+impl Transient for Box<dyn Error> {
+    fn transient(&self) -> bool {
+        match (
+            self.downcast_ref::<io::Error>(),
+            self.downcast_ref::<ImageError>(),
+        ) {
+            (Some(e), _) | (_, Some(ImageError::IoError(e))) => e.transient(),
+            _ => false,
+        }
+    }
+}
+
+impl Retry {
+    // Runs f until it succeeds, fails for good or runs out of attempts
+    pub fn run<T, E: Transient + Display>(
+        &self,
+        what: impl Display,
+        mut f: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if attempt < self.attempts && e.transient() => {
+                    attempt += 1;
+                    println!(
+                        "{}",
+                        tr!(Retrying, what, e, delay.as_millis(), attempt, self.attempts)
+                    );
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_WAIT: Retry = Retry {
+        attempts: 2,
+        delay: Duration::ZERO,
+    };
+
+    #[test]
+    fn retries_only_transient_errors() {
+        let mut calls = 0;
+        let result = NO_WAIT.run("flaky", || {
+            calls += 1;
+            match calls {
+                1 => Err(io::Error::from(ErrorKind::TimedOut)),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let mut calls = 0;
+        let result: io::Result<()> = NO_WAIT.run("missing", || {
+            calls += 1;
+            Err(io::Error::from(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn gives_up_after_the_attempts() {
+        let mut calls = 0;
+        let result: io::Result<()> = NO_WAIT.run("down", || {
+            calls += 1;
+            Err(io::Error::from(ErrorKind::ConnectionReset))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn only_passing_errors_are_transient() {
+        for kind in [
+            ErrorKind::Interrupted,
+            ErrorKind::TimedOut,
+            ErrorKind::WouldBlock,
+            ErrorKind::ConnectionReset,
+            ErrorKind::ConnectionAborted,
+        ] {
+            assert!(io::Error::from(kind).transient(), "{:?}", kind);
+        }
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::PermissionDenied,
+            ErrorKind::StorageFull,
+            ErrorKind::ReadOnlyFilesystem,
+            ErrorKind::InvalidData,
+            ErrorKind::Other,
+        ] {
+            assert!(!io::Error::from(kind).transient(), "{:?}", kind);
+        }
+        // Other counts as what it wraps
+        let wrapped = io::Error::other(io::Error::from(ErrorKind::TimedOut));
+        assert!(wrapped.transient());
+        assert!(!io::Error::other("corrupt").transient());
+        let boxed: Box<dyn Error> = Box::new(io::Error::from(ErrorKind::Interrupted));
+        assert!(boxed.transient());
+        let boxed: Box<dyn Error> = "no".into();
+        assert!(!boxed.transient());
+    }
+}
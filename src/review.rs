@@ -0,0 +1,113 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+};
+
+use crate::{
+    messages::tr,
+    options::Options,
+    plan::{Plan, Strategy},
+    print_savings,
+    tools::{open, temp_path},
+    units,
+};
+
+enum Verdict {
+    Accept,
+    Retry(Vec<Strategy>),
+}
+
+fn read_line() -> io::Result<String> {
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            tr!(OutOfInput),
+        ));
+    }
+    Ok(line.trim().to_ascii_lowercase())
+}
+
+fn names(strategies: &[Strategy]) -> String {
+    strategies
+        .iter()
+        .map(|s| s.name())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Shows what the planner came up with and asks what to do about it. The
+// candidate is only written to a temp file when it's asked to be viewed
+fn ask(plan: &Plan, ext: &str, opts: &Options) -> Result<Verdict, Box<dyn Error>> {
+    println!(
+        "\n{}",
+        tr!(
+            ReviewResult,
+            units::format_size(plan.bytes.len() as u64),
+            units::format_size(opts.target),
+            plan.scale
+        )
+    );
+    print_savings(&plan.attribution());
+    let mut preview = None;
+    let verdict = loop {
+        print!("{} ", tr!(ReviewChoice));
+        io::stdout().flush()?;
+        match read_line()?.as_str() {
+            "" | "a" | "y" => break Verdict::Accept,
+            "v" => {
+                let path = preview.get_or_insert_with(|| temp_path("review", ext));
+                let opened = fs::write(&*path, &plan.bytes).and_then(|_| open(path));
+                if let Err(e) = opened {
+                    println!("{}", tr!(CantOpenViewer, e));
+                }
+            }
+            "r" => {
+                let strategies = loop {
+                    println!("{}", tr!(ReviewStrategies, names(&opts.strategies)));
+                    if let Some(strategies) = Strategy::parse_list(&read_line()?) {
+                        break strategies;
+                    }
+                };
+                break Verdict::Retry(strategies);
+            }
+            "q" => {
+                if let Some(path) = &preview {
+                    let _ = fs::remove_file(path);
+                }
+                return Err(tr!(ReviewCancelled).into());
+            }
+            _ => (),
+        }
+    };
+    if let Some(path) = preview {
+        let _ = fs::remove_file(path);
+    }
+    Ok(verdict)
+}
+
+// Runs fit, and with --review keeps running it with the strategies asked
+// for until its result is accepted. A retry that fails leaves the last
+// result up for review
+pub fn until_accepted(
+    ext: &str,
+    opts: &Options,
+    fit: impl Fn(&Options) -> Result<Plan, Box<dyn Error>>,
+) -> Result<Plan, Box<dyn Error>> {
+    let mut opts = opts.clone();
+    let mut plan = fit(&opts)?;
+    while opts.review {
+        match ask(&plan, ext, &opts)? {
+            Verdict::Accept => break,
+            Verdict::Retry(strategies) => {
+                opts.strategies = strategies;
+                match fit(&opts) {
+                    Ok(retried) => plan = retried,
+                    Err(e) => println!("{}", tr!(RetryFailed, e)),
+                }
+            }
+        }
+    }
+    Ok(plan)
+}
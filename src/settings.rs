@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+// The values entered at the prompts on the previous run, offered as the
+// defaults on the next one so repeat users can just hit Enter
+pub struct LastSettings {
+    pub target: u64,
+    pub byte_halt: u64,
+    pub iters: u64,
+    pub gif_speed: u64,
+}
+
+impl Default for LastSettings {
+    fn default() -> Self {
+        LastSettings {
+            target: 1000,
+            byte_halt: 128,
+            iters: 256,
+            gif_speed: 10,
+        }
+    }
+}
+
+// Where autosize keeps its state and configuration
+pub fn data_dir() -> PathBuf {
+    PathBuf::from(".autosize")
+}
+
+pub fn config_path() -> PathBuf {
+    data_dir().join("config")
+}
+
+// `key = value` lines from the config file, skipping comments. Keys may
+// repeat, e.g. one `tool.png` line per external tool
+pub fn read_config() -> Vec<(String, String)> {
+    fs::read_to_string(config_path())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+fn state_path() -> PathBuf {
+    data_dir().join("last_settings")
+}
+
+impl LastSettings {
+    // Missing or unreadable entries just keep their built-in default
+    pub fn load() -> LastSettings {
+        let mut settings = LastSettings::default();
+        if let Ok(contents) = fs::read_to_string(state_path()) {
+            for line in contents.lines() {
+                let (key, value) = match line.split_once('=') {
+                    Some((k, v)) => (k.trim(), v.trim()),
+                    None => continue,
+                };
+                if let Ok(value) = value.parse() {
+                    match key {
+                        "target" => settings.target = value,
+                        "byte_halt" => settings.byte_halt = value,
+                        "iters" => settings.iters = value,
+                        "gif_speed" => settings.gif_speed = value,
+                        _ => (),
+                    }
+                }
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = state_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "target = {}", self.target)?;
+        writeln!(file, "byte_halt = {}", self.byte_halt)?;
+        writeln!(file, "iters = {}", self.iters)?;
+        writeln!(file, "gif_speed = {}", self.gif_speed)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::{error::Error, path::Path, time::Instant};
+
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::{
+    batch::collect_inputs, color, messages::tr, options::Options, output_stem, prompt_settings,
+};
+
+// Gap between cells and around the edge, in pixels at full size
+const PADDING: u32 = 4;
+
+fn thumbnail(path: &Path, cell: u32) -> Option<DynamicImage> {
+    match image::open(path) {
+        Ok(img) => Some(img.resize(cell, cell, imageops::FilterType::Lanczos3)),
+        Err(e) => {
+            println!("Skipping {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+// Lays the thumbnails out row by row, each centred in a cell x cell square
+fn build(thumbs: &[DynamicImage], columns: u32, cell: u32, background: [u8; 3]) -> RgbImage {
+    let rows = (thumbs.len() as u32).div_ceil(columns);
+    let (w, h) = (
+        columns * (cell + PADDING) + PADDING,
+        rows * (cell + PADDING) + PADDING,
+    );
+    let mut sheet = RgbImage::from_pixel(w, h, Rgb(background));
+    for (i, thumb) in thumbs.iter().enumerate() {
+        let (col, row) = (i as u32 % columns, i as u32 / columns);
+        let x = PADDING + col * (cell + PADDING) + (cell - thumb.width()) / 2;
+        let y = PADDING + row * (cell + PADDING) + (cell - thumb.height()) / 2;
+        let flat = color::flatten(thumb, background).to_rgb8();
+        imageops::replace(&mut sheet, &flat, x as i64, y as i64);
+    }
+    sheet
+}
+
+// `autosize sheet <files or dirs>... [--columns <n>] [--cell <px>]` tiles
+// the images into one contact sheet and fits that into the target size.
+// Any other flag is a normal option, e.g. --format png
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut columns, mut cell) = (None, 256u32);
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if flag != "--columns" && flag != "--cell" {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = inline
+            .or_else(|| args.next().cloned())
+            .ok_or(format!("{} expects a value", flag))?;
+        let number = value.parse::<u32>().ok().filter(|&n| n > 0).ok_or(format!(
+            "{} expects a positive number, got '{}'",
+            flag, value
+        ))?;
+        match flag {
+            "--columns" => columns = Some(number),
+            _ => cell = number,
+        }
+    }
+    let mut opts = Options::parse(&rest)?;
+    let files = collect_inputs(&opts.inputs, false, &opts)?
+        .into_iter()
+        .map(|(file, _)| file)
+        .collect::<Vec<_>>();
+    let thumbs = files
+        .par_iter()
+        .filter_map(|path| thumbnail(path, cell))
+        .collect::<Vec<_>>();
+    if thumbs.is_empty() {
+        return Err(
+            "Usage: autosize sheet <files or directories>... [--columns <n>] [--cell <px>]".into(),
+        );
+    }
+    // Roughly square by default
+    let columns = columns.unwrap_or((thumbs.len() as f64).sqrt().ceil() as u32);
+    let sheet = DynamicImage::ImageRgb8(build(&thumbs, columns, cell, opts.background));
+    println!(
+        "Tiled {} images into a {}x{} sheet",
+        thumbs.len(),
+        sheet.width(),
+        sheet.height()
+    );
+    let ext = opts.format.clone().unwrap_or_else(|| "jpg".to_string());
+    prompt_settings(&mut opts, false)?;
+    println!("{}", tr!(OneMoment));
+    let now = Instant::now();
+    let (plan, output) = crate::find_largest_within(
+        &sheet,
+        &ext,
+        None,
+        &output_stem(Path::new("sheet"), &opts),
+        &opts,
+    )?;
+    println!(
+        "\nWrote {} at a scale of {:.2} in {}ms",
+        output.display(),
+        plan.scale,
+        now.elapsed().as_millis()
+    );
+    Ok(())
+}
@@ -0,0 +1,162 @@
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::fs;
+use std::{env, error::Error, path::Path};
+
+use crate::units;
+
+// The Discord upload limit, where most people want things to fit
+const DEFAULT_SIZE: u64 = 8_000_000;
+
+// Explorer's menu for every file it counts as an image
+#[cfg(windows)]
+const REGISTRY_KEY: &str = r"HKCU\Software\Classes\SystemFileAssociations\image\shell\autosize";
+
+// Runs `reg` with args, which saves pulling in a registry crate for two keys
+#[cfg(windows)]
+fn reg(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = std::process::Command::new("reg").args(args).status()?;
+    if !status.success() {
+        return Err(format!("reg {} failed with {}", args[0], status).into());
+    }
+    Ok(())
+}
+
+// Explorer runs the command from the file's folder, so the result lands next
+// to it. Everything goes under HKCU, no administrator needed
+#[cfg(windows)]
+fn install(exe: &Path, label: &str, size: u64) -> Result<(), Box<dyn Error>> {
+    let command = format!("\"{}\" batch \"%1\" --target {}", exe.display(), size);
+    reg(&["add", REGISTRY_KEY, "/ve", "/d", label, "/f"])?;
+    reg(&[
+        "add",
+        REGISTRY_KEY,
+        "/v",
+        "Icon",
+        "/d",
+        &exe.to_string_lossy(),
+        "/f",
+    ])?;
+    reg(&[
+        "add",
+        &format!(r"{}\command", REGISTRY_KEY),
+        "/ve",
+        "/d",
+        &command,
+        "/f",
+    ])?;
+    println!("Added \"{}\" to the Explorer menu of images", label);
+    Ok(())
+}
+
+#[cfg(windows)]
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    reg(&["delete", REGISTRY_KEY, "/f"])?;
+    println!("Removed autosize from the Explorer menu");
+    Ok(())
+}
+
+// Nautilus lists the scripts in this folder under Scripts in its menu, and
+// runs them from the current folder with the selected files as arguments
+#[cfg(all(unix, not(target_os = "macos")))]
+fn scripts_dir() -> Result<std::path::PathBuf, Box<dyn Error>> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .ok_or("Couldn't find the home folder")?;
+    Ok(data.join("nautilus/scripts"))
+}
+
+// Scripts written by us start with this line, so uninstall leaves anything
+// else in the folder alone
+#[cfg(all(unix, not(target_os = "macos")))]
+const MARKER: &str = "# Installed by autosize install-shell-integration";
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn install(exe: &Path, label: &str, size: u64) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scripts_dir()?;
+    fs::create_dir_all(&dir)?;
+    let script = dir.join(label);
+    let quoted = exe.to_string_lossy().replace('\'', r"'\''");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\n{}\nexec '{}' batch \"$@\" --target {}\n",
+            MARKER, quoted, size
+        ),
+    )?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    println!(
+        "Added \"{}\" to the Scripts menu of Nautilus ({})",
+        label,
+        script.display()
+    );
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    let mut removed = 0;
+    if let Ok(entries) = fs::read_dir(scripts_dir()?) {
+        for entry in entries.flatten() {
+            let ours = fs::read_to_string(entry.path())
+                .is_ok_and(|script| script.lines().nth(1) == Some(MARKER));
+            if ours {
+                fs::remove_file(entry.path())?;
+                println!("Removed {}", entry.path().display());
+                removed += 1;
+            }
+        }
+    }
+    if removed == 0 {
+        println!("No autosize scripts were installed");
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+fn install(_: &Path, _: &str, _: u64) -> Result<(), Box<dyn Error>> {
+    Err("Shell integration is only available for Windows Explorer and Nautilus".into())
+}
+
+#[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    install(Path::new(""), "", 0)
+}
+
+// `autosize install-shell-integration [--size <size>] [--uninstall]`: adds a
+// "Shrink to 8 MB" entry to the file manager's menu for images, which runs
+// `autosize batch <files> --target <size>` on the selection
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut size = DEFAULT_SIZE;
+    let mut remove = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) => (f, Some(v.to_string())),
+            None => (arg.as_str(), None),
+        };
+        match flag {
+            "--size" => {
+                let value = inline
+                    .or_else(|| args.next().cloned())
+                    .ok_or("--size expects a value")?;
+                size = units::parse_size(&value)
+                    .filter(|&size| size >= 128)
+                    .ok_or(format!("--size expects a size like 8MB, got '{}'", value))?;
+            }
+            "--uninstall" => remove = true,
+            _ => return Err(format!("Unknown argument '{}'", arg).into()),
+        }
+    }
+    if remove {
+        return uninstall();
+    }
+    let exe = env::current_exe()?;
+    install(
+        &exe,
+        &format!("Shrink to {}", units::format_size(size)),
+        size,
+    )
+}
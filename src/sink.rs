@@ -0,0 +1,228 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+use crate::{
+    backup,
+    options::Options,
+    remote,
+    tools::{pipe_into, temp_path},
+};
+
+// Where a finished output goes. The fit itself only produces bytes and a
+// name for them, so adding somewhere new to send results doesn't touch the
+// search. --output picks one, see parse
+pub trait OutputSink {
+    // Sends the output off, returning where it ended up for the messages
+    // and the history
+    fn deliver(&self, bytes: &[u8], name: &str, opts: &Options) -> Result<String, Box<dyn Error>>;
+
+    // Whether the result is a file on this machine afterwards, which is
+    // what --open and --checksums need
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    // Where a file that goes with the output, like the --preview, is sent.
+    // None when there's only room for the one
+    fn companion(&self, _name: &str) -> Option<Box<dyn OutputSink>> {
+        None
+    }
+}
+
+// A path on disk, written like any other output
+pub struct File(pub PathBuf);
+
+impl OutputSink for File {
+    fn deliver(&self, bytes: &[u8], _: &str, opts: &Options) -> Result<String, Box<dyn Error>> {
+        backup::write_output(&self.0, bytes, opts)?;
+        Ok(self.0.display().to_string())
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn companion(&self, name: &str) -> Option<Box<dyn OutputSink>> {
+        Some(Box::new(File(self.0.with_file_name(name))))
+    }
+}
+
+// An s3:// or gs:// object, or a prefix the output goes under by name
+pub struct Store(pub remote::Object);
+
+impl OutputSink for Store {
+    fn deliver(&self, bytes: &[u8], name: &str, _: &Options) -> Result<String, Box<dyn Error>> {
+        let object = self.0.for_file(name);
+        // The providers' tools upload files, so it goes through one
+        let dir = remote::staging_dir()?;
+        let staged = dir.join(name);
+        let uploaded = fs::write(&staged, bytes)
+            .map_err(Box::from)
+            .and_then(|_| object.upload(&staged));
+        remote::clean_up(&staged);
+        uploaded?;
+        Ok(object.url().to_string())
+    }
+
+    fn companion(&self, name: &str) -> Option<Box<dyn OutputSink>> {
+        Some(Box::new(Store(self.0.beside(name))))
+    }
+}
+
+// The raw bytes on stdout, for `autosize --output - ... | something`
+pub struct Stdout(Mutex<fs::File>);
+
+impl Stdout {
+    // Everything autosize says goes to stdout, so from here on it's sent to
+    // stderr instead and the real stdout is kept for the bytes alone
+    #[cfg(unix)]
+    pub fn claim() -> io::Result<Stdout> {
+        use std::os::fd::FromRawFd;
+        io::stdout().flush()?;
+        // SAFETY: dup hands back a descriptor nothing else owns, and dup2
+        // only repoints fd 1, which std keeps using through its own handle
+        unsafe {
+            let saved = libc::dup(1);
+            if saved < 0 || libc::dup2(2, 1) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Stdout(Mutex::new(fs::File::from_raw_fd(saved))))
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn claim() -> io::Result<Stdout> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--output - is only supported on Unix",
+        ))
+    }
+}
+
+impl OutputSink for Stdout {
+    fn deliver(&self, bytes: &[u8], _: &str, _: &Options) -> Result<String, Box<dyn Error>> {
+        let mut out = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        out.write_all(bytes)?;
+        out.flush()?;
+        Ok("stdout".to_string())
+    }
+}
+
+// The system clipboard, as an image, for pasting straight into a chat
+pub struct Clipboard;
+
+fn mime(name: &str) -> &'static str {
+    let ext = Path::new(name)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_ascii_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "jxl" => "image/jxl",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+impl OutputSink for Clipboard {
+    fn deliver(&self, bytes: &[u8], name: &str, _: &Options) -> Result<String, Box<dyn Error>> {
+        let mime = mime(name);
+        if cfg!(any(windows, target_os = "macos")) {
+            // Both only take images from files
+            let file = temp_path("clipboard", name.rsplit('.').next().unwrap_or("png"));
+            fs::write(&file, bytes)?;
+            let path = file.to_string_lossy().replace('"', "");
+            let mut command = if cfg!(windows) {
+                let mut command = Command::new("powershell");
+                command.args(["-NoProfile", "-Command", &format!(
+                    "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; [Windows.Forms.Clipboard]::SetImage([Drawing.Image]::FromFile(\"{}\"))",
+                    path
+                )]);
+                command
+            } else {
+                let class = if mime == "image/jpeg" { "JPEG" } else { "PNGf" };
+                let mut command = Command::new("osascript");
+                command.args([
+                    "-e",
+                    &format!(
+                        "set the clipboard to (read (POSIX file \"{}\") as «class {}»)",
+                        path, class
+                    ),
+                ]);
+                command
+            };
+            let copied = pipe_into(&mut command, &[]);
+            let _ = fs::remove_file(&file);
+            copied?;
+        } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            pipe_into(Command::new("wl-copy").args(["--type", mime]), bytes)?;
+        } else {
+            let args = ["-selection", "clipboard", "-t", mime, "-i"];
+            pipe_into(Command::new("xclip").args(args), bytes)?;
+        }
+        Ok("the clipboard".to_string())
+    }
+}
+
+// An HTTP or HTTPS endpoint the bytes are POSTed to, through curl so TLS,
+// proxies and redirects are its problem
+pub struct HttpPost(pub String);
+
+impl OutputSink for HttpPost {
+    fn deliver(&self, bytes: &[u8], name: &str, _: &Options) -> Result<String, Box<dyn Error>> {
+        let response = pipe_into(
+            Command::new("curl").args([
+                "--fail",
+                "--silent",
+                "--show-error",
+                "--location",
+                "--data-binary",
+                "@-",
+                "--header",
+                &format!("Content-Type: {}", mime(name)),
+                "--header",
+                &format!("X-Filename: {}", name.replace(['\r', '\n'], "")),
+                &self.0,
+            ]),
+            bytes,
+        )?;
+        // Upload services usually answer with where the file can be found
+        let response = String::from_utf8_lossy(&response);
+        let response = response.trim();
+        if !response.is_empty() && response.len() <= 200 && !response.contains('\n') {
+            return Ok(format!("{} ({})", self.0, response));
+        }
+        Ok(self.0.clone())
+    }
+
+    // The same endpoint, told apart by X-Filename
+    fn companion(&self, _name: &str) -> Option<Box<dyn OutputSink>> {
+        Some(Box::new(HttpPost(self.0.clone())))
+    }
+}
+
+// What --output names: `-` for stdout, `clipboard:`, an http(s):// URL to
+// POST to, an s3:// or gs:// URL, or else a file path
+pub fn parse(output: &str) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+    Ok(if output == "-" {
+        Box::new(Stdout::claim()?)
+    } else if output == "clipboard:" {
+        Box::new(Clipboard)
+    } else if output.starts_with("http://") || output.starts_with("https://") {
+        Box::new(HttpPost(output.to_string()))
+    } else if let Some(object) = remote::Object::parse(output) {
+        Box::new(Store(object))
+    } else {
+        Box::new(File(PathBuf::from(output)))
+    })
+}
@@ -0,0 +1,262 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use image::ImageFormat;
+
+use crate::{
+    remote,
+    tools::{pipe_into, temp_path},
+};
+
+// Where an input comes from. Whatever it is, it ends up as a local file
+// named after it with the extension its content calls for, so everything
+// past fetch (the decoders, --low-memory, the ICC profile) reads files the
+// same way. --input picks one, see parse
+pub trait InputSource {
+    // What the input is called in messages and the history
+    fn describe(&self) -> String;
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>>;
+
+    // Whether the input is a file on this machine already, which --in-place
+    // needs to have something to replace
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+// A fetched input. Anything that had to be written out is in a folder of
+// its own in the temp folder, removed again by clean_up
+pub struct Fetched {
+    pub path: PathBuf,
+    temporary: bool,
+}
+
+impl Fetched {
+    pub fn clean_up(self) {
+        if self.temporary {
+            remote::clean_up(&self.path);
+        }
+    }
+}
+
+// The extension for what the bytes hold, going by their first bytes
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    let format = image::guess_format(bytes).ok()?;
+    format.extensions_str().first().copied()
+}
+
+// The extension name has when it agrees with the content, so a .jpeg stays
+// .jpeg, or else the content's
+fn agreeing(name: &Path, sniffed: &str) -> String {
+    match name.extension().map(|e| e.to_string_lossy().to_string()) {
+        Some(ext) if ImageFormat::from_extension(&ext) == ImageFormat::from_extension(sniffed) => {
+            ext
+        }
+        _ => sniffed.to_string(),
+    }
+}
+
+// The format of a file by its content, for inputs whose name says nothing
+// or the wrong thing
+pub fn format_of(path: &Path) -> Option<String> {
+    let mut head = [0; 64];
+    let read = fs::File::open(path)
+        .and_then(|mut f| f.read(&mut head))
+        .ok()?;
+    Some(agreeing(path, sniff(&head[..read])?))
+}
+
+// Writes bytes to a fresh temp folder, named like name but with the
+// extension their content calls for
+fn stage(bytes: &[u8], name: &str) -> Result<Fetched, Box<dyn Error>> {
+    let ext = sniff(bytes).ok_or("That doesn't look like an image autosize can read")?;
+    let dir = temp_path("input", "d");
+    fs::create_dir_all(&dir)?;
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .unwrap_or(name.as_os_str())
+        .to_string_lossy();
+    let path = dir.join(format!("{}.{}", stem, agreeing(name, ext)));
+    fs::write(&path, bytes)?;
+    Ok(Fetched {
+        path,
+        temporary: true,
+    })
+}
+
+// A file on disk
+pub struct File(pub PathBuf);
+
+impl InputSource for File {
+    fn describe(&self) -> String {
+        self.0.display().to_string()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        Ok(Fetched {
+            path: self.0.clone(),
+            temporary: false,
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+// An image already in memory, with a name for the outputs to go by
+pub struct Bytes {
+    pub bytes: Vec<u8>,
+    pub name: String,
+}
+
+impl InputSource for Bytes {
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        stage(&self.bytes, &self.name)
+    }
+}
+
+// Whatever is piped in, for `something | autosize --input - ...`
+pub struct Stdin;
+
+impl InputSource for Stdin {
+    fn describe(&self) -> String {
+        "stdin".to_string()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        Bytes {
+            bytes,
+            name: "stdin".to_string(),
+        }
+        .fetch()
+    }
+}
+
+// An image on the system clipboard, e.g. a screenshot just taken
+pub struct Clipboard;
+
+impl InputSource for Clipboard {
+    fn describe(&self) -> String {
+        "the clipboard".to_string()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        if cfg!(any(windows, target_os = "macos")) {
+            // Both only hand images over as files
+            let dir = temp_path("input", "d");
+            fs::create_dir_all(&dir)?;
+            let path = dir.join("clipboard.png");
+            let target = path.to_string_lossy().replace('"', "");
+            let mut command = if cfg!(windows) {
+                let mut command = Command::new("powershell");
+                command.args(["-NoProfile", "-Command", &format!(
+                    "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; $i = [Windows.Forms.Clipboard]::GetImage(); if (!$i) {{ exit 1 }}; $i.Save(\"{}\", [Drawing.Imaging.ImageFormat]::Png)",
+                    target
+                )]);
+                command
+            } else {
+                let mut command = Command::new("osascript");
+                command.args([
+                    "-e",
+                    &format!(
+                        "set f to open for access (POSIX file \"{}\") with write permission",
+                        target
+                    ),
+                    "-e",
+                    "write (the clipboard as «class PNGf») to f",
+                    "-e",
+                    "close access f",
+                ]);
+                command
+            };
+            let fetched = Fetched {
+                path,
+                temporary: true,
+            };
+            if let Err(e) = pipe_into(&mut command, &[]) {
+                fetched.clean_up();
+                return Err(format!("There's no image on the clipboard ({})", e).into());
+            }
+            return Ok(fetched);
+        }
+        let bytes = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            pipe_into(Command::new("wl-paste").args(["--type", "image/png"]), &[])?
+        } else {
+            let args = ["-selection", "clipboard", "-t", "image/png", "-o"];
+            pipe_into(Command::new("xclip").args(args), &[])?
+        };
+        stage(&bytes, "clipboard")
+    }
+}
+
+// An http:// or https:// URL, fetched with curl like --output posts with it
+pub struct Url(pub String);
+
+impl InputSource for Url {
+    fn describe(&self) -> String {
+        self.0.clone()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        let bytes = pipe_into(
+            Command::new("curl").args([
+                "--fail",
+                "--silent",
+                "--show-error",
+                "--location",
+                &self.0,
+            ]),
+            &[],
+        )?;
+        // Named after the last part of the path, minus any query
+        let path = self.0.split(['?', '#']).next().unwrap_or_default();
+        let name = path.rsplit('/').next().filter(|n| !n.is_empty());
+        stage(&bytes, name.unwrap_or("download"))
+    }
+}
+
+// An s3:// or gs:// object
+pub struct Store(pub remote::Object);
+
+impl InputSource for Store {
+    fn describe(&self) -> String {
+        self.0.url().to_string()
+    }
+
+    fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        Ok(Fetched {
+            path: self.0.download()?,
+            temporary: true,
+        })
+    }
+}
+
+// What --input names: `-` for stdin, `clipboard:`, an http(s):// URL, an
+// s3:// or gs:// URL, or else a file path
+pub fn parse(input: &str) -> Box<dyn InputSource> {
+    if input == "-" {
+        Box::new(Stdin)
+    } else if input == "clipboard:" {
+        Box::new(Clipboard)
+    } else if input.starts_with("http://") || input.starts_with("https://") {
+        Box::new(Url(input.to_string()))
+    } else if let Some(object) = remote::Object::parse(input) {
+        Box::new(Store(object))
+    } else {
+        Box::new(File(PathBuf::from(input)))
+    }
+}
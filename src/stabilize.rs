@@ -0,0 +1,123 @@
+use image::{imageops, GrayImage, RgbaImage};
+use rayon::prelude::*;
+
+use crate::animation::Animation;
+
+// Frames are compared at about this width, which is plenty to find a shift
+// and keeps the search cheap
+const PROBE_WIDTH: u32 = 160;
+// Largest shift looked for between two frames, in probe pixels
+const MAX_SHIFT: i32 = 8;
+// Steadying that would crop away more than this fraction of either side is
+// most likely a pan or a scroll, not shake
+const MAX_CROP: f64 = 0.25;
+
+// What stabilize did, for the caller to report
+pub enum Outcome {
+    Steadied(Animation),
+    Steady,
+    TooMuchMotion,
+}
+
+fn probe(frame: &RgbaImage, factor: f64) -> GrayImage {
+    let gray = imageops::grayscale(frame);
+    let (w, h) = gray.dimensions();
+    imageops::resize(
+        &gray,
+        ((w as f64 / factor) as u32).max(1),
+        ((h as f64 / factor) as u32).max(1),
+        imageops::FilterType::Triangle,
+    )
+}
+
+// Mean difference between cur and prev moved by (dx, dy), over where they
+// overlap, looking at every other pixel
+fn difference(prev: &GrayImage, cur: &GrayImage, dx: i32, dy: i32) -> f64 {
+    let (w, h) = (cur.width() as i32, cur.height() as i32);
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in (dy.max(0)..h + dy.min(0)).step_by(2) {
+        for x in (dx.max(0)..w + dx.min(0)).step_by(2) {
+            let a = cur.get_pixel(x as u32, y as u32)[0];
+            let b = prev.get_pixel((x - dx) as u32, (y - dy) as u32)[0];
+            sum += a.abs_diff(b) as u64;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return f64::MAX;
+    }
+    sum as f64 / count as f64
+}
+
+// How far the content moved from prev to cur, the smallest shift winning
+// ties so still frames stay put
+fn shift(prev: &GrayImage, cur: &GrayImage) -> (i32, i32) {
+    let mut best: ((i32, i32), f64) = ((0, 0), difference(prev, cur, 0, 0));
+    for dy in -MAX_SHIFT..=MAX_SHIFT {
+        for dx in -MAX_SHIFT..=MAX_SHIFT {
+            let diff = difference(prev, cur, dx, dy);
+            let closer = dx.abs() + dy.abs() < best.0 .0.abs() + best.0 .1.abs();
+            if diff < best.1 || (diff == best.1 && closer) {
+                best = ((dx, dy), diff);
+            }
+        }
+    }
+    best.0
+}
+
+// Moves every frame so its content stays where it was in the first one, and
+// crops to the area all the frames still cover. Made for handheld or
+// jittery screen recordings: steady frames differ less from each other, so
+// frame differencing and the palettes do better with them. All of the
+// motion is taken out, not just the jitter, so anything that moves further
+// than MAX_CROP allows is taken for a pan and left alone
+pub fn stabilize(anim: &Animation) -> Outcome {
+    let Some(first) = anim.frames.first() else {
+        return Outcome::Steady;
+    };
+    let (w, h) = first.dimensions();
+    let factor = (w as f64 / PROBE_WIDTH as f64).max(1.0);
+    let probes = anim
+        .frames
+        .par_iter()
+        .map(|f| probe(f, factor))
+        .collect::<Vec<_>>();
+    let shifts = (1..probes.len())
+        .into_par_iter()
+        .map(|i| shift(&probes[i - 1], &probes[i]))
+        .collect::<Vec<_>>();
+    // Where each frame's content sits relative to the first frame's
+    let mut positions = vec![(0i64, 0i64)];
+    for (dx, dy) in shifts {
+        let (x, y) = positions[positions.len() - 1];
+        positions.push((
+            x + (dx as f64 * factor).round() as i64,
+            y + (dy as f64 * factor).round() as i64,
+        ));
+    }
+    let min_x = positions.iter().map(|p| p.0).min().unwrap_or(0);
+    let max_x = positions.iter().map(|p| p.0).max().unwrap_or(0);
+    let min_y = positions.iter().map(|p| p.1).min().unwrap_or(0);
+    let max_y = positions.iter().map(|p| p.1).max().unwrap_or(0);
+    let (spread_x, spread_y) = (max_x - min_x, max_y - min_y);
+    if spread_x == 0 && spread_y == 0 {
+        return Outcome::Steady;
+    }
+    if spread_x as f64 > w as f64 * MAX_CROP || spread_y as f64 > h as f64 * MAX_CROP {
+        return Outcome::TooMuchMotion;
+    }
+    let (out_w, out_h) = (w - spread_x as u32, h - spread_y as u32);
+    let frames = anim
+        .frames
+        .par_iter()
+        .zip(&positions)
+        .map(|(frame, &(x, y))| {
+            imageops::crop_imm(frame, (x - min_x) as u32, (y - min_y) as u32, out_w, out_h)
+                .to_image()
+        })
+        .collect();
+    Outcome::Steadied(Animation {
+        frames,
+        delays: anim.delays.clone(),
+    })
+}
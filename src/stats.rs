@@ -0,0 +1,150 @@
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+use crate::{history::Entry, units};
+
+// The value below which a fraction p of the sorted values fall
+fn percentile<T: Copy>(sorted: &[T], p: f64) -> T {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn sorted<T: PartialOrd>(mut values: Vec<T>) -> Vec<T> {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    values
+}
+
+// How far under the target a run ended, as a percentage of the target
+fn under(e: &Entry) -> f64 {
+    (e.target as f64 - e.size as f64) / e.target.max(1) as f64 * 100.0
+}
+
+fn n_searches(n: usize) -> String {
+    format!("{} search{}", n, if n == 1 { "" } else { "es" })
+}
+
+fn format_of(e: &Entry) -> String {
+    Path::new(&e.output)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+// `autosize stats [<filter>]`: how many candidates past searches needed and
+// how close they got, from the history. Nothing leaves the machine, it's
+// only the local .autosize/history read back
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut filter = None;
+    for arg in args {
+        if arg.starts_with("--") {
+            return Err(format!("Unknown argument '{}'", arg).into());
+        }
+        filter = Some(arg.to_lowercase());
+    }
+    let entries = Entry::load_all();
+    let runs = entries.len();
+    let searches = entries
+        .into_iter()
+        .filter(|e| e.encodes.is_some())
+        .filter(|e| {
+            filter.as_ref().is_none_or(|f| {
+                [&e.input, &e.output, &e.flags]
+                    .iter()
+                    .any(|s| s.to_lowercase().contains(f.as_str()))
+            })
+        })
+        .collect::<Vec<_>>();
+    if searches.is_empty() {
+        println!("No searches recorded yet");
+        return Ok(());
+    }
+    println!(
+        "{} out of {} recorded runs\n",
+        n_searches(searches.len()),
+        runs
+    );
+
+    let encodes = sorted(searches.iter().filter_map(|e| e.encodes).collect());
+    // A search that ran out of iterations encodes a couple more candidates
+    // than the limit, the first ones come before the loop
+    let limited = searches
+        .iter()
+        .filter(|e| matches!((e.encodes, e.iters), (Some(n), Some(m)) if n as i64 > m as i64))
+        .count();
+    println!("Candidates encoded per search:");
+    println!(
+        "  median {}, 90% at or below {}, most {}",
+        percentile(&encodes, 0.5),
+        percentile(&encodes, 0.9),
+        encodes[encodes.len() - 1]
+    );
+    println!("  {} ran out of iterations", limited);
+
+    let fitted = searches
+        .iter()
+        .filter(|e| e.size <= e.target)
+        .collect::<Vec<_>>();
+    let over = searches.len() - fitted.len();
+    println!("\nDistance below the target when done:");
+    if fitted.is_empty() {
+        println!("  none fit");
+    } else {
+        let gaps = sorted(fitted.iter().map(|e| e.target - e.size).collect());
+        let percents = sorted(fitted.iter().map(|e| under(e)).collect());
+        println!(
+            "  median {:.1}% ({}), 90% within {:.1}% ({})",
+            percentile(&percents, 0.5),
+            units::format_size(percentile(&gaps, 0.5)),
+            percentile(&percents, 0.9),
+            units::format_size(percentile(&gaps, 0.9))
+        );
+        let within = fitted
+            .iter()
+            .filter(|e| e.byte_halt.is_some_and(|halt| e.target - e.size <= halt))
+            .count();
+        println!("  {} stopped within the byte threshold", within);
+    }
+    if over > 0 {
+        println!("  {} ended over the target", over);
+    }
+
+    let mut formats = BTreeMap::<String, Vec<&Entry>>::new();
+    for e in &searches {
+        formats.entry(format_of(e)).or_default().push(e);
+    }
+    if formats.len() > 1 {
+        println!("\nBy output format:");
+        for (format, entries) in &formats {
+            let encodes = sorted(entries.iter().filter_map(|e| e.encodes).collect());
+            let percents = sorted(entries.iter().map(|e| under(e)).collect());
+            println!(
+                "  {:<5} {:>13}, median {} encodes, median {:.1}% under",
+                format,
+                n_searches(entries.len()),
+                percentile(&encodes, 0.5),
+                percentile(&percents, 0.5)
+            );
+        }
+    }
+
+    // Searches mostly stop on the byte threshold or a narrow enough range
+    // long before the limit, so a limit just above what nearly all of them
+    // used costs nothing and caps the occasional runaway
+    let enough = percentile(&encodes, 0.95);
+    let current = searches.iter().rev().find_map(|e| e.iters);
+    println!();
+    match current {
+        Some(current) if limited * 10 > searches.len() => println!(
+            "{} of {} searches ran out of iterations, a limit above {} would get them closer to the target",
+            limited,
+            searches.len(),
+            current
+        ),
+        Some(current) if (enough as i64) < current as i64 / 2 => println!(
+            "95% of searches needed {} candidates or fewer, an iteration limit around {} would be plenty (the last run allowed {})",
+            enough,
+            enough + enough / 4,
+            current
+        ),
+        _ => println!("The iteration limit suits these searches"),
+    }
+    Ok(())
+}
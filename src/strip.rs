@@ -0,0 +1,67 @@
+use std::{
+    error::Error,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use image::{imageops, DynamicImage, Rgb, RgbImage};
+
+use crate::{
+    animation::Animation,
+    backup, color,
+    encode::{self, EncoderSettings},
+    options::Options,
+};
+
+// Gap between frames and around the edge, as on a contact sheet
+const PADDING: u32 = 4;
+
+// Indices of count frames out of total, spread evenly from the first to the
+// last
+fn pick(total: usize, count: usize) -> Vec<usize> {
+    let count = count.min(total);
+    match count {
+        0 => Vec::new(),
+        1 => vec![0],
+        _ => (0..count).map(|i| i * (total - 1) / (count - 1)).collect(),
+    }
+}
+
+// With --preview-strip, count frames of the fitted animation side by side in
+// one PNG, written next to the output as `<name>_strip.png`. They're decoded
+// from the GIF that was written, so the strip shows the palette, dithering
+// and scale the output really has. Returns the path and how many frames it
+// holds, fewer than count when the animation is shorter
+pub fn write(
+    gif: &[u8],
+    name: &Path,
+    count: usize,
+    opts: &Options,
+) -> Result<(PathBuf, usize), Box<dyn Error>> {
+    let anim = Animation::read_gif(Cursor::new(gif))?;
+    let picked = pick(anim.frames.len(), count);
+    let (w, h) = anim.frames.first().ok_or("No frames")?.dimensions();
+    let n = picked.len() as u32;
+    let mut strip = RgbImage::from_pixel(
+        n * (w + PADDING) + PADDING,
+        h + 2 * PADDING,
+        Rgb(opts.background),
+    );
+    for (i, &frame) in picked.iter().enumerate() {
+        let frame = DynamicImage::ImageRgba8(anim.frames[frame].clone());
+        let flat = color::flatten(&frame, opts.background).to_rgb8();
+        let x = PADDING + i as u32 * (w + PADDING);
+        imageops::replace(&mut strip, &flat, x as i64, PADDING as i64);
+    }
+    // Lossless, anything else would add artifacts of its own to judge
+    let bytes = encode::encode(
+        &DynamicImage::ImageRgb8(strip),
+        "png",
+        &EncoderSettings::default(),
+    )?;
+    let mut output = name.as_os_str().to_os_string();
+    output.push("_strip.png");
+    let output = PathBuf::from(output);
+    backup::write_output(&output, &bytes, opts)?;
+    Ok((output, picked.len()))
+}
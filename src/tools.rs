@@ -0,0 +1,142 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::settings;
+
+// A user-configured command run on every candidate of one format, e.g.
+//   tool.png = pngquant --force --output {out} {in}
+//   tool.gif = gifsicle -O3 {in} -o {out}
+// {in} is replaced with the candidate's path and {out} with where the tool
+// should write its result. Tools for the same format run in config order
+#[derive(Clone)]
+pub struct ExternalTool {
+    pub ext: String,
+    pub args: Vec<String>,
+}
+
+// Reads `tool.<ext> = <command>` lines from the config file
+pub fn load() -> Vec<ExternalTool> {
+    let mut tools = Vec::new();
+    for (key, value) in settings::read_config() {
+        if let Some(ext) = key.strip_prefix("tool.") {
+            let args = value
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            if !args.is_empty() {
+                tools.push(ExternalTool {
+                    ext: ext.to_ascii_lowercase(),
+                    args,
+                });
+            }
+        }
+    }
+    tools
+}
+
+// A fresh path in the system's temp folder, different for every call and
+// process, so fits running side by side never write over each other's files
+pub fn temp_path(name: &str, ext: &str) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    env::temp_dir().join(format!(
+        "autosize_{}_{}_{}.{}",
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+        name,
+        ext
+    ))
+}
+
+// Opens a file in whatever the system uses for its type, without waiting
+// for it to be closed
+pub fn open(path: &Path) -> io::Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        // The empty argument is start's window title
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+// Runs a command with bytes on its stdin, failing with what it printed
+pub fn pipe_into(command: &mut Command, bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Couldn't run {} (is it installed?): {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(bytes)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+// Runs every tool configured for ext over the encoded bytes, keeping each
+// result only when it's smaller. Tools that exit unsuccessfully (pngquant
+// does when it can't meet its quality bar) are treated as no improvement
+pub fn apply(
+    tools: &[ExternalTool],
+    ext: &str,
+    mut bytes: Vec<u8>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let ext = ext.to_ascii_lowercase();
+    for tool in tools.iter().filter(|t| t.ext == ext) {
+        let (input, output) = (temp_path("tool_in", &ext), temp_path("tool_out", &ext));
+        fs::write(&input, &bytes)?;
+        let args = tool
+            .args
+            .iter()
+            .map(|arg| {
+                arg.replace("{in}", &input.to_string_lossy())
+                    .replace("{out}", &output.to_string_lossy())
+            })
+            .collect::<Vec<_>>();
+        let status = Command::new(&args[0])
+            .args(&args[1..])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let result = fs::read(&output);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+        let status =
+            status.map_err(|e| format!("Couldn't run external tool '{}': {}", args[0], e))?;
+        if !status.success() {
+            continue;
+        }
+        if let Ok(result) = result {
+            if !result.is_empty() && result.len() < bytes.len() {
+                bytes = result;
+            }
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use ksni::{
+    blocking::TrayMethods,
+    menu::{CheckmarkItem, StandardItem},
+    MenuItem,
+};
+use notify_rust::Notification;
+
+use crate::{
+    batch::{self, collect_inputs, fit_file},
+    options::Options,
+    prompt_settings,
+    settings::{self, LastSettings},
+    units,
+};
+
+// How often the watched folders are listed again
+const POLL: Duration = Duration::from_secs(2);
+
+// Where results go when there's no --output-dir, inside each watched folder
+// so they aren't picked up again
+const OUTPUT_DIR: &str = "autosized";
+
+// The icon and its menu. The watcher reads paused, the menu flips it
+struct Agent {
+    folders: Vec<PathBuf>,
+    target: u64,
+    paused: Arc<AtomicBool>,
+    last: String,
+}
+
+impl ksni::Tray for Agent {
+    fn id(&self) -> String {
+        "autosize".into()
+    }
+
+    fn title(&self) -> String {
+        "autosize".into()
+    }
+
+    fn icon_name(&self) -> String {
+        "image-x-generic".into()
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let info = |label: String| {
+            StandardItem {
+                label,
+                enabled: false,
+                ..Default::default()
+            }
+            .into()
+        };
+        vec![
+            info(format!(
+                "Fitting new images in {} folder{} to {}",
+                self.folders.len(),
+                if self.folders.len() == 1 { "" } else { "s" },
+                units::format_size(self.target)
+            )),
+            info(self.last.clone()),
+            MenuItem::Separator,
+            CheckmarkItem {
+                label: "Paused".into(),
+                checked: self.paused.load(Ordering::Relaxed),
+                activate: Box::new(|agent: &mut Agent| {
+                    agent.paused.fetch_xor(true, Ordering::Relaxed);
+                }),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Quit".into(),
+                activate: Box::new(|_| std::process::exit(0)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+// A desktop notification, or just the console when there's no notification
+// service to show it
+fn notify(summary: &str, body: &str) {
+    println!("{}: {}", summary, body);
+    let _ = Notification::new()
+        .appname("autosize")
+        .summary(summary)
+        .body(body)
+        .show();
+}
+
+// The settings of the last interactive run, with --target or else
+// `watch_target = <size>` from the config file taking over the target
+fn watch_options(mut opts: Options) -> io::Result<Options> {
+    let target = settings::read_config()
+        .into_iter()
+        .rev()
+        .find(|(key, _)| key == "watch_target")
+        .and_then(|(_, size)| units::parse_size(&size));
+    opts.given_target = opts
+        .given_target
+        .or(target)
+        .or(Some(LastSettings::load().target));
+    // Files are fitted as they show up, with no one to answer --review
+    opts.review = false;
+    prompt_settings(&mut opts, false)?;
+    Ok(opts)
+}
+
+fn size_of(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().map(|m| m.len())
+}
+
+// Fits a file that showed up in folder and says how it went
+fn fit_new(file: &Path, folder: &Path, opts: &Options) -> String {
+    let mut opts = opts.clone();
+    opts.output_dir = Some(opts.output_dir.unwrap_or_else(|| folder.join(OUTPUT_DIR)));
+    if opts.by_date {
+        opts.output_dir = opts
+            .output_dir
+            .map(|dir| dir.join(batch::date_folder(file)));
+    }
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    let original = size_of(file).unwrap_or(0);
+    match fit_file(file, &opts, &["tray".to_string()]) {
+        Ok((fit, size)) => {
+            let body = format!(
+                "{} -> {} at a scale of {:.2}, saved as {}",
+                units::format_size(original),
+                units::format_size(size),
+                fit.scale,
+                fit.output.display()
+            );
+            notify(&format!("Fitted {}", name), &body);
+            format!("Last: {} -> {}", name, units::format_size(size))
+        }
+        Err(e) => {
+            notify(&format!("Couldn't fit {}", name), &e.to_string());
+            format!("Last: {} failed", name)
+        }
+    }
+}
+
+// `autosize tray [<folders>...] [options]`: an icon in the system tray that
+// watches folders and fits images as they're added, with a notification for
+// each. Folders come from the arguments and `watch = <dir>` lines in the
+// config file. Files already there when it starts are left alone
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let opts = watch_options(Options::parse(args)?)?;
+    let mut folders = opts.inputs.clone();
+    folders.extend(
+        settings::read_config()
+            .into_iter()
+            .filter(|(key, _)| key == "watch")
+            .map(|(_, dir)| PathBuf::from(dir)),
+    );
+    if folders.is_empty() {
+        return Err(
+            "Usage: autosize tray <folders>..., or add `watch = <folder>` lines to .autosize/config"
+                .into(),
+        );
+    }
+    for folder in &folders {
+        if !folder.is_dir() {
+            return Err(format!("{} isn't a folder", folder.display()).into());
+        }
+    }
+    let paused = Arc::new(AtomicBool::new(false));
+    let agent = Agent {
+        folders: folders.clone(),
+        target: opts.target,
+        paused: paused.clone(),
+        last: "Nothing fitted yet".into(),
+    };
+    // Still useful without an icon, e.g. on a desktop with no tray
+    let tray = match agent.spawn() {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            println!("Couldn't show the tray icon ({}), watching anyway", e);
+            None
+        }
+    };
+    println!(
+        "Watching {} for new images to fit to {}",
+        folders
+            .iter()
+            .map(|f| f.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        units::format_size(opts.target)
+    );
+
+    let listing = |folder: &PathBuf| {
+        collect_inputs(std::slice::from_ref(folder), false, &opts)
+            .map(|files| files.into_iter().map(|(file, _)| file).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    let mut seen = folders.iter().flat_map(listing).collect::<Vec<_>>();
+    // New files and their size at the last poll. One is only fitted once its
+    // size has stopped changing, so a copy in progress isn't read half-done
+    let mut pending = HashMap::<PathBuf, u64>::new();
+    loop {
+        thread::sleep(POLL);
+        if paused.load(Ordering::Relaxed) {
+            continue;
+        }
+        for folder in &folders {
+            for file in listing(folder) {
+                if seen.contains(&file) {
+                    continue;
+                }
+                let Some(size) = size_of(&file) else {
+                    continue;
+                };
+                if pending.insert(file.clone(), size) != Some(size) {
+                    continue;
+                }
+                pending.remove(&file);
+                seen.push(file.clone());
+                let last = fit_new(&file, folder, &opts);
+                if let Some(tray) = &tray {
+                    tray.update(|agent| agent.last = last);
+                }
+            }
+        }
+    }
+}
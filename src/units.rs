@@ -0,0 +1,242 @@
+// Decimal units first, matching the suffixes used in output filenames
+const UNITS: [(&str, u64); 6] = [
+    ("EB", 1_000_000_000_000_000_000),
+    ("PB", 1_000_000_000_000_000),
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("KB", 1_000),
+];
+
+// Spaces and apostrophes only ever group digits (`1 000 000`, `1'000'000`)
+const GROUPING: [char; 4] = [' ', '\u{a0}', '\u{202f}', '\''];
+
+// Rewrites a number with thousands separators or a decimal comma, like
+// `1.000.000`, `1,000,000`, `1 000 000`, `1.234,5` or `2,5`, into the plain
+// form Rust parses. A separator used more than once, or ahead of the other
+// one, groups thousands, and then the groups must be three digits. A lone
+// one followed by exactly three digits (`1.000`, `1,000`) could be either:
+// it groups thousands when it's the grouping given, which the prompts take
+// from the interface language. Without one, anything that reads from a
+// file or another program mustn't depend on who runs it, so a lone point
+// is a decimal point as it always was, and a lone comma is refused
+pub fn normalize_number(input: &str, grouping: Option<char>) -> Option<String> {
+    let number = input
+        .trim()
+        .chars()
+        .filter(|c| !GROUPING.contains(c))
+        .collect::<String>();
+    if number.is_empty()
+        || !number
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == ',')
+    {
+        return None;
+    }
+    let count = |sep: char| number.matches(sep).count();
+    let (thousands, decimal) = match (count('.'), count(',')) {
+        (0, 0) => (None, None),
+        (dots, commas) if dots == 0 || commas == 0 => {
+            let (sep, times) = match dots {
+                0 => (',', commas),
+                _ => ('.', dots),
+            };
+            let (before, after) = number.split_once(sep)?;
+            let ambiguous = times == 1 && after.len() == 3 && (1..=3).contains(&before.len());
+            match (times > 1, ambiguous, grouping) {
+                (true, _, _) => (Some(sep), None),
+                (false, true, None) if sep == ',' => return None,
+                (false, true, Some(grouping)) if sep == grouping => (Some(sep), None),
+                _ => (None, Some(sep)),
+            }
+        }
+        // Both: the last one is the decimal point
+        _ => {
+            let decimal = number.chars().rev().find(|&c| c == '.' || c == ',')?;
+            let thousands = if decimal == '.' { ',' } else { '.' };
+            if count(decimal) > 1 {
+                return None;
+            }
+            (Some(thousands), Some(decimal))
+        }
+    };
+    let whole = match decimal {
+        Some(decimal) => number.split(decimal).next()?,
+        None => &number,
+    };
+    if let Some(thousands) = thousands {
+        let mut groups = whole.split(thousands);
+        let first = groups.next()?;
+        if first.is_empty() || first.len() > 3 || groups.any(|g| g.len() != 3) {
+            return None;
+        }
+    }
+    let mut plain = whole.replace(thousands.unwrap_or(' '), "");
+    if let Some(decimal) = decimal {
+        let fraction = number.split(decimal).nth(1)?;
+        plain = format!("{}.{}", plain, fraction);
+    }
+    Some(plain)
+}
+
+// Parses sizes like `128`, `500k`, `2MB`, `1.5 mb` or `4MiB` into bytes,
+// the same for everyone, see normalize_number
+pub fn parse_size(input: &str) -> Option<u64> {
+    parse_size_grouped(input, None)
+}
+
+// parse_size, with a lone separator before three digits read as grouping
+// thousands when it's grouping
+pub fn parse_size_grouped(input: &str, grouping: Option<char>) -> Option<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "ki" | "kib" => 1 << 10,
+        "mi" | "mib" => 1 << 20,
+        "gi" | "gib" => 1 << 30,
+        "ti" | "tib" => 1 << 40,
+        _ => return None,
+    };
+    let number = normalize_number(number, grouping)?;
+    // Plain integers stay exact, fractions go through f64
+    if let Ok(value) = number.parse::<u64>() {
+        return value.checked_mul(multiplier);
+    }
+    let value: f64 = number.parse().ok()?;
+    if !value.is_finite() || value < 0.0 {
+        return None;
+    }
+    let bytes = (value * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return None;
+    }
+    Some(bytes as u64)
+}
+
+// Parses lengths of time like `5s`, `2.5 s`, `1500ms` or `1m` into
+// milliseconds. A plain number is in seconds
+pub fn parse_duration(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" | "sec" | "secs" => 1_000.0,
+        "ms" => 1.0,
+        "m" | "min" | "mins" => 60_000.0,
+        _ => return None,
+    };
+    let value: f64 = normalize_number(number, None)?.parse().ok()?;
+    if !value.is_finite() || value <= 0.0 {
+        return None;
+    }
+    Some((value * multiplier).round() as u64)
+}
+
+// Formats a byte count using the largest unit it reaches, e.g. `1.5 MB`
+pub fn format_size(bytes: u64) -> String {
+    for (unit, size) in UNITS {
+        if bytes >= size {
+            let value = format!("{:.2}", bytes as f64 / size as f64);
+            let value = value.trim_end_matches('0').trim_end_matches('.');
+            return format!("{} {}", value, unit);
+        }
+    }
+    format!("{} B", bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_with_separators() {
+        for grouping in [None, Some(','), Some('.')] {
+            let plain = |input| normalize_number(input, grouping);
+            for input in [
+                "1.000.000",
+                "1,000,000",
+                "1 000 000",
+                "1'000'000",
+                "1\u{a0}000",
+            ] {
+                let want = match input.len() {
+                    5 | 6 => "1000",
+                    _ => "1000000",
+                };
+                assert_eq!(plain(input).as_deref(), Some(want), "{}", input);
+            }
+            assert_eq!(plain("1.234,5").as_deref(), Some("1234.5"));
+            assert_eq!(plain("1,234.5").as_deref(), Some("1234.5"));
+            assert_eq!(plain("2,5").as_deref(), Some("2.5"));
+            assert_eq!(plain("2.5").as_deref(), Some("2.5"));
+            assert_eq!(plain("12,3456").as_deref(), Some("12.3456"));
+            assert_eq!(plain("500").as_deref(), Some("500"));
+            // Groups of three only, one decimal point, digits only
+            for bad in ["1,00,000", "1.2.3", "1.5,5", "1,5,5", "", "1e3", "-5"] {
+                assert_eq!(plain(bad), None, "{}", bad);
+            }
+        }
+    }
+
+    #[test]
+    fn a_lone_separator_before_three_digits_depends_on_the_grouping() {
+        assert_eq!(normalize_number("1.000", None).as_deref(), Some("1.000"));
+        assert_eq!(normalize_number("1,500", None), None);
+        assert_eq!(
+            normalize_number("1,500", Some(',')).as_deref(),
+            Some("1500")
+        );
+        assert_eq!(
+            normalize_number("1.500", Some(',')).as_deref(),
+            Some("1.500")
+        );
+        assert_eq!(
+            normalize_number("1.500", Some('.')).as_deref(),
+            Some("1500")
+        );
+        assert_eq!(
+            normalize_number("1,500", Some('.')).as_deref(),
+            Some("1.500")
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("500k"), Some(500_000));
+        assert_eq!(parse_size("2,5MB"), Some(2_500_000));
+        assert_eq!(parse_size("1.000.000"), Some(1_000_000));
+        assert_eq!(parse_size("1 000 k"), Some(1_000_000));
+        assert_eq!(parse_size("1.5 mb"), Some(1_500_000));
+        assert_eq!(parse_size("4MiB"), Some(4 << 20));
+        assert_eq!(parse_size("1,500KB"), None);
+        assert_eq!(parse_size_grouped("1,500KB", Some(',')), Some(1_500_000));
+        assert_eq!(parse_size_grouped("1.500KB", Some('.')), Some(1_500_000));
+        assert_eq!(parse_size("5 parsecs"), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("5s"), Some(5_000));
+        assert_eq!(parse_duration("2.5 s"), Some(2_500));
+        assert_eq!(parse_duration("2,5s"), Some(2_500));
+        assert_eq!(parse_duration("1500ms"), Some(1_500));
+        assert_eq!(parse_duration("1 500 ms"), Some(1_500));
+        assert_eq!(parse_duration("1m"), Some(60_000));
+        assert_eq!(parse_duration("1.5 MIN"), Some(90_000));
+        // A plain number is in seconds
+        assert_eq!(parse_duration("3"), Some(3_000));
+        for bad in ["", "0", "-1s", "5h", "s", "1,500ms", "fast"] {
+            assert_eq!(parse_duration(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use crate::{messages::tr, units};
+
+// What some work cost the process: CPU time over all its threads, and the
+// most memory it held meanwhile when that can be told
+#[derive(Clone, Copy)]
+pub struct Usage {
+    pub cpu: Duration,
+    pub peak: Option<u64>,
+}
+
+impl Usage {
+    pub fn describe(&self) -> String {
+        let cpu = self.cpu.as_secs_f64();
+        match self.peak {
+            Some(peak) => tr!(UsageWithPeak, cpu, units::format_size(peak)),
+            None => tr!(UsageCpu, cpu),
+        }
+    }
+}
+
+// Taken before some work, to see what it cost with usage()
+pub struct Meter {
+    cpu: Duration,
+}
+
+impl Meter {
+    // Resets the peak where the system allows (Linux), so it's the work's
+    // own rather than the most the process ever held. None where there's
+    // nothing to measure with
+    pub fn start() -> Option<Meter> {
+        reset_peak();
+        Some(Meter {
+            cpu: cpu_and_peak()?.0,
+        })
+    }
+
+    pub fn usage(&self) -> Option<Usage> {
+        let (cpu, peak) = cpu_and_peak()?;
+        Some(Usage {
+            cpu: cpu.saturating_sub(self.cpu),
+            peak: peak_since_reset().or(peak),
+        })
+    }
+}
+
+// The process's CPU time so far and its peak resident memory in bytes
+#[cfg(unix)]
+fn cpu_and_peak() -> Option<(Duration, Option<u64>)> {
+    // SAFETY: getrusage only fills in the struct it's given
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        if libc::getrusage(libc::RUSAGE_SELF, &mut usage) != 0 {
+            return None;
+        }
+        usage
+    };
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1_000);
+    let cpu = time(usage.ru_utime) + time(usage.ru_stime);
+    // Kilobytes everywhere but on macOS, where it's bytes
+    let peak = match cfg!(target_os = "macos") {
+        true => usage.ru_maxrss as u64,
+        false => usage.ru_maxrss as u64 * 1024,
+    };
+    Some((cpu, (peak > 0).then_some(peak)))
+}
+
+#[cfg(not(unix))]
+fn cpu_and_peak() -> Option<(Duration, Option<u64>)> {
+    None
+}
+
+// Writing 5 to clear_refs sets the high-water mark back to what's resident
+// now. getrusage's peak can't be reset, it's the whole process's
+#[cfg(target_os = "linux")]
+fn reset_peak() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reset_peak() {}
+
+// The high-water mark since reset_peak, VmHWM in kilobytes
+#[cfg(target_os = "linux")]
+fn peak_since_reset() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb = line["VmHWM:".len()..].trim().trim_end_matches("kB").trim();
+    Some(kb.parse::<u64>().ok()? * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_since_reset() -> Option<u64> {
+    None
+}
@@ -0,0 +1,155 @@
+// Fits the sample images to a few targets each and checks the results still
+// fit, aren't wastefully small and decode again. Catches an encoder or search
+// change that quietly breaks the output
+
+use std::{path::PathBuf, thread};
+
+use autosize::{
+    fit,
+    image::{self, DynamicImage, GenericImageView},
+    FitOptions, Fitted,
+};
+
+fn open(path: &str) -> DynamicImage {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path);
+    image::open(&path).unwrap_or_else(|e| panic!("couldn't open {}: {}", path.display(), e))
+}
+
+// Fits img to every target and checks each result, returning them decoded
+fn check(img: &DynamicImage, format: &str, targets: &[u64]) -> Vec<DynamicImage> {
+    targets
+        .iter()
+        .map(|&target| {
+            let options = FitOptions::new(target)
+                .format(format)
+                .tolerance(target / 50)
+                .iterations(32);
+            let Fitted { bytes, scale, .. } = fit(img, &options).unwrap();
+            let size = bytes.len() as u64;
+            assert!(
+                size <= target,
+                "{}: {} B is over {} B",
+                format,
+                size,
+                target
+            );
+            assert!(
+                size >= target / 4,
+                "{}: {} B is far under {} B (scale {})",
+                format,
+                size,
+                target,
+                scale
+            );
+            let decoded = image::load_from_memory(&bytes)
+                .unwrap_or_else(|e| panic!("{} at {} B doesn't decode: {}", format, size, e));
+            let (w, h) = decoded.dimensions();
+            assert!(w > 0 && h > 0);
+            decoded
+        })
+        .collect()
+}
+
+#[test]
+fn photo_to_jpeg() {
+    let img = open("input/fuji.jpeg");
+    let decoded = check(&img, "jpg", &[3_000, 6_000, 12_000]);
+    // Bigger targets keep more of the resolution
+    let widths = decoded.iter().map(|d| d.width()).collect::<Vec<_>>();
+    assert!(widths.windows(2).all(|w| w[0] <= w[1]), "{:?}", widths);
+}
+
+#[test]
+fn screenshot_to_png() {
+    let img = open("tests/assets/screenshot.png");
+    check(&img, "png", &[10_000, 25_000]);
+}
+
+#[test]
+fn screenshot_to_webp() {
+    let img = open("tests/assets/screenshot.png");
+    check(&img, "webp", &[8_000, 30_000]);
+}
+
+#[test]
+fn pixel_art_to_gif() {
+    let img = open("tests/assets/pixel_art.gif");
+    check(&img, "gif", &[400, 2_000]);
+}
+
+#[test]
+fn transparent_png_keeps_alpha() {
+    let img = open("tests/assets/transparent.png");
+    for decoded in check(&img, "png", &[1_500, 3_000]) {
+        assert!(decoded.color().has_alpha());
+        // The corners are see-through in the input
+        assert_eq!(decoded.to_rgba8().get_pixel(0, 0)[3], 0);
+    }
+}
+
+#[test]
+fn fits_side_by_side() {
+    let img = open("input/fuji.jpeg");
+    let options = FitOptions::new(6_000)
+        .format("jpg")
+        .reproducible(true)
+        .quiet(true);
+    // Several fits at once, like a server handling uploads, come out the
+    // same as each other
+    let results = thread::scope(|s| {
+        let fits = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    fit(&img, &options)
+                        .map(|f| f.bytes)
+                        .map_err(|e| e.to_string())
+                })
+            })
+            .collect::<Vec<_>>();
+        fits.into_iter()
+            .map(|f| f.join().unwrap().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert!(results.windows(2).all(|r| r[0] == r[1]));
+    assert!(results[0].len() <= 6_000);
+}
+
+#[test]
+fn transparent_png_to_jpeg_is_flattened() {
+    let img = open("tests/assets/transparent.png");
+    let options = FitOptions::new(3_000).background([255, 255, 255]);
+    let bytes = fit(&img, &options).unwrap().bytes;
+    assert!(bytes.len() <= 3_000);
+    let decoded = image::load_from_memory(&bytes).unwrap().to_rgb8();
+    // Transparent corners land on the background rather than black
+    assert!(decoded.get_pixel(0, 0).0.iter().all(|&c| c > 240));
+}
+
+#[test]
+fn grey_and_deep_inputs() {
+    let img = open("input/fuji.jpeg");
+    let grey = DynamicImage::ImageLuma8(img.to_luma8());
+    check(&grey, "png", &[15_000, 30_000]);
+    check(&grey, "jpg", &[3_000, 6_000]);
+    let grey_alpha = DynamicImage::ImageLumaA8(img.to_luma_alpha8());
+    check(&grey_alpha, "png", &[15_000, 30_000]);
+    let deep = DynamicImage::ImageRgb16(img.to_rgb16());
+    check(&deep, "jpg", &[3_000, 6_000]);
+}
+
+#[test]
+fn unreachable_targets_fail() {
+    let img = open("input/fuji.jpeg");
+    // Smaller than any JPEG can be, the search must say so rather than hand
+    // back something over the target
+    let options = FitOptions::new(300).format("jpg").quiet(true);
+    assert!(fit(&img, &options).is_err());
+    let options = options.max_scale(0.5);
+    assert!(fit(&img, &options).is_err());
+    // A floor it can't get under fails the same way
+    let options = FitOptions::new(1_000)
+        .format("png")
+        .min_scale(0.5)
+        .quiet(true);
+    assert!(fit(&img, &options).is_err());
+}